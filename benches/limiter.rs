@@ -0,0 +1,118 @@
+//! Throughput benchmarks for the limiter hot path.
+//!
+//! Run with `cargo bench`. Uses a plain timing harness so no extra
+//! dependencies are needed; numbers are nanoseconds per `allow()` call.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use api_rate_limiter::cache::in_memory::InMemoryCache;
+use api_rate_limiter::limiter::{RateLimiter, Strategy};
+
+const ITERATIONS: u32 = 1_000_000;
+
+fn report(name: &str, calls: u32, elapsed: Duration) {
+    println!(
+        "{:<44} {:>8.1} ns/op {:>12.0} ops/s",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(calls),
+        f64::from(calls) / elapsed.as_secs_f64()
+    );
+}
+
+fn limiter(limit: u32, strategy: Strategy) -> RateLimiter<InMemoryCache> {
+    RateLimiter::new(Arc::new(InMemoryCache::new()), limit, Duration::from_secs(60))
+        .with_strategy(strategy)
+}
+
+fn single_key_allowed(strategy: Strategy) {
+    let limiter = limiter(u32::MAX, strategy);
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(limiter.allow("127.0.0.1"));
+    }
+    report(&format!("{:?}: single key, allowed", strategy), ITERATIONS, started.elapsed());
+}
+
+fn single_key_blocked(strategy: Strategy) {
+    let limiter = limiter(10, strategy);
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(limiter.allow("127.0.0.1"));
+    }
+    report(&format!("{:?}: single key, blocked", strategy), ITERATIONS, started.elapsed());
+}
+
+fn many_keys(strategy: Strategy) {
+    let limiter = limiter(100, strategy);
+    let keys: Vec<String> = (0..10_000).map(|i| format!("10.0.{}.{}", i / 256, i % 256)).collect();
+    let started = Instant::now();
+    for i in 0..ITERATIONS {
+        black_box(limiter.allow(&keys[i as usize % keys.len()]));
+    }
+    report(&format!("{:?}: 10k keys", strategy), ITERATIONS, started.elapsed());
+}
+
+fn contended_single_key(strategy: Strategy, threads: u32) {
+    let limiter = Arc::new(limiter(u32::MAX, strategy));
+    let per_thread = ITERATIONS / threads;
+    let started = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let limiter = Arc::clone(&limiter);
+            thread::spawn(move || {
+                for _ in 0..per_thread {
+                    black_box(limiter.allow("127.0.0.1"));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    report(
+        &format!("{:?}: single key, {} threads", strategy, threads),
+        per_thread * threads,
+        started.elapsed(),
+    );
+}
+
+fn contended_hot_key(threshold: u32, threads: u32) {
+    let cache = InMemoryCache::new().with_hot_key_threshold(threshold);
+    let limiter = Arc::new(RateLimiter::new(Arc::new(cache), u32::MAX, Duration::from_secs(60)));
+    let per_thread = ITERATIONS / threads;
+    let started = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let limiter = Arc::clone(&limiter);
+            thread::spawn(move || {
+                for _ in 0..per_thread {
+                    black_box(limiter.allow("tenant"));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let path = if threshold == u32::MAX { "locked" } else { "promoted" };
+    report(
+        &format!("FixedWindow: hot key {}, {} threads", path, threads),
+        per_thread * threads,
+        started.elapsed(),
+    );
+}
+
+fn main() {
+    for strategy in [Strategy::FixedWindow, Strategy::LocalLockFree] {
+        single_key_allowed(strategy);
+        single_key_blocked(strategy);
+        many_keys(strategy);
+        contended_single_key(strategy, 4);
+    }
+    for threads in [1, 4, 16] {
+        contended_hot_key(u32::MAX, threads);
+        contended_hot_key(1000, threads);
+    }
+}
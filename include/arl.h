@@ -0,0 +1,60 @@
+/*
+ * C interface to api-rate-limiter, built with the `ffi` feature:
+ *
+ *   cargo build --release --features ffi
+ *
+ * which produces a shared and a static library in target/release. Keep in
+ * sync with src/ffi.rs; tests/ffi_test.rs checks the exports and the layout
+ * of ArlDecision against this file.
+ */
+#ifndef ARL_H
+#define ARL_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A limiter counting in process memory. Safe to share between threads. */
+typedef struct ArlLimiter ArlLimiter;
+
+typedef struct ArlDecision {
+    /* Whether the request may proceed. */
+    bool allowed;
+    /* Requests counted in the current window. */
+    uint32_t count;
+    /* The limit applied. */
+    uint32_t limit;
+    /* Requests left in the window. */
+    uint32_t remaining;
+    /* For blocked requests, milliseconds until the window resets, or 0 if unknown. */
+    uint64_t retry_after_ms;
+} ArlDecision;
+
+/* Creates a limiter allowing `rate`, e.g. "100/5m". Returns NULL if the rate
+ * does not parse. */
+ArlLimiter *arl_limiter_new(const char *rate);
+
+/* Frees a limiter. NULL is ignored. */
+void arl_limiter_free(ArlLimiter *limiter);
+
+/* Adds or replaces the policy `name` allowing `rate`. Returns 0, or -1 on
+ * invalid arguments. */
+int arl_add_policy(const ArlLimiter *limiter, const char *name, const char *rate);
+
+/* Checks a request from `key` against the policy named `policy`, or the
+ * limiter's own rate if `policy` is NULL. Unknown policies and invalid
+ * arguments are blocked. */
+ArlDecision arl_check(const ArlLimiter *limiter, const char *key, const char *policy);
+
+/* Gives `amount` units back to `key` under the limiter's own rate. Returns
+ * the new count, or -1 on error. */
+int64_t arl_refund(const ArlLimiter *limiter, const char *key, uint32_t amount);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* ARL_H */
@@ -0,0 +1,144 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::limiter::{CacheBackend, RateLimiter};
+use crate::priority::Priority;
+
+/// A source of feedback about the health of the protected system.
+///
+/// Implementations typically compare a measurement such as p99 latency, CPU
+/// usage or queue depth against a threshold.
+pub trait LoadSignal: Send + Sync {
+    /// Returns `true` when the system is overloaded and limits should tighten.
+    fn is_overloaded(&self) -> bool;
+}
+
+impl<F> LoadSignal for F
+where
+    F: Fn() -> bool + Send + Sync,
+{
+    fn is_overloaded(&self) -> bool {
+        self()
+    }
+}
+
+/// A rate limiter whose effective limit follows a load signal using AIMD
+/// (additive increase, multiplicative decrease).
+///
+/// While the signal reports a healthy system, the limit grows by
+/// `increase` per adjustment up to `max_limit`. When it reports overload,
+/// the limit is multiplied by `decrease_factor`, down to `min_limit`.
+pub struct AdaptiveLimiter<B: CacheBackend, S: LoadSignal> {
+    limiter: RateLimiter<B>,
+    signal: S,
+    min_limit: u32,
+    max_limit: u32,
+    increase: u32,
+    decrease_factor: f64,
+    adjust_interval: Duration,
+    current: AtomicU32,
+    last_adjusted: Mutex<Instant>,
+}
+
+impl<B: CacheBackend, S: LoadSignal> AdaptiveLimiter<B, S> {
+    /// Wraps `limiter` so its limit adapts to `signal`.
+    ///
+    /// The limiter's configured limit becomes the maximum and the starting
+    /// point. Defaults: minimum of 1, increase of 1, decrease factor of 0.5,
+    /// adjustments at most once per second.
+    pub fn new(limiter: RateLimiter<B>, signal: S) -> Self {
+        let max_limit = limiter.limit;
+        AdaptiveLimiter {
+            limiter,
+            signal,
+            min_limit: 1.min(max_limit),
+            max_limit,
+            increase: 1,
+            decrease_factor: 0.5,
+            adjust_interval: Duration::from_secs(1),
+            current: AtomicU32::new(max_limit),
+            last_adjusted: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Sets the bounds the effective limit stays within.
+    pub fn with_bounds(mut self, min_limit: u32, max_limit: u32) -> Self {
+        self.min_limit = min_limit.min(max_limit);
+        self.max_limit = max_limit;
+        let current = self.current.get_mut();
+        *current = (*current).clamp(self.min_limit, self.max_limit);
+        self
+    }
+
+    /// Sets how much the limit grows on each healthy adjustment.
+    pub fn with_increase(mut self, increase: u32) -> Self {
+        self.increase = increase;
+        self
+    }
+
+    /// Sets the factor (between 0 and 1) applied to the limit on overload.
+    pub fn with_decrease_factor(mut self, factor: f64) -> Self {
+        self.decrease_factor = factor.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the minimum time between automatic adjustments made by `allow`.
+    pub fn with_adjust_interval(mut self, interval: Duration) -> Self {
+        self.adjust_interval = interval;
+        self
+    }
+
+    /// Returns the current effective limit.
+    pub fn current_limit(&self) -> u32 {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    /// Returns the wrapped rate limiter.
+    pub fn limiter(&self) -> &RateLimiter<B> {
+        &self.limiter
+    }
+
+    /// Reads the load signal and applies one AIMD step, returning the new limit.
+    pub fn adjust(&self) -> u32 {
+        let overloaded = self.signal.is_overloaded();
+        let mut current = self.current.load(Ordering::Relaxed);
+        loop {
+            let next = if overloaded {
+                ((current as f64 * self.decrease_factor) as u32).max(self.min_limit)
+            } else {
+                current.saturating_add(self.increase).min(self.max_limit)
+            };
+            match self
+                .current
+                .compare_exchange_weak(current, next, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return next,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Checks whether a request from the given IP is allowed under the
+    /// current effective limit, adjusting the limit first if the adjust
+    /// interval has elapsed.
+    pub fn allow(&self, ip: &str) -> bool {
+        self.maybe_adjust();
+        self.limiter
+            .check_with_limit(ip, self.current_limit(), Priority::Normal)
+            .allowed
+    }
+
+    fn maybe_adjust(&self) {
+        let due = match self.last_adjusted.try_lock() {
+            Ok(mut last) if last.elapsed() >= self.adjust_interval => {
+                *last = Instant::now();
+                true
+            }
+            // Another thread is adjusting, or it is not time yet.
+            _ => false,
+        };
+        if due {
+            self.adjust();
+        }
+    }
+}
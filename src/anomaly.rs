@@ -0,0 +1,203 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use dashmap::DashMap;
+use crate::clock::{Clock, SystemClock};
+use crate::telemetry::{DecisionEvent, DecisionObserver};
+
+/// A key whose short-term request rate spiked above its baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnomalyEvent<'a> {
+    /// The client key, hashed if the limiter has a key hasher.
+    pub key: &'a str,
+    /// Recent rate, in units per second, averaged over the short window.
+    pub short_rate: f64,
+    /// Baseline rate, in units per second, averaged over the long window.
+    pub baseline_rate: f64,
+    /// Whether the request that raised the flag was allowed. Usually still
+    /// `true`: spikes are flagged before the hard limit trips.
+    pub allowed: bool,
+}
+
+/// Receives anomaly events, e.g. to alert security tooling or tighten the
+/// key's policy.
+///
+/// Any `Fn(&AnomalyEvent)` is a handler.
+pub trait AnomalyHandler: Send + Sync {
+    /// Called once when a key is flagged.
+    fn flagged(&self, event: &AnomalyEvent<'_>);
+}
+
+impl<F> AnomalyHandler for F
+where
+    F: Fn(&AnomalyEvent<'_>) + Send + Sync,
+{
+    fn flagged(&self, event: &AnomalyEvent<'_>) {
+        self(event)
+    }
+}
+
+/// How many observations pass between purges of idle keys.
+const PURGE_EVERY: u64 = 1024;
+
+struct KeyRates {
+    short: f64,
+    long: f64,
+    first_seen: SystemTime,
+    updated_at: SystemTime,
+    flagged: bool,
+}
+
+/// Flags keys whose short-window rate spikes to a multiple of their
+/// long-window baseline, e.g. 10x their trailing average, so security
+/// tooling can react before hard limits trip. Register it with
+/// `RateLimiter::with_observer`.
+///
+/// Both rates are exponentially weighted moving averages, so each key costs
+/// a few words of memory however busy it is. Averages are corrected for how
+/// long a key has been seen, so a new key with steady traffic is not
+/// mistaken for a spike over an empty baseline.
+///
+/// A key is reported once when it is flagged and again only after it has
+/// calmed down. Keys that went quiet are purged every 1024 observations, so
+/// random keys cannot grow the detector without bound.
+pub struct BurstDetector {
+    short_window: Duration,
+    long_window: Duration,
+    ratio: f64,
+    min_rate: f64,
+    handler: Arc<dyn AnomalyHandler>,
+    clock: Arc<dyn Clock>,
+    rates: DashMap<String, KeyRates>,
+    observed: AtomicU64,
+}
+
+impl BurstDetector {
+    /// Reports to `handler`. Defaults: a 10 second short window, a 10 minute
+    /// baseline, a ratio of 10 and a minimum short rate of 1 unit per second.
+    pub fn new(handler: Arc<dyn AnomalyHandler>) -> Self {
+        BurstDetector {
+            short_window: Duration::from_secs(10),
+            long_window: Duration::from_secs(600),
+            ratio: 10.0,
+            min_rate: 1.0,
+            handler,
+            clock: Arc::new(SystemClock),
+            rates: DashMap::new(),
+            observed: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the windows the short-term and baseline rates average over.
+    pub fn with_windows(mut self, short: Duration, long: Duration) -> Self {
+        self.short_window = short.max(Duration::from_millis(1));
+        self.long_window = long.max(self.short_window);
+        self
+    }
+
+    /// Flags keys whose short-term rate reaches `ratio` times their baseline.
+    pub fn with_ratio(mut self, ratio: f64) -> Self {
+        self.ratio = ratio;
+        self
+    }
+
+    /// Ignores keys below `rate` units per second, so quiet keys with a near
+    /// zero baseline are not flagged for a handful of requests.
+    pub fn with_min_rate(mut self, rate: f64) -> Self {
+        self.min_rate = rate;
+        self
+    }
+
+    /// Sets the clock used to time requests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns whether `key` is currently flagged.
+    pub fn is_flagged(&self, key: &str) -> bool {
+        self.rates.get(key).is_some_and(|rates| rates.flagged)
+    }
+
+    /// Returns how many keys have rates in memory.
+    pub fn key_count(&self) -> usize {
+        self.rates.len()
+    }
+
+    /// Forgets keys not seen for four baseline windows, by which time their
+    /// rates have decayed to nearly zero. Observations call it every 1024
+    /// requests.
+    pub fn purge_idle(&self) {
+        let now = self.clock.now();
+        let idle = self.long_window * 4;
+        self.rates.retain(|_, rates| {
+            now.duration_since(rates.updated_at).unwrap_or_default() < idle
+        });
+    }
+}
+
+/// Decays `rate` over `elapsed` seconds and adds `units` arriving now, for
+/// a moving average over `window` seconds.
+fn decay(rate: f64, elapsed: f64, window: f64, units: f64) -> f64 {
+    rate * (-elapsed / window).exp() + units / window
+}
+
+/// Scales up a moving average over `window` seconds for a key seen only for
+/// `age` seconds, which has had less time to build up.
+fn corrected(rate: f64, age: f64, window: f64) -> f64 {
+    rate / (1.0 - (-age / window).exp())
+}
+
+impl DecisionObserver for BurstDetector {
+    fn observe(&self, event: &DecisionEvent<'_>) {
+        let now = self.clock.now();
+        let short_window = self.short_window.as_secs_f64();
+        let long_window = self.long_window.as_secs_f64();
+        let units = f64::from(event.cost);
+        if self.observed.fetch_add(1, Ordering::Relaxed) % PURGE_EVERY == PURGE_EVERY - 1 {
+            self.purge_idle();
+        }
+
+        let flagged = {
+            let mut rates = match self.rates.get_mut(event.key) {
+                Some(rates) => rates,
+                None => self.rates.entry(event.key.to_string()).or_insert(KeyRates {
+                    short: 0.0,
+                    long: 0.0,
+                    first_seen: now,
+                    updated_at: now,
+                    flagged: false,
+                }),
+            };
+            let elapsed = now.duration_since(rates.updated_at).unwrap_or_default().as_secs_f64();
+            rates.short = decay(rates.short, elapsed, short_window, units);
+            rates.long = decay(rates.long, elapsed, long_window, units);
+            rates.updated_at = now;
+
+            let age = now.duration_since(rates.first_seen).unwrap_or_default().as_secs_f64();
+            // A key's first request says nothing about its rate yet.
+            let spiking = age > 0.0 && {
+                let short = corrected(rates.short, age, short_window);
+                let long = corrected(rates.long, age, long_window);
+                short >= self.min_rate && short >= self.ratio * long
+            };
+            let newly_flagged = spiking && !rates.flagged;
+            rates.flagged = spiking;
+            newly_flagged.then(|| {
+                (
+                    corrected(rates.short, age, short_window),
+                    corrected(rates.long, age, long_window),
+                )
+            })
+        };
+        // Report outside the map's lock, so the handler may query the detector.
+        if let Some((short_rate, baseline_rate)) = flagged {
+            self.handler.flagged(&AnomalyEvent {
+                key: event.key,
+                short_rate,
+                baseline_rate,
+                allowed: event.allowed,
+            });
+        }
+    }
+}
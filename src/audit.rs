@@ -0,0 +1,227 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::priority::Priority;
+
+/// A blocked request, as recorded in the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// When the request was blocked.
+    pub timestamp: SystemTime,
+    /// Hash of the client key, so logs can be correlated without storing raw
+    /// IPs or API keys: `hash_key`, or the limiter's `KeyHasher` if it has one.
+    pub key_hash: u64,
+    /// Version of the policy the request was checked under.
+    pub policy_version: u32,
+    /// The limit in force for the request.
+    pub limit: u32,
+    /// Requests counted in the window when the request was blocked.
+    pub count: u32,
+    /// The priority band the request was checked against.
+    pub priority: Priority,
+}
+
+impl AuditRecord {
+    /// Formats the record as a single JSON object, without a trailing newline.
+    pub fn to_json(&self) -> String {
+        let millis = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis())
+            .unwrap_or(0);
+        format!(
+            "{{\"timestamp_ms\":{},\"key_hash\":\"{:016x}\",\"policy_version\":{},\"limit\":{},\"count\":{},\"priority\":\"{:?}\"}}",
+            millis, self.key_hash, self.policy_version, self.limit, self.count, self.priority
+        )
+    }
+}
+
+/// Hashes a client key with 64-bit FNV-1a, which is stable across builds and
+/// platforms so hashes from different hosts and releases can be compared.
+pub fn hash_key(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// An append-only destination for audit records of blocked requests.
+pub trait AuditSink: Send + Sync {
+    /// Records one blocked request. Failures must not affect the decision,
+    /// so implementations handle their own errors.
+    fn record(&self, record: &AuditRecord);
+
+    /// Makes records written so far durable. The default does nothing.
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Appends records to a file as JSON lines, one object per blocked request.
+pub struct JsonLinesSink {
+    file: Mutex<File>,
+    write_errors: AtomicU64,
+}
+
+impl JsonLinesSink {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonLinesSink {
+            file: Mutex::new(file),
+            write_errors: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns how many records could not be written.
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors.load(Ordering::Relaxed)
+    }
+}
+
+impl AuditSink for JsonLinesSink {
+    fn record(&self, record: &AuditRecord) {
+        let mut line = record.to_json();
+        line.push('\n');
+        // One write per line, so concurrent writers never interleave records.
+        let written = match self.file.lock() {
+            Ok(mut file) => file.write_all(line.as_bytes()).is_ok(),
+            Err(_) => false,
+        };
+        if !written {
+            self.write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        let file = self.file.lock().map_err(|_| "audit log poisoned".to_string())?;
+        file.sync_data().map_err(|err| err.to_string())
+    }
+}
+
+/// Wraps a sink so only a sample of records reaches it, bounding audit
+/// volume during floods.
+///
+/// A record is kept if it is one of every `every` blocked requests and the
+/// per-second budget, if set, is not yet spent.
+pub struct SampledSink<S: AuditSink> {
+    inner: S,
+    every: u64,
+    max_per_second: Option<u64>,
+    seen: AtomicU64,
+    /// Current second since the Unix epoch in the upper 32 bits, records
+    /// kept in that second in the lower 32.
+    budget: AtomicU64,
+}
+
+impl<S: AuditSink> SampledSink<S> {
+    /// Keeps one of every `every` records (all of them for `every <= 1`).
+    pub fn new(inner: S, every: u64) -> Self {
+        SampledSink {
+            inner,
+            every: every.max(1),
+            max_per_second: None,
+            seen: AtomicU64::new(0),
+            budget: AtomicU64::new(0),
+        }
+    }
+
+    /// Keeps at most `max` records per second after sampling.
+    pub fn with_max_per_second(mut self, max: u64) -> Self {
+        self.max_per_second = Some(max);
+        self
+    }
+
+    fn within_budget(&self, timestamp: SystemTime) -> bool {
+        let Some(max) = self.max_per_second else {
+            return true;
+        };
+        let second = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() & 0xffff_ffff)
+            .unwrap_or(0);
+        let mut current = self.budget.load(Ordering::Relaxed);
+        loop {
+            let kept = if current >> 32 == second { current & 0xffff_ffff } else { 0 };
+            if kept >= max {
+                return false;
+            }
+            let next = (second << 32) | (kept + 1);
+            match self
+                .budget
+                .compare_exchange_weak(current, next, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+impl<S: AuditSink> AuditSink for SampledSink<S> {
+    fn record(&self, record: &AuditRecord) {
+        if !self.seen.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.every) {
+            return;
+        }
+        if self.within_budget(record.timestamp) {
+            self.inner.record(record);
+        }
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::priority::Priority;
+    use super::{hash_key, AuditRecord, AuditSink, SampledSink};
+
+    struct Collect(Mutex<Vec<AuditRecord>>);
+
+    impl AuditSink for Collect {
+        fn record(&self, record: &AuditRecord) {
+            self.0.lock().unwrap().push(record.clone());
+        }
+    }
+
+    fn record(secs: u64) -> AuditRecord {
+        AuditRecord {
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            key_hash: hash_key("10.0.0.1"),
+            policy_version: 0,
+            limit: 5,
+            count: 5,
+            priority: Priority::Normal,
+        }
+    }
+
+    #[test]
+    fn test_sampling_and_budget() {
+        let sink = SampledSink::new(Collect(Mutex::new(Vec::new())), 2).with_max_per_second(3);
+        for _ in 0..10 {
+            sink.record(&record(100));
+        }
+        // Every second record is sampled, capped at 3 per second.
+        assert_eq!(sink.inner.0.lock().unwrap().len(), 3);
+        // The budget resets in the next second.
+        sink.record(&record(101));
+        assert_eq!(sink.inner.0.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_json_line() {
+        assert_eq!(
+            record(1).to_json(),
+            format!(
+                "{{\"timestamp_ms\":1000,\"key_hash\":\"{:016x}\",\"policy_version\":0,\"limit\":5,\"count\":5,\"priority\":\"Normal\"}}",
+                hash_key("10.0.0.1")
+            )
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! `arl`: command-line tool for exercising the rate limiter.
+//!
+//! Usage:
+//!
+//! ```text
+//! arl load-test --rate 500/s [--duration 10s] [--limit 100] [--window 1s] [--keys 1]
+//! ```
+
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use api_rate_limiter::cache::in_memory::InMemoryCache;
+use api_rate_limiter::limiter::RateLimiter;
+use api_rate_limiter::rate::{parse_duration, Rate};
+
+const USAGE: &str = "usage: arl load-test --rate <n>/<unit> [--duration <d>] [--limit <n>] [--window <d>] [--keys <n>]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("load-test") => load_test(&args[1..]),
+        Some(command) => Err(format!("unknown command: {}\n{}", command, USAGE)),
+        None => Err(USAGE.to_string()),
+    };
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(2);
+    }
+}
+
+/// Options of the `load-test` command.
+#[derive(Debug, PartialEq, Eq)]
+struct LoadTest {
+    rate: Rate,
+    duration: Duration,
+    limit: u32,
+    window: Duration,
+    keys: u32,
+}
+
+/// Parses the flags of the `load-test` command.
+fn parse_load_test(args: &[String]) -> Result<LoadTest, String> {
+    let mut rate = None;
+    let mut duration = Duration::from_secs(10);
+    let mut limit = 100;
+    let mut window = Duration::from_secs(1);
+    let mut keys = 1u32;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--rate" => rate = Some(value.parse::<Rate>()?),
+            "--duration" => duration = parse_duration(value)?,
+            "--limit" => limit = value.parse().map_err(|_| format!("invalid limit: {}", value))?,
+            "--window" => window = parse_duration(value)?,
+            "--keys" => keys = value.parse().map_err(|_| format!("invalid key count: {}", value))?,
+            _ => return Err(format!("unknown flag: {}\n{}", flag, USAGE)),
+        }
+    }
+    let rate = rate.ok_or_else(|| format!("--rate is required\n{}", USAGE))?;
+    if rate.limit == 0 || keys == 0 {
+        return Err("rate and key count must be positive".to_string());
+    }
+    Ok(LoadTest {
+        rate,
+        duration,
+        limit,
+        window,
+        keys,
+    })
+}
+
+/// Returns the mean of `total` over `count` samples, or zero without any.
+fn average(total: Duration, count: u64) -> Duration {
+    match count {
+        0 => Duration::ZERO,
+        count => Duration::from_nanos((total.as_nanos() / u128::from(count)) as u64),
+    }
+}
+
+/// Sends requests at a fixed rate through an in-memory limiter and reports
+/// how many were allowed and blocked, plus the check latency.
+fn load_test(args: &[String]) -> Result<(), String> {
+    let LoadTest {
+        rate,
+        duration,
+        limit,
+        window,
+        keys,
+    } = parse_load_test(args)?;
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), limit, window);
+    let interval = rate.interval();
+    let started = Instant::now();
+    let mut next = started;
+    let mut sent = 0u64;
+    let mut slowest = Duration::ZERO;
+    let mut total_latency = Duration::ZERO;
+
+    while started.elapsed() < duration {
+        let key = format!("load-test-{}", sent % u64::from(keys));
+        let checked_at = Instant::now();
+        limiter.allow(&key);
+        let latency = checked_at.elapsed();
+        slowest = slowest.max(latency);
+        total_latency += latency;
+        sent += 1;
+
+        next += interval;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+
+    let stats = limiter.stats();
+    println!("sent:     {}", sent);
+    println!("allowed:  {}", stats.allowed);
+    println!("blocked:  {}", stats.blocked);
+    println!("rate:     {:.1}/s", sent as f64 / started.elapsed().as_secs_f64());
+    if sent > 0 {
+        println!("latency:  avg {:?}, max {:?}", average(total_latency, sent), slowest);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use api_rate_limiter::rate::Rate;
+    use super::{average, parse_load_test, LoadTest};
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_load_test_flags() {
+        assert_eq!(
+            parse_load_test(&args("--rate 500/s --duration 2s --limit 50 --window 100ms --keys 4")),
+            Ok(LoadTest {
+                rate: Rate::per_second(500),
+                duration: Duration::from_secs(2),
+                limit: 50,
+                window: Duration::from_millis(100),
+                keys: 4,
+            })
+        );
+        assert_eq!(parse_load_test(&args("--rate 10/s")).map(|test| test.limit), Ok(100));
+        assert!(parse_load_test(&args("--duration 2s")).unwrap_err().starts_with("--rate is required"));
+        assert!(parse_load_test(&args("--rate 10/s --keys 0")).is_err());
+        assert!(parse_load_test(&args("--rate 10/s --limit")).is_err());
+        assert!(parse_load_test(&args("--rate 10/s --burst 5")).is_err());
+    }
+
+    #[test]
+    fn test_average_latency_does_not_truncate_or_divide_by_zero() {
+        assert_eq!(average(Duration::from_secs(1), 0), Duration::ZERO);
+        assert_eq!(average(Duration::from_secs(3), 4), Duration::from_millis(750));
+        // More samples than fit in a u32.
+        assert_eq!(average(Duration::from_secs(5_000_000), 5_000_000_000), Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,22 @@
+/// Receives keys a backend dropped because their window ended, e.g. to
+/// clean up related state or emit "client went quiet" events.
+///
+/// Keys are backend keys such as `rate_limit:<client>`, as written by the
+/// limiter. Backends that can report expiry take a handler at construction,
+/// like `InMemoryCache::with_expiry_handler`; a Redis backend can forward
+/// keyspace notifications (`notify-keyspace-events Ex`).
+///
+/// Any `Fn(&str)` is a handler.
+pub trait ExpiryHandler: Send + Sync {
+    /// Called once per expired key, outside the backend's locks.
+    fn expired(&self, key: &str);
+}
+
+impl<F> ExpiryHandler for F
+where
+    F: Fn(&str) + Send + Sync,
+{
+    fn expired(&self, key: &str) {
+        self(key)
+    }
+}
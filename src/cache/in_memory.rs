@@ -1,17 +1,114 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
+use crate::cache::expiry::ExpiryHandler;
 use crate::limiter::CacheBackend;
+use crate::state::StateBackend;
+
+/// Increments of a key within one window after which it moves to a
+/// lock-free counter.
+const DEFAULT_HOT_KEY_THRESHOLD: u32 = 1000;
 
 #[derive(Debug)]
 struct CacheEntry {
     value: u32,
     expires_at: Instant,
+    /// Increments in the current window, for detecting hot keys.
+    increments: u32,
+}
+
+impl CacheEntry {
+    /// An entry that has already expired, to be filled in by its first update.
+    fn expired(at: Instant) -> Self {
+        CacheEntry {
+            value: 0,
+            expires_at: at,
+            increments: 0,
+        }
+    }
+
+    /// Applies `CacheBackend::incr_within` to this entry while its lock is held.
+    fn incr_within(&mut self, amount: u32, ceiling: u32, ttl: Duration, now: Instant) -> (bool, u32) {
+        if self.expires_at <= now {
+            // Expired or freshly inserted: start a new window.
+            self.value = 0;
+            self.expires_at = now + ttl;
+            self.increments = 0;
+        }
+        self.increments = self.increments.saturating_add(1);
+        // A count that would overflow is past any ceiling.
+        match self.value.checked_add(amount) {
+            Some(value) if value <= ceiling => {
+                self.value = value;
+                (true, value)
+            }
+            _ => (false, self.value),
+        }
+    }
+}
+
+/// Packs a hot key's window expiry, in milliseconds since its counter's
+/// base, and its count into one word, so both change in a single
+/// compare-and-swap.
+fn pack(expires: u32, value: u32) -> u64 {
+    (u64::from(expires) << 32) | u64::from(value)
 }
 
-/// An in-memory cache implementation of the `CacheBackend` trait.
-/// It uses a concurrent DashMap to store keys with their expiration.
+fn unpack(state: u64) -> (u32, u32) {
+    ((state >> 32) as u32, state as u32)
+}
+
+/// A promoted key's lock-free counter.
+///
+/// Expiries are kept relative to `base`, the time of promotion in
+/// milliseconds since the cache's epoch, so they fit in 32 bits however long
+/// the cache has been running. A key hot for so long that its next window
+/// no longer fits is demoted and promoted afresh.
+#[derive(Debug)]
+struct HotCounter {
+    base: u64,
+    state: AtomicU64,
+}
+
+impl HotCounter {
+    /// Returns the counter's expiry, in milliseconds since the cache's
+    /// epoch, and its count.
+    fn load(&self) -> (u64, u32) {
+        let (expires, value) = unpack(self.state.load(Ordering::Acquire));
+        (self.base + u64::from(expires), value)
+    }
+}
+
+#[derive(Debug)]
+struct StateEntry {
+    bytes: Vec<u8>,
+    expires_at: Instant,
+}
+
+/// An in-memory cache implementation of the `CacheBackend` and
+/// `StateBackend` traits.
+/// It uses concurrent DashMaps to store keys with their expiration.
+///
+/// Expired keys are dropped lazily when read, or in bulk by `sweep`. Without
+/// a sweeper, keys that are never read again stay in memory; see
+/// `spawn_sweeper`.
+///
+/// Counters are updated under their shard's lock, which serializes every
+/// key in the shard. A key incremented often enough within one window is
+/// promoted to a lock-free counter, a single atomic word holding its count
+/// and expiry, so a very hot key (one tenant sending hundreds of thousands
+/// of requests per second) no longer contends for the shard. Cold keys keep
+/// the simple locked path.
 pub struct InMemoryCache {
     store: DashMap<String, CacheEntry>,
+    hot: DashMap<String, HotCounter>,
+    states: DashMap<String, StateEntry>,
+    on_expire: Option<Arc<dyn ExpiryHandler>>,
+    epoch: Instant,
+    hot_key_threshold: u32,
 }
 
 impl InMemoryCache {
@@ -19,54 +116,479 @@ impl InMemoryCache {
     pub fn new() -> Self {
         InMemoryCache {
             store: DashMap::new(),
+            hot: DashMap::new(),
+            states: DashMap::new(),
+            on_expire: None,
+            epoch: Instant::now(),
+            hot_key_threshold: DEFAULT_HOT_KEY_THRESHOLD,
+        }
+    }
+
+    /// Promotes keys to lock-free counters after `increments` increments
+    /// within one window (1000 by default). `u32::MAX` keeps every key on the
+    /// locked path.
+    pub fn with_hot_key_threshold(mut self, increments: u32) -> Self {
+        self.hot_key_threshold = increments.max(1);
+        self
+    }
+
+    /// Calls `handler` with each expired key as it is dropped, by `sweep` or
+    /// by a read that finds it expired. A key whose window ends and is
+    /// reused before being dropped starts its next window in place without a
+    /// notification, since its client is still active.
+    pub fn with_expiry_handler(mut self, handler: Arc<dyn ExpiryHandler>) -> Self {
+        self.on_expire = Some(handler);
+        self
+    }
+
+    /// Drops every expired key, reporting each to the expiry handler, and
+    /// returns how many were dropped.
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.store.retain(|key, entry| {
+            let live = entry.expires_at > now;
+            if !live {
+                expired.push(key.clone());
+            }
+            live
+        });
+        let now_ms = self.millis(now);
+        self.hot.retain(|key, counter| {
+            let live = counter.load().0 > now_ms;
+            if !live {
+                expired.push(key.clone());
+            }
+            live
+        });
+        self.states.retain(|key, entry| {
+            let live = entry.expires_at > now;
+            if !live {
+                expired.push(key.clone());
+            }
+            live
+        });
+        if let Some(handler) = &self.on_expire {
+            for key in &expired {
+                handler.expired(key);
+            }
+        }
+        expired.len()
+    }
+
+    /// Starts a thread calling `sweep` every `interval`. It stops when the
+    /// returned `Sweeper` is dropped or the cache is.
+    pub fn spawn_sweeper(cache: &Arc<Self>, interval: Duration) -> Sweeper {
+        Sweeper::spawn(cache, interval, |cache| {
+            cache.sweep();
+        })
+    }
+
+    fn expired(&self, key: &str) {
+        if let Some(handler) = &self.on_expire {
+            handler.expired(key);
+        }
+    }
+
+    /// Returns milliseconds from the cache's epoch to `at`.
+    fn millis(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.epoch).as_millis() as u64
+    }
+
+    /// Returns the expiry of a window of `ttl` opening at `now` relative to
+    /// a hot counter's `base`, if it fits.
+    fn hot_expiry(&self, base: u64, now: Instant, ttl: Duration) -> Option<u32> {
+        let ttl = ttl.as_nanos().div_ceil(1_000_000);
+        u32::try_from(u128::from(self.millis(now).saturating_sub(base)) + ttl).ok()
+    }
+
+    /// Returns a hot key's count, or `Some(None)` if its window has ended,
+    /// in which case the key is dropped and reported to the expiry handler.
+    /// Returns `None` if the key is not hot.
+    fn hot_get(&self, key: &str, now: Instant) -> Option<Option<u32>> {
+        let counter = self.hot.get(key)?;
+        let (expires, value) = counter.load();
+        let now_ms = self.millis(now);
+        if expires > now_ms {
+            return Some(Some(value));
+        }
+        // Expired: remove the counter, unless it was renewed meanwhile.
+        drop(counter);
+        if self.hot.remove_if(key, |_, counter| counter.load().0 <= now_ms).is_some() {
+            self.expired(key);
+        }
+        Some(None)
+    }
+
+    /// Applies `incr_within` to a hot key with a compare-and-swap loop.
+    /// Returns `None` if the key is not hot.
+    fn hot_incr(&self, key: &str, amount: u32, ceiling: u32, ttl: Duration, now: Instant) -> Option<(bool, u32)> {
+        let counter = self.hot.get(key)?;
+        let now_ms = self.millis(now);
+        let mut state = counter.state.load(Ordering::Acquire);
+        loop {
+            let (mut expires, mut value) = unpack(state);
+            if counter.base + u64::from(expires) <= now_ms {
+                match self.hot_expiry(counter.base, now, ttl) {
+                    Some(next) => {
+                        expires = next;
+                        value = 0;
+                    }
+                    None => {
+                        // The next window does not fit: go back to the locked path.
+                        drop(counter);
+                        self.demote(key);
+                        return None;
+                    }
+                }
+            }
+            let value = match value.checked_add(amount) {
+                Some(next) if next <= ceiling => next,
+                _ => return Some((false, value)),
+            };
+            let next = pack(expires, value);
+            match counter.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Some((true, value)),
+                Err(current) => state = current,
+            }
+        }
+    }
+
+    /// Runs `update` on `key`'s locked entry, inserting an expired one if
+    /// absent, and promotes the key if it turned hot. Returns `None` without
+    /// running `update` if the key was promoted since it was looked up.
+    fn update_cold<T>(&self, key: &str, now: Instant, update: impl FnOnce(&mut CacheEntry) -> T) -> Option<T> {
+        let (result, hot) = match self.store.get_mut(key) {
+            // Existing keys are updated in place without allocating the key.
+            Some(mut entry) => (update(&mut entry), entry.increments >= self.hot_key_threshold),
+            None => {
+                let mut entry = match self.store.entry(key.to_string()) {
+                    Entry::Occupied(entry) => entry.into_ref(),
+                    Entry::Vacant(_) if self.hot.contains_key(key) => return None,
+                    Entry::Vacant(entry) => entry.insert(CacheEntry::expired(now)),
+                };
+                (update(&mut entry), entry.increments >= self.hot_key_threshold)
+            }
+        };
+        if hot {
+            self.promote(key);
+        }
+        Some(result)
+    }
+
+    /// Moves `key` to a lock-free counter based at the current time, if its
+    /// window is live and its expiry fits.
+    ///
+    /// Both maps change while the key's entry is locked, and the locked path
+    /// checks for a hot counter under that lock, so no update lands in the
+    /// map the key just left.
+    fn promote(&self, key: &str) {
+        if let Entry::Occupied(entry) = self.store.entry(key.to_string()) {
+            let now = Instant::now();
+            let base = self.millis(now);
+            let expires = u32::try_from(self.millis(entry.get().expires_at).saturating_sub(base)).ok();
+            if let (true, Some(expires)) = (entry.get().expires_at > now, expires) {
+                let value = entry.get().value;
+                let state = AtomicU64::new(pack(expires, value));
+                self.hot.insert(key.to_string(), HotCounter { base, state });
+                entry.remove();
+            }
+        }
+    }
+
+    /// Moves a hot `key` back to a locked entry, for updates a
+    /// compare-and-swap cannot express.
+    fn demote(&self, key: &str) {
+        if !self.hot.contains_key(key) {
+            return;
+        }
+        let mut entry = self
+            .store
+            .entry(key.to_string())
+            .or_insert_with(|| CacheEntry::expired(self.epoch));
+        if let Some((_, counter)) = self.hot.remove(key) {
+            let (expires, value) = counter.load();
+            entry.value = value;
+            entry.expires_at = self.epoch + Duration::from_millis(expires);
+            entry.increments = 0;
+        }
+    }
+}
+
+/// A background maintenance thread, started by
+/// `InMemoryCache::spawn_sweeper` or `ReplicatedBackend::spawn_flusher`.
+/// Dropping it stops the thread.
+pub struct Sweeper {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Sweeper {
+    /// Starts a thread calling `run` on `target` every `interval`, until the
+    /// sweeper or the target is dropped.
+    pub(crate) fn spawn<T: Send + Sync + 'static>(target: &Arc<T>, interval: Duration, run: fn(&T)) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let target: Weak<T> = Arc::downgrade(target);
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || loop {
+                let (stopped, wake) = &*stop;
+                let guard = stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                let (guard, _) = wake
+                    .wait_timeout_while(guard, interval, |stopped| !*stopped)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                if *guard {
+                    return;
+                }
+                drop(guard);
+                match target.upgrade() {
+                    Some(target) => run(&target),
+                    None => return,
+                }
+            })
+        };
+        Sweeper {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = true;
+        wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
         }
     }
 }
 
+impl InMemoryCache {
+    /// Atomically replaces the count for `key` with `update(current)`, where
+    /// `current` is `None` if the key is absent or expired, and returns the new
+    /// count. A new or expired key gets the given TTL; a live key keeps its TTL.
+    ///
+    /// The shard lock is held while `update` runs, so it should be cheap.
+    pub fn update_with<F>(&self, key: &str, ttl: Duration, update: F) -> u32
+    where
+        F: FnOnce(Option<u32>) -> u32,
+    {
+        self.demote(key);
+        let now = Instant::now();
+        let mut entry = self.store.entry(key.to_string()).or_insert(CacheEntry::expired(now));
+        if entry.expires_at > now {
+            entry.value = update(Some(entry.value));
+        } else {
+            entry.value = update(None);
+            entry.expires_at = now + ttl;
+        }
+        entry.value
+    }
+}
+
+impl Default for InMemoryCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CacheBackend for InMemoryCache {
     fn get(&self, key: &str) -> Option<u32> {
+        if let Some(value) = self.hot_get(key, Instant::now()) {
+            return value;
+        }
         if let Some(entry) = self.store.get(key) {
             if entry.expires_at > Instant::now() {
-                // println!("Returning the current entry");
-                return Some(entry.value);
+                Some(entry.value)
             } else {
-                // Expired: remove the entry.
-                // println!("removing the current entry and returning None");
+                // Expired: remove the entry, unless it was renewed meanwhile.
                 drop(entry);
-                self.store.remove(key);
-                // println!("removed the current entry and returning None");
-                return None;
+                let now = Instant::now();
+                if self.store.remove_if(key, |_, entry| entry.expires_at <= now).is_some() {
+                    self.expired(key);
+                }
+                None
             }
         } else {
-            // println!("no entry found and returning None");
-            return None;
+            // The key may have been promoted since it was looked up.
+            self.hot_get(key, Instant::now()).flatten()
         }
     }
 
     fn set(&self, key: &str, value: u32, ttl: Duration) -> Result<(), String> {
+        self.demote(key);
         let expires_at = Instant::now() + ttl;
-        let entry = CacheEntry { value, expires_at };
+        let entry = CacheEntry {
+            value,
+            expires_at,
+            increments: 0,
+        };
         self.store.insert(key.to_string(), entry);
         Ok(())
     }
 
-    fn incr(&self, key: &str, amount: u32) -> Result<u32, String> {
+    fn incr(&self, key: &str, amount: u32, ttl_if_new: Duration) -> Result<u32, String> {
+        self.incr_within(key, amount, u32::MAX, ttl_if_new)
+            .map(|(_, value)| value)
+    }
+
+    fn incr_within(
+        &self,
+        key: &str,
+        amount: u32,
+        ceiling: u32,
+        ttl: Duration,
+    ) -> Result<(bool, u32), String> {
         let now = Instant::now();
-        if let Some(mut entry) = self.store.get_mut(key) {
-            if entry.expires_at <= now {
-                // If the entry is expired, reset it.
-                entry.value = amount;
-            } else {
-                entry.value += amount;
+        loop {
+            if let Some(result) = self.hot_incr(key, amount, ceiling, ttl, now) {
+                return Ok(result);
+            }
+            let result = self.update_cold(key, now, |entry| entry.incr_within(amount, ceiling, ttl, now));
+            if let Some(result) = result {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn decr(&self, key: &str, amount: u32) -> Result<u32, String> {
+        let now = Instant::now();
+        loop {
+            if let Some(counter) = self.hot.get(key) {
+                let now_ms = self.millis(now);
+                let mut state = counter.state.load(Ordering::Acquire);
+                loop {
+                    let (expires, value) = unpack(state);
+                    if counter.base + u64::from(expires) <= now_ms {
+                        return Ok(0);
+                    }
+                    let next = value.saturating_sub(amount);
+                    let swapped = counter.state.compare_exchange_weak(state, pack(expires, next), Ordering::AcqRel, Ordering::Acquire);
+                    match swapped {
+                        Ok(_) => return Ok(next),
+                        Err(current) => state = current,
+                    }
+                }
+            }
+            match self.store.get_mut(key) {
+                Some(mut entry) if entry.expires_at > now => {
+                    entry.value = entry.value.saturating_sub(amount);
+                    return Ok(entry.value);
+                }
+                Some(_) => return Ok(0),
+                // Retry if the key was promoted since it was looked up.
+                None if self.hot.contains_key(key) => continue,
+                None => return Ok(0),
             }
-            Ok(entry.value)
+        }
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        old: Option<u32>,
+        new: u32,
+        ttl: Duration,
+    ) -> Result<bool, String> {
+        self.demote(key);
+        let now = Instant::now();
+        let mut entry = self.store.entry(key.to_string()).or_insert(CacheEntry::expired(now));
+        let current = if entry.expires_at > now {
+            Some(entry.value)
         } else {
-            // Insert a new entry. The TTL will be set by the caller if needed.
-            self.store.insert(key.to_string(), CacheEntry {
-                value: amount,
-                expires_at: now, // Temporary; caller should update TTL with `set`.
-            });
-            Ok(amount)
+            None
+        };
+        if current != old {
+            return Ok(false);
+        }
+        entry.value = new;
+        entry.expires_at = now + ttl;
+        Ok(true)
+    }
+
+    fn ttl(&self, key: &str) -> Option<Duration> {
+        if let Some(counter) = self.hot.get(key) {
+            let (expires, _) = counter.load();
+            let left = expires.saturating_sub(self.millis(Instant::now()));
+            return Some(Duration::from_millis(left)).filter(|ttl| !ttl.is_zero());
+        }
+        let entry = self.store.get(key)?;
+        entry.expires_at.checked_duration_since(Instant::now()).filter(|ttl| !ttl.is_zero())
+    }
+
+    fn key_count(&self) -> Option<usize> {
+        Some(self.store.len() + self.hot.len())
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl StateBackend for InMemoryCache {
+    fn get_state(&self, key: &str) -> Option<Vec<u8>> {
+        let entry = self.states.get(key)?;
+        if entry.expires_at > Instant::now() {
+            Some(entry.bytes.clone())
+        } else {
+            None
+        }
+    }
+
+    fn compare_and_swap_state(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: &[u8],
+        ttl: Duration,
+    ) -> Result<bool, String> {
+        let now = Instant::now();
+        // The entry guard holds the shard lock, making the compare and the swap atomic.
+        let mut entry = self.states.entry(key.to_string()).or_insert(StateEntry {
+            bytes: Vec::new(),
+            expires_at: now,
+        });
+        let current = if entry.expires_at > now {
+            Some(entry.bytes.as_slice())
+        } else {
+            None
+        };
+        if current != expected {
+            return Ok(false);
+        }
+        entry.bytes = new.to_vec();
+        entry.expires_at = now + ttl;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::limiter::CacheBackend;
+    use super::InMemoryCache;
+
+    #[test]
+    fn test_hot_keys_are_promoted_after_49_days() {
+        // An epoch a second short of u32::MAX milliseconds ago.
+        let Some(epoch) = Instant::now().checked_sub(Duration::from_millis(u64::from(u32::MAX) - 1_000)) else {
+            return;
+        };
+        let cache = InMemoryCache {
+            epoch,
+            ..InMemoryCache::new().with_hot_key_threshold(2)
+        };
+        let ttl = Duration::from_secs(60);
+        for _ in 0..5 {
+            cache.incr("k", 1, ttl).unwrap();
         }
+        assert!(cache.hot.contains_key("k"));
+        assert_eq!(cache.incr_within("k", 1, 6, ttl), Ok((true, 6)));
+        assert_eq!(cache.incr_within("k", 1, 6, ttl), Ok((false, 6)));
+        assert_eq!(cache.get("k"), Some(6));
+        assert!(cache.ttl("k").unwrap() > Duration::from_secs(59));
+        assert_eq!(cache.decr("k", 2), Ok(4));
     }
 }
@@ -1 +1,5 @@
-pub mod in_memory;
\ No newline at end of file
+pub mod expiry;
+pub mod in_memory;
+pub mod replicated;
+pub mod pool;
+pub mod retry;
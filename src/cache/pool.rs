@@ -0,0 +1,450 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use crate::limiter::CacheBackend;
+
+/// Opens and validates connections for a `Pool`.
+///
+/// Network backends (Redis, Memcached, Postgres) implement this for their
+/// client type and keep a `Pool` of connections. If the connection type
+/// implements `CacheBackend`, the pool is a backend itself.
+pub trait ConnectionManager: Send + Sync {
+    /// The connection type handed out by the pool.
+    type Connection: Send;
+
+    /// Opens a new connection.
+    fn connect(&self) -> Result<Self::Connection, String>;
+
+    /// Checks an idle connection before it is handed out again (e.g. with a
+    /// `PING`). Unhealthy connections are dropped and replaced.
+    fn is_healthy(&self, conn: &mut Self::Connection) -> bool;
+
+    /// Returns whether `error`, returned by a call on a connection, means the
+    /// connection itself is broken (e.g. a reset socket or a protocol
+    /// error). When the pool is used as a `CacheBackend`, broken connections
+    /// are discarded instead of returned. The default keeps every
+    /// connection, leaving broken ones to `is_healthy` on their next
+    /// checkout; override it to discard them straight away.
+    fn is_broken(&self, _error: &str) -> bool {
+        false
+    }
+}
+
+/// A snapshot of a connection pool's state and wait times.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Maximum number of open connections.
+    pub max_size: usize,
+    /// Connections currently handed out.
+    pub in_use: usize,
+    /// Open connections waiting in the pool.
+    pub idle: usize,
+    /// Number of `get` calls that had to wait for a connection.
+    pub waits: u64,
+    /// Total time spent waiting for connections.
+    pub wait_time: Duration,
+    /// Number of `get` calls that gave up after the wait timeout.
+    pub timeouts: u64,
+    /// Number of failed attempts to open a connection.
+    pub connect_errors: u64,
+}
+
+struct Slots<C> {
+    idle: Vec<C>,
+    /// Open connections, idle or in use, plus connections being opened.
+    open: usize,
+}
+
+/// A bounded, blocking pool of connections with health checks.
+pub struct Pool<M: ConnectionManager> {
+    manager: M,
+    max_size: usize,
+    wait_timeout: Duration,
+    slots: Mutex<Slots<M::Connection>>,
+    returned: Condvar,
+    waits: AtomicU64,
+    wait_nanos: AtomicU64,
+    timeouts: AtomicU64,
+    connect_errors: AtomicU64,
+}
+
+impl<M: ConnectionManager> Pool<M> {
+    /// Creates a pool opening at most `max_size` connections through
+    /// `manager`. Connections are opened lazily; `get` waits up to 5 seconds
+    /// by default when all of them are in use.
+    pub fn new(manager: M, max_size: usize) -> Self {
+        Pool {
+            manager,
+            max_size: max_size.max(1),
+            wait_timeout: Duration::from_secs(5),
+            slots: Mutex::new(Slots {
+                idle: Vec::new(),
+                open: 0,
+            }),
+            returned: Condvar::new(),
+            waits: AtomicU64::new(0),
+            wait_nanos: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            connect_errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets how long `get` waits for a connection to be returned before
+    /// failing.
+    pub fn with_wait_timeout(mut self, timeout: Duration) -> Self {
+        self.wait_timeout = timeout;
+        self
+    }
+
+    /// Takes a connection from the pool, opening one if the pool is not full
+    /// and waiting for one to be returned otherwise.
+    pub fn checkout(&self) -> Result<PooledConnection<'_, M>, String> {
+        let started = Instant::now();
+        let mut waited = false;
+        let mut slots = self.slots.lock().map_err(|_| "connection pool poisoned".to_string())?;
+        loop {
+            if let Some(mut conn) = slots.idle.pop() {
+                // Check health without holding the lock, as it may do I/O.
+                drop(slots);
+                if self.manager.is_healthy(&mut conn) {
+                    self.record_wait(waited, started);
+                    return Ok(PooledConnection {
+                        pool: self,
+                        conn: Some(conn),
+                    });
+                }
+                slots = self.slots.lock().map_err(|_| "connection pool poisoned".to_string())?;
+                slots.open -= 1;
+                continue;
+            }
+            if slots.open < self.max_size {
+                slots.open += 1;
+                drop(slots);
+                self.record_wait(waited, started);
+                return match self.manager.connect() {
+                    Ok(conn) => Ok(PooledConnection {
+                        pool: self,
+                        conn: Some(conn),
+                    }),
+                    Err(err) => {
+                        self.connect_errors.fetch_add(1, Ordering::Relaxed);
+                        self.release_slot();
+                        Err(err)
+                    }
+                };
+            }
+            let Some(left) = self.wait_timeout.checked_sub(started.elapsed()) else {
+                self.record_wait(waited, started);
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+                return Err("timed out waiting for a pooled connection".to_string());
+            };
+            waited = true;
+            slots = self
+                .returned
+                .wait_timeout(slots, left)
+                .map_err(|_| "connection pool poisoned".to_string())?
+                .0;
+        }
+    }
+
+    /// Returns a snapshot of the pool's state and wait times.
+    pub fn stats(&self) -> PoolStats {
+        let (idle, open) = self
+            .slots
+            .lock()
+            .map(|slots| (slots.idle.len(), slots.open))
+            .unwrap_or_default();
+        PoolStats {
+            max_size: self.max_size,
+            in_use: open - idle,
+            idle,
+            waits: self.waits.load(Ordering::Relaxed),
+            wait_time: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            connect_errors: self.connect_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_wait(&self, waited: bool, started: Instant) {
+        if waited {
+            self.waits.fetch_add(1, Ordering::Relaxed);
+            self.wait_nanos
+                .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn put_back(&self, conn: M::Connection) {
+        if let Ok(mut slots) = self.slots.lock() {
+            slots.idle.push(conn);
+        }
+        self.returned.notify_one();
+    }
+
+    fn release_slot(&self) {
+        if let Ok(mut slots) = self.slots.lock() {
+            slots.open -= 1;
+        }
+        self.returned.notify_one();
+    }
+}
+
+/// A connection borrowed from a `Pool`, returned to it when dropped.
+pub struct PooledConnection<'a, M: ConnectionManager> {
+    pool: &'a Pool<M>,
+    conn: Option<M::Connection>,
+}
+
+impl<M: ConnectionManager> PooledConnection<'_, M> {
+    /// Closes the connection instead of returning it to the pool. Use this
+    /// after an I/O error leaves the connection in an unknown state.
+    pub fn discard(mut self) {
+        self.conn = None;
+        self.pool.release_slot();
+    }
+}
+
+impl<M: ConnectionManager> Deref for PooledConnection<'_, M> {
+    type Target = M::Connection;
+
+    fn deref(&self) -> &M::Connection {
+        self.conn.as_ref().expect("connection is present until dropped")
+    }
+}
+
+impl<M: ConnectionManager> DerefMut for PooledConnection<'_, M> {
+    fn deref_mut(&mut self) -> &mut M::Connection {
+        self.conn.as_mut().expect("connection is present until dropped")
+    }
+}
+
+impl<M: ConnectionManager> Drop for PooledConnection<'_, M> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.put_back(conn);
+        }
+    }
+}
+
+/// A pool of connections that are backends themselves is a backend: each
+/// call borrows a connection, and a connection whose call fails with an
+/// error the manager reports as broken is discarded rather than reused.
+/// Failing to check out a connection is reported as a backend error, or as
+/// a missing key by `get`.
+impl<M> CacheBackend for Pool<M>
+where
+    M: ConnectionManager,
+    M::Connection: CacheBackend,
+{
+    fn get(&self, key: &str) -> Option<u32> {
+        self.checkout().ok().and_then(|conn| conn.get(key))
+    }
+
+    fn set(&self, key: &str, value: u32, ttl: Duration) -> Result<(), String> {
+        self.call(|conn| conn.set(key, value, ttl))
+    }
+
+    fn incr(&self, key: &str, amount: u32, ttl_if_new: Duration) -> Result<u32, String> {
+        self.call(|conn| conn.incr(key, amount, ttl_if_new))
+    }
+
+    fn decr(&self, key: &str, amount: u32) -> Result<u32, String> {
+        self.call(|conn| conn.decr(key, amount))
+    }
+
+    fn get_many(&self, keys: &[&str]) -> Vec<Option<u32>> {
+        match self.checkout() {
+            Ok(conn) => conn.get_many(keys),
+            Err(_) => vec![None; keys.len()],
+        }
+    }
+
+    fn incr_within(
+        &self,
+        key: &str,
+        amount: u32,
+        ceiling: u32,
+        ttl: Duration,
+    ) -> Result<(bool, u32), String> {
+        self.call(|conn| conn.incr_within(key, amount, ceiling, ttl))
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        old: Option<u32>,
+        new: u32,
+        ttl: Duration,
+    ) -> Result<bool, String> {
+        self.call(|conn| conn.compare_and_swap(key, old, new, ttl))
+    }
+
+    fn ttl(&self, key: &str) -> Option<Duration> {
+        self.checkout().ok().and_then(|conn| conn.ttl(key))
+    }
+
+    fn key_count(&self) -> Option<usize> {
+        self.checkout().ok().and_then(|conn| conn.key_count())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(self.stats())
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        self.call(|conn| conn.ping())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        self.call(|conn| conn.flush())
+    }
+}
+
+impl<M: ConnectionManager> Pool<M> {
+    /// Runs `op` on a borrowed connection, discarding it if `op` fails
+    /// with an error the manager reports as broken.
+    fn call<T>(&self, op: impl FnOnce(&M::Connection) -> Result<T, String>) -> Result<T, String> {
+        let conn = self.checkout()?;
+        let result = op(&conn);
+        if let Err(err) = &result {
+            if self.manager.is_broken(err) {
+                conn.discard();
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::cache::in_memory::InMemoryCache;
+    use crate::limiter::{CacheBackend, RateLimiter};
+    use super::{ConnectionManager, Pool};
+
+    /// Hands out numbered connections; even ones fail their health check.
+    struct Numbered(AtomicU32);
+
+    impl ConnectionManager for Numbered {
+        type Connection = u32;
+
+        fn connect(&self) -> Result<u32, String> {
+            Ok(self.0.fetch_add(1, Ordering::Relaxed))
+        }
+
+        fn is_healthy(&self, conn: &mut u32) -> bool {
+            *conn % 2 == 1
+        }
+    }
+
+    #[test]
+    fn test_pool_reuses_healthy_connections() {
+        let pool = Pool::new(Numbered(AtomicU32::new(1)), 2)
+            .with_wait_timeout(Duration::from_millis(20));
+
+        let first = pool.checkout().unwrap();
+        let second = pool.checkout().unwrap();
+        assert_eq!((*first, *second), (1, 2));
+        assert_eq!(pool.stats().in_use, 2);
+
+        // The pool is full, so a third caller times out.
+        assert!(pool.checkout().is_err());
+        let stats = pool.stats();
+        assert_eq!((stats.waits, stats.timeouts), (1, 1));
+
+        drop(first);
+        drop(second);
+        assert_eq!(pool.stats().idle, 2);
+
+        // Connection 2 fails its health check and is dropped; 1 is reused.
+        let a = pool.checkout().unwrap();
+        let b = pool.checkout().unwrap();
+        assert_eq!((*a, *b), (1, 3));
+        b.discard();
+        assert_eq!(pool.stats().in_use, 1);
+    }
+
+    /// A connection to a shared in-memory store that fails once `down`.
+    struct Conn {
+        store: Arc<InMemoryCache>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl CacheBackend for Conn {
+        fn get(&self, key: &str) -> Option<u32> {
+            self.store.get(key)
+        }
+
+        fn set(&self, key: &str, value: u32, ttl: Duration) -> Result<(), String> {
+            self.store.set(key, value, ttl)
+        }
+
+        fn incr(&self, key: &str, amount: u32, ttl: Duration) -> Result<u32, String> {
+            if self.down.load(Ordering::Relaxed) {
+                return Err("connection reset".to_string());
+            }
+            self.store.incr(key, amount, ttl)
+        }
+    }
+
+    struct Connector {
+        store: Arc<InMemoryCache>,
+        down: Arc<AtomicBool>,
+        opened: AtomicU32,
+    }
+
+    impl ConnectionManager for Connector {
+        type Connection = Conn;
+
+        fn connect(&self) -> Result<Conn, String> {
+            self.opened.fetch_add(1, Ordering::Relaxed);
+            Ok(Conn {
+                store: Arc::clone(&self.store),
+                down: Arc::clone(&self.down),
+            })
+        }
+
+        fn is_healthy(&self, _conn: &mut Conn) -> bool {
+            true
+        }
+
+        fn is_broken(&self, error: &str) -> bool {
+            error == "connection reset"
+        }
+    }
+
+    #[test]
+    fn test_pool_of_backends_is_a_backend() {
+        let down = Arc::new(AtomicBool::new(false));
+        let pool = Arc::new(Pool::new(
+            Connector {
+                store: Arc::new(InMemoryCache::new()),
+                down: Arc::clone(&down),
+                opened: AtomicU32::new(0),
+            },
+            4,
+        ));
+        let limiter = RateLimiter::new(Arc::clone(&pool), 2, Duration::from_secs(60));
+        assert!(limiter.allow("10.0.0.1"));
+        assert!(limiter.allow("10.0.0.1"));
+        assert!(!limiter.allow("10.0.0.1"));
+        // Calls reuse one idle connection, reported in the limiter's stats.
+        assert_eq!(pool.manager.opened.load(Ordering::Relaxed), 1);
+        assert_eq!(limiter.stats().pool.map(|stats| stats.idle), Some(1));
+
+        // Unsupported operations keep the connection.
+        assert!(pool.compare_and_swap("k", None, 1, Duration::from_secs(1)).is_err());
+        assert_eq!(pool.stats().idle, 1);
+
+        // A broken connection is discarded; the next call opens another.
+        down.store(true, Ordering::Relaxed);
+        assert!(pool.incr("k", 1, Duration::from_secs(1)).is_err());
+        assert_eq!(pool.stats().idle, 0);
+        down.store(false, Ordering::Relaxed);
+        assert_eq!(pool.incr("k", 1, Duration::from_secs(1)), Ok(1));
+        assert_eq!(pool.get("k"), Some(1));
+        assert_eq!(pool.manager.opened.load(Ordering::Relaxed), 2);
+    }
+}
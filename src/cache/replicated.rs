@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+use crate::cache::in_memory::Sweeper;
+use crate::limiter::CacheBackend;
+
+/// A counter update shipped between regions.
+///
+/// Deltas carry the sending region's total for the key rather than an
+/// increment, so applying one twice or out of order is harmless.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterDelta {
+    /// The region the count belongs to.
+    pub region: String,
+    /// The backend key.
+    pub key: String,
+    /// The region's current count for the key.
+    pub count: u32,
+    /// Time left in the region's window, if known.
+    pub ttl: Option<Duration>,
+}
+
+/// Ships counter deltas to peer regions (e.g. over gRPC, Kafka or HTTP).
+pub trait ReplicationTransport: Send + Sync {
+    /// Sends `delta` to every peer region.
+    fn send(&self, delta: &CounterDelta) -> Result<(), String>;
+
+    /// Sends several deltas at once. The default sends them one by one;
+    /// transports with a batch API should override it.
+    fn send_batch(&self, deltas: &[CounterDelta]) -> Result<(), String> {
+        deltas.iter().try_for_each(|delta| self.send(delta))
+    }
+}
+
+impl<F> ReplicationTransport for F
+where
+    F: Fn(&CounterDelta) -> Result<(), String> + Send + Sync,
+{
+    fn send(&self, delta: &CounterDelta) -> Result<(), String> {
+        self(delta)
+    }
+}
+
+/// Which counts the replicated backend reports to the limiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LimitScope {
+    /// Counts from all regions are summed, so the limit is global.
+    #[default]
+    Global,
+    /// Only the local region's count is reported, so the limit applies per
+    /// region. Remote counts are still merged and available via `global_count`.
+    Regional,
+}
+
+struct RemoteCount {
+    count: u32,
+    expires_at: Instant,
+}
+
+/// How many deltas `apply` merges between sweeps of expired remote counts.
+const SWEEP_EVERY: u64 = 1024;
+
+/// Outbound deltas waiting to be shipped, latest per key.
+#[derive(Default)]
+struct Outbox {
+    deltas: HashMap<String, CounterDelta>,
+    since: Option<Instant>,
+}
+
+/// A backend for active-active deployments that applies increments to a
+/// local backend and ships them to peer regions.
+///
+/// Remote counts are merged as a grow-only counter (G-counter): each region
+/// owns one slot per key, merges keep the highest count seen for a slot,
+/// and the key's global value is the sum of all slots. A slot is replaced
+/// once its window has expired, so window resets propagate. Expired slots
+/// are dropped as deltas are merged and by `sweep`.
+pub struct ReplicatedBackend<B: CacheBackend, T: ReplicationTransport> {
+    region: String,
+    local: B,
+    transport: T,
+    scope: LimitScope,
+    remote_ttl: Duration,
+    remote: DashMap<String, HashMap<String, RemoteCount>>,
+    applied: AtomicU64,
+    batch_size: usize,
+    max_delay: Duration,
+    outbox: Mutex<Outbox>,
+    replication_errors: AtomicU64,
+}
+
+impl<B: CacheBackend, T: ReplicationTransport> ReplicatedBackend<B, T> {
+    /// Creates a replicated backend for `region`, storing local counts in
+    /// `local` and shipping deltas through `transport`.
+    pub fn new(region: &str, local: B, transport: T) -> Self {
+        ReplicatedBackend {
+            region: region.to_string(),
+            local,
+            transport,
+            scope: LimitScope::Global,
+            remote_ttl: Duration::from_secs(60),
+            remote: DashMap::new(),
+            applied: AtomicU64::new(0),
+            batch_size: 1,
+            max_delay: Duration::ZERO,
+            outbox: Mutex::new(Outbox::default()),
+            replication_errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets whether the limit applies to the global or the regional count.
+    pub fn with_scope(mut self, scope: LimitScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Sets how long remote counts are kept when a delta carries no TTL.
+    pub fn with_remote_ttl(mut self, ttl: Duration) -> Self {
+        self.remote_ttl = ttl;
+        self
+    }
+
+    /// Buffers outbound deltas instead of sending one per increment. Only
+    /// the latest count per key is kept, and the buffer is sent with
+    /// `send_batch` once it holds `max_deltas` keys or its oldest delta is
+    /// `max_delay` old, checked on each increment. The last deltas before
+    /// a quiet spell wait for the next increment, so run `spawn_flusher` or
+    /// call `flush` periodically; `flush` sends whatever is buffered. Peers
+    /// see local counts up to `max_delay` late.
+    pub fn with_batching(mut self, max_deltas: usize, max_delay: Duration) -> Self {
+        self.batch_size = max_deltas.max(1);
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Merges a delta received from a peer region.
+    pub fn apply(&self, delta: &CounterDelta) {
+        if delta.region == self.region {
+            return;
+        }
+        if self.applied.fetch_add(1, Ordering::Relaxed) % SWEEP_EVERY == SWEEP_EVERY - 1 {
+            self.sweep();
+        }
+        let now = Instant::now();
+        let expires_at = now + delta.ttl.unwrap_or(self.remote_ttl);
+        let mut slots = self.remote.entry(delta.key.clone()).or_default();
+        // Slots whose window is over are dropped, so they start afresh.
+        slots.retain(|_, slot| slot.expires_at > now);
+        let slot = slots.entry(delta.region.clone()).or_insert(RemoteCount {
+            count: 0,
+            expires_at,
+        });
+        slot.count = slot.count.max(delta.count);
+        if delta.ttl.is_some() {
+            slot.expires_at = expires_at;
+        }
+    }
+
+    /// Drops expired remote counts, and keys left without any, and returns
+    /// how many keys were dropped. `apply` calls it every 1024 deltas.
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        let before = self.remote.len();
+        self.remote.retain(|_, slots| {
+            slots.retain(|_, slot| slot.expires_at > now);
+            !slots.is_empty()
+        });
+        before.saturating_sub(self.remote.len())
+    }
+
+    /// Returns the sum of live remote counts for `key`.
+    pub fn remote_count(&self, key: &str) -> u32 {
+        let now = Instant::now();
+        self.remote
+            .get(key)
+            .map(|slots| {
+                slots
+                    .values()
+                    .filter(|slot| slot.expires_at > now)
+                    .fold(0u32, |sum, slot| sum.saturating_add(slot.count))
+            })
+            .unwrap_or(0)
+    }
+
+    /// Returns the merged count for `key` across all regions.
+    pub fn global_count(&self, key: &str) -> u32 {
+        self.local
+            .get(key)
+            .unwrap_or(0)
+            .saturating_add(self.remote_count(key))
+    }
+
+    /// Starts a thread sending buffered deltas every `interval` once their
+    /// oldest is `max_delay` old, so they go out when increments stop. Hand
+    /// the returned `Sweeper` to `RateLimiter::with_sweeper` to stop it on
+    /// shutdown.
+    pub fn spawn_flusher(backend: &Arc<Self>, interval: Duration) -> Sweeper
+    where
+        B: 'static,
+        T: 'static,
+    {
+        Sweeper::spawn(backend, interval, |backend| {
+            let due = {
+                let mut outbox = backend.outbox.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                match outbox.since {
+                    Some(since) if since.elapsed() >= backend.max_delay => {
+                        outbox.since = None;
+                        mem::take(&mut outbox.deltas)
+                    }
+                    _ => HashMap::new(),
+                }
+            };
+            let _ = backend.send_all(due);
+        })
+    }
+
+    /// Returns how many deltas failed to send.
+    pub fn replication_errors(&self) -> u64 {
+        self.replication_errors.load(Ordering::Relaxed)
+    }
+
+    fn ship(&self, key: &str, count: u32) {
+        let delta = CounterDelta {
+            region: self.region.clone(),
+            key: key.to_string(),
+            count,
+            ttl: self.local.ttl(key),
+        };
+        if self.batch_size == 1 {
+            if self.transport.send(&delta).is_err() {
+                self.replication_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            return;
+        }
+        let due = {
+            let mut outbox = self.outbox.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let now = Instant::now();
+            let since = *outbox.since.get_or_insert(now);
+            outbox.deltas.insert(delta.key.clone(), delta);
+            if outbox.deltas.len() >= self.batch_size || now.duration_since(since) >= self.max_delay {
+                outbox.since = None;
+                mem::take(&mut outbox.deltas)
+            } else {
+                HashMap::new()
+            }
+        };
+        let _ = self.send_all(due);
+    }
+
+    /// Sends buffered deltas outside the outbox lock.
+    fn send_all(&self, deltas: HashMap<String, CounterDelta>) -> Result<(), String> {
+        if deltas.is_empty() {
+            return Ok(());
+        }
+        let deltas: Vec<CounterDelta> = deltas.into_values().collect();
+        self.transport.send_batch(&deltas).inspect_err(|_| {
+            self.replication_errors.fetch_add(deltas.len() as u64, Ordering::Relaxed);
+        })
+    }
+
+    fn scoped(&self, key: &str, local: u32) -> u32 {
+        match self.scope {
+            LimitScope::Global => local.saturating_add(self.remote_count(key)),
+            LimitScope::Regional => local,
+        }
+    }
+}
+
+impl<B: CacheBackend, T: ReplicationTransport> CacheBackend for ReplicatedBackend<B, T> {
+    fn get(&self, key: &str) -> Option<u32> {
+        let local = self.local.get(key);
+        let count = self.scoped(key, local.unwrap_or(0));
+        if local.is_none() && count == 0 {
+            None
+        } else {
+            Some(count)
+        }
+    }
+
+    fn set(&self, key: &str, value: u32, ttl: Duration) -> Result<(), String> {
+        self.local.set(key, value, ttl)?;
+        self.ship(key, value);
+        Ok(())
+    }
+
+    fn incr(&self, key: &str, amount: u32, ttl_if_new: Duration) -> Result<u32, String> {
+        let local = self.local.incr(key, amount, ttl_if_new)?;
+        self.ship(key, local);
+        Ok(self.scoped(key, local))
+    }
+
+    /// Checks the ceiling against the merged count, then increments the
+    /// local count atomically. Only applied increments are shipped, so
+    /// rejected requests never reach peers.
+    fn incr_within(
+        &self,
+        key: &str,
+        amount: u32,
+        ceiling: u32,
+        ttl: Duration,
+    ) -> Result<(bool, u32), String> {
+        let remote = self.scoped(key, 0);
+        let (applied, local) = self
+            .local
+            .incr_within(key, amount, ceiling.saturating_sub(remote), ttl)?;
+        if applied {
+            self.ship(key, local);
+        }
+        Ok((applied, local.saturating_add(remote)))
+    }
+
+    /// Decrements the local count. Remote counts merge as a grow-only
+    /// counter, so peers keep the highest count they saw for this region
+    /// until the window ends; refunds only take effect locally.
+    fn decr(&self, key: &str, amount: u32) -> Result<u32, String> {
+        let local = self.local.decr(key, amount)?;
+        Ok(self.scoped(key, local))
+    }
+
+    fn ttl(&self, key: &str) -> Option<Duration> {
+        self.local.ttl(key)
+    }
+
+    fn key_count(&self) -> Option<usize> {
+        self.local.key_count()
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        self.local.ping()
+    }
+
+    /// Sends any buffered deltas, then flushes the local backend.
+    fn flush(&self) -> Result<(), String> {
+        let buffered = {
+            let mut outbox = self.outbox.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            outbox.since = None;
+            mem::take(&mut outbox.deltas)
+        };
+        self.send_all(buffered)?;
+        self.local.flush()
+    }
+}
@@ -0,0 +1,316 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::cache::pool::PoolStats;
+use crate::limiter::CacheBackend;
+
+/// Decides from its error whether a failed increment or decrement may be
+/// retried.
+///
+/// Any `Fn(&str) -> bool` is a classifier. Only errors raised before the
+/// command reached the server are safe to retry, since then it cannot have
+/// been applied; `not_sent` recognizes the common ones.
+pub trait RetryClassifier: Send + Sync {
+    /// Returns `true` if the operation that failed with `error` was never sent.
+    fn is_retryable(&self, error: &str) -> bool;
+}
+
+impl<F> RetryClassifier for F
+where
+    F: Fn(&str) -> bool + Send + Sync,
+{
+    fn is_retryable(&self, error: &str) -> bool {
+        self(error)
+    }
+}
+
+/// The default classifier: a refused connection, a missing connection, or
+/// a timeout waiting for one from a `Pool`, all of which fail before any
+/// command is written.
+pub fn not_sent(error: &str) -> bool {
+    let error = error.to_ascii_lowercase();
+    ["connection refused", "not connected", "timed out waiting for a pooled connection"]
+        .iter()
+        .any(|cause| error.contains(cause))
+}
+
+/// A backend wrapper that retries failed operations with exponential
+/// backoff and jitter, so a single dropped packet does not turn into a
+/// failed check.
+///
+/// Operations that are safe to repeat, `set` and `flush`, are retried on
+/// any error. Increments and decrements are retried only when the
+/// `RetryClassifier` says the command was never sent, as a retry after a
+/// lost reply could count or refund a request twice. Compare-and-swap is
+/// passed through once, as a retry could report a swap that succeeded as
+/// failed. `get` reports no errors, so there is nothing to classify.
+pub struct RetryBackend<B: CacheBackend> {
+    inner: B,
+    classifier: Box<dyn RetryClassifier>,
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    seed: AtomicU64,
+    retries: AtomicU64,
+}
+
+impl<B: CacheBackend> RetryBackend<B> {
+    /// Wraps `inner`. Defaults: 3 attempts, backoff starting at 10ms and
+    /// capped at 200ms, with full jitter.
+    pub fn new(inner: B) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0);
+        RetryBackend {
+            inner,
+            classifier: Box::new(not_sent),
+            max_attempts: 3,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(200),
+            jitter: true,
+            seed: AtomicU64::new(seed | 1),
+            retries: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the total number of attempts per operation, including the first.
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry; it doubles on each further
+    /// retry up to `max_delay`.
+    pub fn with_backoff(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay.max(base_delay);
+        self
+    }
+
+    /// Enables or disables jitter. With jitter, each delay is drawn uniformly
+    /// between zero and the backoff, so clients that failed together do not
+    /// retry together.
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets which errors make increments and decrements retryable. The
+    /// default is `not_sent`.
+    pub fn with_retry_classifier<C: RetryClassifier + 'static>(mut self, classifier: C) -> Self {
+        self.classifier = Box::new(classifier);
+        self
+    }
+
+    /// Returns the wrapped backend.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Returns how many retries have been made.
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Runs an idempotent operation, retrying it on any error.
+    fn retry<T>(&self, op: impl FnMut() -> Result<T, String>) -> Result<T, String> {
+        self.retry_if(|_| true, op)
+    }
+
+    /// Runs an operation that must not be applied twice, retrying it only
+    /// when the classifier says it was never sent.
+    fn retry_unsent<T>(&self, op: impl FnMut() -> Result<T, String>) -> Result<T, String> {
+        self.retry_if(|err| self.classifier.is_retryable(err), op)
+    }
+
+    fn retry_if<T>(
+        &self,
+        retryable: impl Fn(&str) -> bool,
+        mut op: impl FnMut() -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt >= self.max_attempts || !retryable(&err) => return Err(err),
+                Err(_) => {
+                    thread::sleep(self.delay(attempt));
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Returns the delay before retry number `attempt` (starting at 1).
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        if !self.jitter {
+            return backoff;
+        }
+        backoff.mul_f64(self.next_random())
+    }
+
+    /// Returns a pseudo-random number in `[0, 1)` from a shared xorshift state.
+    fn next_random(&self) -> f64 {
+        let mut x = self.seed.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.seed.store(x, Ordering::Relaxed);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl<B: CacheBackend> CacheBackend for RetryBackend<B> {
+    fn get(&self, key: &str) -> Option<u32> {
+        self.inner.get(key)
+    }
+
+    fn set(&self, key: &str, value: u32, ttl: Duration) -> Result<(), String> {
+        self.retry(|| self.inner.set(key, value, ttl))
+    }
+
+    fn incr(&self, key: &str, amount: u32, ttl_if_new: Duration) -> Result<u32, String> {
+        self.retry_unsent(|| self.inner.incr(key, amount, ttl_if_new))
+    }
+
+    fn decr(&self, key: &str, amount: u32) -> Result<u32, String> {
+        self.retry_unsent(|| self.inner.decr(key, amount))
+    }
+
+    fn get_many(&self, keys: &[&str]) -> Vec<Option<u32>> {
+        self.inner.get_many(keys)
+    }
+
+    fn incr_within(
+        &self,
+        key: &str,
+        amount: u32,
+        ceiling: u32,
+        ttl: Duration,
+    ) -> Result<(bool, u32), String> {
+        self.retry_unsent(|| self.inner.incr_within(key, amount, ceiling, ttl))
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        old: Option<u32>,
+        new: u32,
+        ttl: Duration,
+    ) -> Result<bool, String> {
+        self.inner.compare_and_swap(key, old, new, ttl)
+    }
+
+    fn ttl(&self, key: &str) -> Option<Duration> {
+        self.inner.ttl(key)
+    }
+
+    fn key_count(&self) -> Option<usize> {
+        self.inner.key_count()
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.pool_stats()
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        self.inner.ping()
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        self.retry(|| self.inner.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+    use crate::limiter::CacheBackend;
+    use super::RetryBackend;
+
+    /// Fails the first `failures` writes and decrements, and every
+    /// increment, with `error`.
+    struct Flaky {
+        failures: AtomicU32,
+        calls: AtomicU32,
+        error: &'static str,
+    }
+
+    impl Flaky {
+        fn fail(&self) -> Result<(), String> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            if self.failures.load(Ordering::Relaxed) > 0 {
+                self.failures.fetch_sub(1, Ordering::Relaxed);
+                return Err(self.error.to_string());
+            }
+            Ok(())
+        }
+    }
+
+    impl CacheBackend for Flaky {
+        fn get(&self, _key: &str) -> Option<u32> {
+            None
+        }
+
+        fn set(&self, _key: &str, _value: u32, _ttl: Duration) -> Result<(), String> {
+            self.fail()
+        }
+
+        fn incr(&self, _key: &str, _amount: u32, _ttl: Duration) -> Result<u32, String> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            Err(self.error.to_string())
+        }
+
+        fn decr(&self, _key: &str, _amount: u32) -> Result<u32, String> {
+            self.fail().map(|_| 0)
+        }
+    }
+
+    fn flaky(failures: u32, error: &'static str) -> RetryBackend<Flaky> {
+        RetryBackend::new(Flaky {
+            failures: AtomicU32::new(failures),
+            calls: AtomicU32::new(0),
+            error,
+        })
+        .with_backoff(Duration::from_millis(1), Duration::from_millis(2))
+    }
+
+    #[test]
+    fn test_retries_idempotent_writes() {
+        let backend = flaky(2, "connection reset");
+        assert!(backend.set("k", 1, Duration::from_secs(1)).is_ok());
+        assert_eq!(backend.retries(), 2);
+
+        let backend = flaky(5, "connection reset");
+        assert!(backend.set("k", 1, Duration::from_secs(1)).is_err());
+        assert_eq!(backend.inner().calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_retries_counters_only_when_unsent() {
+        // The reply was lost, so the increment may have been applied.
+        let backend = flaky(0, "connection reset");
+        assert!(backend.incr("k", 1, Duration::from_secs(1)).is_err());
+        assert!(backend.incr_within("k", 1, 5, Duration::from_secs(1)).is_err());
+        assert_eq!(backend.inner().calls.load(Ordering::Relaxed), 2);
+        assert_eq!(backend.retries(), 0);
+
+        // The connection was refused, so nothing was sent.
+        let backend = flaky(1, "Connection refused (os error 111)");
+        assert_eq!(backend.decr("k", 1), Ok(0));
+        assert!(backend.incr("k", 1, Duration::from_secs(1)).is_err());
+        assert_eq!(backend.inner().calls.load(Ordering::Relaxed), 5);
+
+        let backend = flaky(1, "server busy").with_retry_classifier(|err: &str| err == "server busy");
+        assert_eq!(backend.decr("k", 1), Ok(0));
+        assert_eq!(backend.retries(), 1);
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use crate::clock::{Clock, SystemClock};
+
+/// Aligns fixed windows to the calendar in a fixed UTC offset, instead of
+/// starting each key's window at its first request.
+///
+/// A window of one minute starts at :00 seconds, one hour at :00 minutes and
+/// one day at local midnight, as billing-style quotas ("1000 requests per
+/// calendar day") require. Other lengths are aligned to multiples of the
+/// length since the Unix epoch in local time. Every instance must use the
+/// same offset.
+#[derive(Clone)]
+pub struct CalendarWindows {
+    utc_offset_minutes: i32,
+    clock: Arc<dyn Clock>,
+}
+
+impl CalendarWindows {
+    /// Aligns windows in UTC using the system clock.
+    pub fn new() -> Self {
+        CalendarWindows {
+            utc_offset_minutes: 0,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the timezone as an offset from UTC in minutes (e.g. `-300` for UTC-5).
+    pub fn with_utc_offset_minutes(mut self, minutes: i32) -> Self {
+        self.utc_offset_minutes = minutes;
+        self
+    }
+
+    /// Uses `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the index of the current window of length `window`, counted
+    /// from the Unix epoch in local time, and the time left in it.
+    pub fn current(&self, window: Duration) -> (i64, Duration) {
+        let local = self.local_millis();
+        let length = (window.as_millis() as i64).max(1);
+        let left = length - local.rem_euclid(length);
+        (local.div_euclid(length), Duration::from_millis(left as u64))
+    }
+
+    /// Returns the index of the current calendar `period` in local time and
+    /// the time left in it. Months are indexed as `year * 12 + month - 1`.
+    pub fn current_period(&self, period: Period) -> (i64, Duration) {
+        match period {
+            Period::Day => self.current(Duration::from_secs(86_400)),
+            Period::Month => {
+                let local = self.local_millis();
+                let days = local.div_euclid(86_400_000);
+                let (year, month, _) = civil_from_days(days);
+                let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+                let next = days_from_civil(next_year, next_month, 1) * 86_400_000;
+                (year * 12 + month - 1, Duration::from_millis((next - local) as u64))
+            }
+        }
+    }
+
+    fn local_millis(&self) -> i64 {
+        let millis = match self.clock.now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_millis() as i64,
+            Err(before) => -(before.duration().as_millis() as i64),
+        };
+        millis + i64::from(self.utc_offset_minutes) * 60_000
+    }
+}
+
+/// A calendar period for long-horizon quotas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Period {
+    /// From local midnight to the next.
+    Day,
+    /// From the first of the month at local midnight to the next.
+    Month,
+}
+
+impl Period {
+    /// Returns the longest the period can be.
+    pub fn max_length(self) -> Duration {
+        match self {
+            Period::Day => Duration::from_secs(86_400),
+            Period::Month => Duration::from_secs(31 * 86_400),
+        }
+    }
+}
+
+/// Returns the proleptic Gregorian `(year, month, day)` of a day counted
+/// from the Unix epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Returns the day counted from the Unix epoch of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+impl Default for CalendarWindows {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{civil_from_days, days_from_civil, CalendarWindows, Period};
+
+    #[test]
+    fn test_civil_round_trip() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_month_period() {
+        // 2024-02-29 12:00 UTC: 12 hours left in February.
+        let windows = CalendarWindows::new()
+            .with_clock(Arc::new(|| UNIX_EPOCH + Duration::from_secs(1_709_208_000)));
+        assert_eq!(
+            windows.current_period(Period::Month),
+            (2024 * 12 + 1, Duration::from_secs(12 * 3600))
+        );
+    }
+}
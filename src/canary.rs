@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::limiter::{CacheBackend, RateLimitDecision, RateLimiter};
+
+/// How often a candidate policy disagreed with the enforced one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Divergence {
+    /// Requests checked by both policies.
+    pub compared: u64,
+    /// Requests the enforced policy allowed but the candidate would have blocked.
+    pub candidate_stricter: u64,
+    /// Requests the enforced policy blocked but the candidate would have allowed.
+    pub candidate_looser: u64,
+}
+
+/// A limiter that enforces one policy while evaluating a candidate policy
+/// on the same traffic, for tuning limits from real data before rolling
+/// them out.
+///
+/// The candidate keeps its own counters and never affects decisions. Its
+/// keys are namespaced under `canary:`, so both limiters can share a
+/// backend without counting each request twice.
+pub struct CanaryLimiter<B: CacheBackend, C: CacheBackend> {
+    primary: RateLimiter<B>,
+    candidate: RateLimiter<C>,
+    compared: AtomicU64,
+    candidate_stricter: AtomicU64,
+    candidate_looser: AtomicU64,
+}
+
+impl<B: CacheBackend, C: CacheBackend> CanaryLimiter<B, C> {
+    /// Enforces `primary` and evaluates `candidate` alongside it, nesting
+    /// the candidate's key namespace under `canary:`.
+    pub fn new(primary: RateLimiter<B>, candidate: RateLimiter<C>) -> Self {
+        CanaryLimiter {
+            primary,
+            candidate: candidate.within_namespace("canary:"),
+            compared: AtomicU64::new(0),
+            candidate_stricter: AtomicU64::new(0),
+            candidate_looser: AtomicU64::new(0),
+        }
+    }
+
+    /// Checks whether a request from the given IP is allowed by the enforced
+    /// policy.
+    pub fn allow(&self, ip: &str) -> bool {
+        self.check(ip).allowed
+    }
+
+    /// Checks a request against both policies and returns the enforced
+    /// policy's decision.
+    pub fn check(&self, ip: &str) -> RateLimitDecision {
+        let decision = self.primary.check(ip);
+        let candidate = self.candidate.check(ip);
+        self.compared.fetch_add(1, Ordering::Relaxed);
+        match (decision.allowed, candidate.allowed) {
+            (true, false) => {
+                self.candidate_stricter.fetch_add(1, Ordering::Relaxed);
+            }
+            (false, true) => {
+                self.candidate_looser.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+        decision
+    }
+
+    /// Returns how often the candidate disagreed with the enforced policy.
+    pub fn divergence(&self) -> Divergence {
+        Divergence {
+            compared: self.compared.load(Ordering::Relaxed),
+            candidate_stricter: self.candidate_stricter.load(Ordering::Relaxed),
+            candidate_looser: self.candidate_looser.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the enforced limiter.
+    pub fn primary(&self) -> &RateLimiter<B> {
+        &self.primary
+    }
+
+    /// Returns the candidate limiter.
+    pub fn candidate(&self) -> &RateLimiter<C> {
+        &self.candidate
+    }
+}
@@ -0,0 +1,27 @@
+use std::time::SystemTime;
+
+/// A source of wall-clock time, injectable so time-dependent policies can be
+/// tested or evaluated against a simulated clock.
+pub trait Clock: Send + Sync {
+    /// Returns the current wall-clock time.
+    fn now(&self) -> SystemTime;
+}
+
+/// The system wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> SystemTime + Send + Sync,
+{
+    fn now(&self) -> SystemTime {
+        self()
+    }
+}
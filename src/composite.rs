@@ -0,0 +1,222 @@
+use std::time::{Duration, Instant};
+use crate::key::Key;
+use crate::limiter::{CacheBackend, RateLimitDecision, RateLimiter};
+use crate::priority::Priority;
+use crate::rate::Rate;
+
+/// Reported as the failed dimension when the service-wide limit set by
+/// `with_global_limit` blocks a composite check.
+const GLOBAL_DIMENSION: &str = "global";
+
+/// One dimension of a composite check: a key and the limit that applies to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyedPolicy<'a> {
+    /// Name of the dimension (e.g. `"ip"`, `"api_key"`, `"endpoint"`). Keys of
+    /// different dimensions never share a counter.
+    pub name: &'a str,
+    /// The key to count against, such as the client IP.
+    pub key: &'a str,
+    /// Maximum allowed requests within `window`.
+    pub limit: u32,
+    /// Duration of the rate limiting window.
+    pub window: Duration,
+}
+
+impl<'a> KeyedPolicy<'a> {
+    /// The dimension `name`, counting `key` against `rate`.
+    pub const fn new(name: &'a str, key: &'a str, rate: Rate) -> Self {
+        KeyedPolicy {
+            name,
+            key,
+            limit: rate.limit,
+            window: rate.window,
+        }
+    }
+
+    /// Returns the dimension's limit and window as a `Rate`.
+    pub const fn rate(&self) -> Rate {
+        Rate::new(self.limit, self.window)
+    }
+}
+
+/// The outcome of checking several dimensions in one call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompositeDecision {
+    /// Whether the request is within the limits of every dimension.
+    pub allowed: bool,
+    /// Name of the first dimension that blocked the request, if any, or
+    /// `"global"` if the limiter's service-wide limit blocked it.
+    pub failed: Option<String>,
+    /// The decision for each dimension, in the order the policies were given.
+    pub decisions: Vec<RateLimitDecision>,
+}
+
+impl<B: CacheBackend> RateLimiter<B> {
+    /// Checks a request against several dimensions at once, e.g. per IP, per
+    /// API key and per endpoint.
+    ///
+    /// The request is allowed only if every dimension is under its limit, and
+    /// quota is consumed in every dimension only in that case. Current counts
+    /// are read in a single `get_many` call before anything is incremented.
+    /// If a concurrent request fills a dimension between the read and the
+    /// increments, the dimensions already counted are decremented again.
+    /// A service-wide limit set by `with_global_limit` applies as well, and
+    /// the first dimension's key is validated as the client key, with the
+    /// invalid key policy deciding or replacing it.
+    ///
+    /// Counters live in their own namespace, `rate_limit_policy:`, under the
+    /// current policy version and, with calendar windows, the current
+    /// window of each policy. Blocked dimensions report when their window
+    /// ends as `retry_after`, if the backend can tell.
+    ///
+    /// # Arguments
+    ///
+    /// * `policies` - The dimensions to check, each with its own key and limit.
+    pub fn check_all(&self, policies: &[KeyedPolicy<'_>]) -> CompositeDecision {
+        let started = Instant::now();
+        // The first dimension identifies the client, as in `stats()`.
+        let (client, invalid_key) = match policies.first().map(|first| (first, self.validate_key(first.key))) {
+            None => ("", false),
+            Some((_, Ok(validated))) => validated,
+            Some((first, Err(allowed))) => {
+                self.stats.record(first.key, allowed);
+                return CompositeDecision {
+                    allowed,
+                    failed: (!allowed).then(|| first.name.to_string()),
+                    decisions: policies
+                        .iter()
+                        .map(|policy| RateLimitDecision {
+                            remaining: if allowed { policy.limit } else { 0 },
+                            invalid_key: true,
+                            ..decision(policy, 0, allowed)
+                        })
+                        .collect(),
+                };
+            }
+        };
+        let fallback: Vec<KeyedPolicy<'_>>;
+        let policies = if invalid_key {
+            fallback = policies
+                .iter()
+                .enumerate()
+                .map(|(index, policy)| if index == 0 { KeyedPolicy { key: client, ..*policy } } else { *policy })
+                .collect();
+            &fallback[..]
+        } else {
+            policies
+        };
+
+        let (keys, ttls): (Vec<Key>, Vec<Duration>) = policies
+            .iter()
+            .map(|policy| self.policy_key_for(policy.name, &self.client_key(policy.key), policy.window))
+            .unzip();
+        let key_refs: Vec<&str> = keys.iter().map(|key| &**key).collect();
+        // Blocked dimensions that are full report when their window ends.
+        let blocked_decision = |index: usize, count: u32| {
+            let policy = &policies[index];
+            RateLimitDecision {
+                retry_after: if count >= policy.limit { self.cache.ttl(&keys[index]) } else { None },
+                ..decision(policy, count, false)
+            }
+        };
+        let counts = self.cache.get_many(&key_refs);
+
+        let blocked = policies
+            .iter()
+            .zip(&counts)
+            .position(|(policy, count)| count.unwrap_or(0) >= policy.limit);
+
+        let mut failed = blocked.map(|index| policies[index].name);
+        let global = if blocked.is_none() { self.claim_global(1) } else { Ok(()) };
+        let mut decisions: Vec<RateLimitDecision> = match (blocked, global) {
+            (Some(_), _) => counts
+                .iter()
+                .enumerate()
+                .map(|(index, count)| blocked_decision(index, count.unwrap_or(0)))
+                .collect(),
+            (None, Err(retry_after)) => {
+                failed = Some(GLOBAL_DIMENSION);
+                policies
+                    .iter()
+                    .zip(&counts)
+                    .map(|(policy, count)| RateLimitDecision {
+                        retry_after,
+                        global_limited: true,
+                        ..decision(policy, count.unwrap_or(0), false)
+                    })
+                    .collect()
+            }
+            (None, Ok(())) => {
+                let mut decisions = Vec::with_capacity(policies.len());
+                for (index, policy) in policies.iter().enumerate() {
+                    let (allowed, count) = self
+                        .cache
+                        .incr_within(&keys[index], 1, policy.limit, ttls[index])
+                        .unwrap_or_else(|err| {
+                            self.backend_error(&err);
+                            (false, 0)
+                        });
+                    decisions.push(if allowed { decision(policy, count, true) } else { blocked_decision(index, count) });
+                    if !allowed {
+                        failed = Some(policy.name);
+                        break;
+                    }
+                }
+                if failed.is_some() {
+                    // A concurrent request filled this dimension after the
+                    // read: give back what the earlier ones and the global
+                    // limit counted, and leave the rest.
+                    self.release_global(1);
+                    let index = decisions.len() - 1;
+                    for ((policy, key), claimed) in policies.iter().zip(&keys).zip(&mut decisions[..index]) {
+                        let count = self.cache.decr(key, 1).unwrap_or_else(|err| {
+                            self.backend_error(&err);
+                            claimed.count
+                        });
+                        *claimed = decision(policy, count, false);
+                    }
+                    for (index, count) in counts.iter().enumerate().skip(index + 1) {
+                        decisions.push(blocked_decision(index, count.unwrap_or(0)));
+                    }
+                }
+                decisions
+            }
+        };
+
+        let allowed = failed.is_none();
+        if let Some(first) = policies.first() {
+            // Report the dimension that blocked the request, or the client's.
+            let reported = failed
+                .and_then(|name| policies.iter().position(|policy| policy.name == name))
+                .unwrap_or(0);
+            let version = self.policy_version();
+            self.report(first.key, &self.client_key(first.key), 1, version, started, &decisions[reported]);
+        }
+        if invalid_key {
+            for decision in &mut decisions {
+                decision.invalid_key = true;
+            }
+        }
+        CompositeDecision {
+            allowed,
+            failed: failed.map(str::to_string),
+            decisions,
+        }
+    }
+}
+
+fn decision(policy: &KeyedPolicy<'_>, count: u32, allowed: bool) -> RateLimitDecision {
+    RateLimitDecision {
+        allowed,
+        count,
+        limit: policy.limit,
+        remaining: policy.limit.saturating_sub(count),
+        priority: Priority::Normal,
+        borrowed: false,
+        retry_after: None,
+        warning: false,
+        global_limited: false,
+        invalid_key: false,
+        checked_at: Instant::now(),
+    }
+}
@@ -0,0 +1,33 @@
+use crate::limiter::{CacheBackend, RateLimitDecision, RateLimiter};
+
+/// Computes how many units of quota a request consumes, e.g. from its body
+/// size, query complexity or GraphQL depth.
+///
+/// Any `Fn(&R) -> u32` is a cost extractor, so middleware can take a closure
+/// over its framework's request type.
+pub trait CostExtractor<R: ?Sized>: Send + Sync {
+    /// Returns the units `request` consumes.
+    fn cost(&self, request: &R) -> u32;
+}
+
+impl<R: ?Sized, F> CostExtractor<R> for F
+where
+    F: Fn(&R) -> u32 + Send + Sync,
+{
+    fn cost(&self, request: &R) -> u32 {
+        self(request)
+    }
+}
+
+impl<B: CacheBackend> RateLimiter<B> {
+    /// Checks `request` from `client`, consuming the number of units
+    /// `extractor` assigns to it. See `check_n`.
+    pub fn check_request<R: ?Sized>(
+        &self,
+        client: &str,
+        request: &R,
+        extractor: &impl CostExtractor<R>,
+    ) -> RateLimitDecision {
+        self.check_n(client, extractor.cost(request))
+    }
+}
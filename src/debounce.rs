@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use std::time::Duration;
+use crate::hashing::KeyHasher;
+use crate::key::Key;
+use crate::limiter::CacheBackend;
+
+/// Answers "has this exact key been seen within the last `window`?", for
+/// idempotency checks and webhook duplicate suppression.
+///
+/// Built on `CacheBackend::incr`, which creates a key together with its TTL
+/// atomically, so exactly one of several concurrent callers sees a key as
+/// new, including across hosts sharing a backend.
+pub struct Debouncer<B: CacheBackend> {
+    /// The caching backend instance.
+    pub cache: Arc<B>,
+    /// How long a key is remembered after it is first seen.
+    pub window: Duration,
+    hasher: Option<KeyHasher>,
+}
+
+impl<B: CacheBackend> Debouncer<B> {
+    /// Constructs a new Debouncer.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - A caching backend instance wrapped in `Arc`.
+    /// * `window` - How long a key is remembered after it is first seen.
+    pub fn new(cache: Arc<B>, window: Duration) -> Self {
+        Debouncer {
+            cache,
+            window,
+            hasher: None,
+        }
+    }
+
+    /// Hashes keys with `hasher` before they reach the backend. See
+    /// `RateLimiter::with_key_hasher`.
+    pub fn with_key_hasher(mut self, hasher: KeyHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Records `key` and returns `true` if it had not been seen within the
+    /// window, or `false` if this is a duplicate.
+    ///
+    /// # Errors
+    ///
+    /// Returns the backend error if the key could not be recorded.
+    pub fn first_seen(&self, key: &str) -> Result<bool, String> {
+        Ok(self.cache.incr(&self.key(key), 1, self.window)? == 1)
+    }
+
+    /// Returns `true` if `key` was seen within the window, without recording it.
+    pub fn seen(&self, key: &str) -> bool {
+        self.cache.get(&self.key(key)).is_some()
+    }
+
+    fn key(&self, key: &str) -> Key {
+        match &self.hasher {
+            Some(hasher) => Key::new(&["debounce:", &hasher.hash_hex(key)]),
+            None => Key::new(&["debounce:", key]),
+        }
+    }
+}
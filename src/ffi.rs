@@ -0,0 +1,192 @@
+//! C ABI for using the limiter from other languages, e.g. sidecars or
+//! nginx/OpenResty modules. Declarations are in `include/arl.h`.
+//!
+//! Limiters created here count in an `InMemoryCache` owned by the limiter.
+//! Strings are NUL-terminated UTF-8. Functions never unwind into the caller:
+//! a panic is reported like a backend error, as a blocked decision.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, CStr};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, RwLock};
+use crate::cache::in_memory::InMemoryCache;
+use crate::composite::KeyedPolicy;
+use crate::limiter::{RateLimitDecision, RateLimiter};
+use crate::rate::Rate;
+
+/// A limiter with named policies, opaque to C.
+pub struct ArlLimiter {
+    limiter: RateLimiter<InMemoryCache>,
+    policies: RwLock<HashMap<String, Rate>>,
+}
+
+/// A decision as seen from C.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArlDecision {
+    /// Whether the request may proceed.
+    pub allowed: bool,
+    /// Requests counted in the current window.
+    pub count: u32,
+    /// The limit applied.
+    pub limit: u32,
+    /// Requests left in the window.
+    pub remaining: u32,
+    /// For blocked requests, milliseconds until the window resets, or 0 if
+    /// unknown.
+    pub retry_after_ms: u64,
+}
+
+impl From<RateLimitDecision> for ArlDecision {
+    fn from(decision: RateLimitDecision) -> Self {
+        ArlDecision {
+            allowed: decision.allowed,
+            count: decision.count,
+            limit: decision.limit,
+            remaining: decision.remaining,
+            retry_after_ms: decision
+                .retry_after
+                .map_or(0, |retry_after| retry_after.as_millis().min(u128::from(u64::MAX)) as u64),
+        }
+    }
+}
+
+const BLOCKED: ArlDecision = ArlDecision {
+    allowed: false,
+    count: 0,
+    limit: 0,
+    remaining: 0,
+    retry_after_ms: 0,
+};
+
+/// Reads a C string, or `None` if it is null or not UTF-8.
+///
+/// # Safety
+///
+/// `value` must be null or point to a NUL-terminated string.
+unsafe fn read_str<'a>(value: *const c_char) -> Option<&'a str> {
+    if value.is_null() {
+        return None;
+    }
+    CStr::from_ptr(value).to_str().ok()
+}
+
+/// Creates a limiter allowing `rate`, such as `"100/5m"` (see `Rate`).
+/// Returns null if the rate does not parse. Free it with `arl_limiter_free`.
+///
+/// # Safety
+///
+/// `rate` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn arl_limiter_new(rate: *const c_char) -> *mut ArlLimiter {
+    let Some(rate) = read_str(rate).and_then(|rate| rate.parse::<Rate>().ok()) else {
+        return std::ptr::null_mut();
+    };
+    Box::into_raw(Box::new(ArlLimiter {
+        limiter: RateLimiter::from_rate(Arc::new(InMemoryCache::new()), rate),
+        policies: RwLock::new(HashMap::new()),
+    }))
+}
+
+/// Frees a limiter. Null is ignored.
+///
+/// # Safety
+///
+/// `limiter` must be null or come from `arl_limiter_new`, and must not be
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn arl_limiter_free(limiter: *mut ArlLimiter) {
+    if !limiter.is_null() {
+        drop(Box::from_raw(limiter));
+    }
+}
+
+/// Adds or replaces the policy `name` allowing `rate`, counted separately
+/// from other policies. Returns 0 on success, or -1 if an argument is null
+/// or invalid.
+///
+/// # Safety
+///
+/// `limiter` must be null or a live limiter; `name` and `rate` must be null
+/// or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn arl_add_policy(
+    limiter: *const ArlLimiter,
+    name: *const c_char,
+    rate: *const c_char,
+) -> c_int {
+    let (Some(limiter), Some(name), Some(rate)) = (limiter.as_ref(), read_str(name), read_str(rate)) else {
+        return -1;
+    };
+    let Ok(rate) = rate.parse::<Rate>() else {
+        return -1;
+    };
+    match limiter.policies.write() {
+        Ok(mut policies) => {
+            policies.insert(name.to_string(), rate);
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Checks a request from `key` against the policy named `policy`, or
+/// against the limiter's own rate if `policy` is null. Unknown policies,
+/// null or non-UTF-8 keys and null limiters are blocked.
+///
+/// # Safety
+///
+/// `limiter` must be null or a live limiter; `key` and `policy` must be
+/// null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn arl_check(
+    limiter: *const ArlLimiter,
+    key: *const c_char,
+    policy: *const c_char,
+) -> ArlDecision {
+    let (Some(limiter), Some(key)) = (limiter.as_ref(), read_str(key)) else {
+        return BLOCKED;
+    };
+    let policy = if policy.is_null() {
+        None
+    } else {
+        match read_str(policy) {
+            Some(policy) => Some(policy),
+            None => return BLOCKED,
+        }
+    };
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let Some(name) = policy else {
+            return limiter.limiter.check(key).into();
+        };
+        let rate = match limiter.policies.read() {
+            Ok(policies) => policies.get(name).copied(),
+            Err(_) => None,
+        };
+        let Some(rate) = rate else {
+            return BLOCKED;
+        };
+        let mut decision = limiter.limiter.check_all(&[KeyedPolicy::new(name, key, rate)]);
+        decision.decisions.remove(0).into()
+    }))
+    .unwrap_or(BLOCKED)
+}
+
+/// Gives `amount` units back to `key` under the limiter's own rate, e.g.
+/// for a request that turned out not to count. Returns the new count, or -1
+/// on error.
+///
+/// # Safety
+///
+/// `limiter` must be null or a live limiter; `key` must be null or point
+/// to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn arl_refund(limiter: *const ArlLimiter, key: *const c_char, amount: u32) -> i64 {
+    let (Some(limiter), Some(key)) = (limiter.as_ref(), read_str(key)) else {
+        return -1;
+    };
+    panic::catch_unwind(AssertUnwindSafe(|| limiter.limiter.refund(key, amount)))
+        .ok()
+        .and_then(Result::ok)
+        .map_or(-1, i64::from)
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::hashing::KeyHasher;
+use crate::rate::Rate;
+use crate::state::{update_state, State, StateBackend};
+
+/// GCRA state: the theoretical arrival time of the next request, in
+/// nanoseconds since the Unix epoch. Wall-clock time is used so the state can
+/// be shared between hosts.
+struct Tat(u64);
+
+impl State for Tat {
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(Tat(u64::from_be_bytes(bytes.try_into().ok()?)))
+    }
+}
+
+/// A rate limiter using the Generic Cell Rate Algorithm.
+///
+/// GCRA spaces requests evenly: `limit` requests per `period` are allowed, in
+/// bursts of up to `limit`, and capacity is regained continuously rather than
+/// all at once when a window ends. State is kept in a `StateBackend`, so
+/// limits are enforced across every host sharing it.
+pub struct GcraLimiter<B: StateBackend> {
+    /// The state backend instance.
+    pub backend: Arc<B>,
+    /// Maximum burst, and number of requests regained per `period`.
+    pub limit: u32,
+    /// Time over which `limit` requests are regained.
+    pub period: Duration,
+    hasher: Option<KeyHasher>,
+}
+
+impl<B: StateBackend> GcraLimiter<B> {
+    /// Constructs a new GcraLimiter.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - A state backend instance wrapped in `Arc`.
+    /// * `limit` - Maximum burst size and requests per `period`.
+    /// * `period` - Time over which `limit` requests are regained.
+    pub fn new(backend: Arc<B>, limit: u32, period: Duration) -> Self {
+        GcraLimiter {
+            backend,
+            limit,
+            period,
+            hasher: None,
+        }
+    }
+
+    /// Constructs a new GcraLimiter regaining `rate`, which is also the
+    /// maximum burst.
+    pub fn from_rate(backend: Arc<B>, rate: Rate) -> Self {
+        GcraLimiter::new(backend, rate.limit, rate.window)
+    }
+
+    /// Hashes client keys with `hasher` before they reach the backend. See
+    /// `RateLimiter::with_key_hasher`.
+    pub fn with_key_hasher(mut self, hasher: KeyHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Checks whether a request from the given IP is allowed.
+    ///
+    /// Returns `false` if the limit is exceeded or the backend fails.
+    pub fn allow(&self, ip: &str) -> bool {
+        if self.limit == 0 {
+            return false;
+        }
+        let key = match &self.hasher {
+            Some(hasher) => format!("rate_limit_gcra:{}", hasher.hash_hex(ip)),
+            None => format!("rate_limit_gcra:{}", ip),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0);
+        let period = self.period.as_nanos() as u64;
+        let interval = period / u64::from(self.limit);
+
+        update_state(self.backend.as_ref(), &key, |state: Option<Tat>| {
+            let tat = state.map_or(now, |Tat(tat)| tat.max(now));
+            let new_tat = tat + interval;
+            if new_tat - now > period {
+                // Keep the existing state; the request is rejected.
+                (Tat(tat), Duration::from_nanos(tat - now), false)
+            } else {
+                (Tat(new_tat), Duration::from_nanos(new_tat - now), true)
+            }
+        })
+        .unwrap_or(false)
+    }
+}
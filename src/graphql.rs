@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+use crate::cost::CostExtractor;
+
+/// Estimates the cost of a GraphQL query from its text, so GraphQL APIs can
+/// enforce cost-based quotas with `check_request` or `allow_n`.
+///
+/// Each field costs one unit plus the cost of its selection, multiplied by
+/// the page size of list fields: a field with a `first`, `last` or `limit`
+/// integer argument multiplies its selection by that number. Page sizes
+/// given as variables count as `default_list_size`. Named fragments are
+/// expanded, each costed once. For a document with several operations, the
+/// most expensive one is charged. Queries nested more than 64 levels deep
+/// and fragments that spread themselves are rejected.
+///
+/// As a `CostExtractor<str>`, a query that does not parse costs one unit;
+/// the server rejects it anyway.
+#[derive(Debug, Clone)]
+pub struct QueryComplexity {
+    list_arguments: Vec<String>,
+    default_list_size: u32,
+}
+
+impl QueryComplexity {
+    /// Creates an estimator treating `first`, `last` and `limit` as page
+    /// sizes, with variable page sizes counted as 10.
+    pub fn new() -> Self {
+        QueryComplexity {
+            list_arguments: ["first", "last", "limit"].map(String::from).to_vec(),
+            default_list_size: 10,
+        }
+    }
+
+    /// Sets the argument names that give a list field's page size.
+    pub fn with_list_arguments(mut self, names: &[&str]) -> Self {
+        self.list_arguments = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Sets the page size assumed when it is given as a variable.
+    pub fn with_default_list_size(mut self, size: u32) -> Self {
+        self.default_list_size = size;
+        self
+    }
+
+    /// Returns the estimated cost of `query`, or an error if it does not parse.
+    pub fn complexity(&self, query: &str) -> Result<u32, String> {
+        let tokens = tokenize(query)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            depth: 0,
+            complexity: self,
+        };
+        let mut operations = Vec::new();
+        let mut fragments = HashMap::new();
+        while parser.pos < tokens.len() {
+            match parser.next()? {
+                Token::Punct('{') => {
+                    parser.pos -= 1;
+                    operations.push(parser.selection_set()?);
+                }
+                Token::Name("fragment") => {
+                    let name = parser.name()?;
+                    parser.expect_name("on")?;
+                    parser.name()?;
+                    parser.directives()?;
+                    fragments.insert(name, parser.selection_set()?);
+                }
+                Token::Name("query" | "mutation" | "subscription") => {
+                    if let Some(Token::Name(_)) = parser.peek() {
+                        parser.pos += 1;
+                    }
+                    if parser.peek() == Some(&Token::Punct('(')) {
+                        parser.skip_group()?;
+                    }
+                    parser.directives()?;
+                    operations.push(parser.selection_set()?);
+                }
+                token => return Err(format!("unexpected {:?}", token)),
+            }
+        }
+        let mut costing = Costing {
+            fragments: &fragments,
+            costs: HashMap::new(),
+            expanding: Vec::new(),
+        };
+        let mut max = None;
+        for operation in &operations {
+            let cost = costing.cost(operation)?;
+            max = max.max(Some(cost));
+        }
+        max.ok_or_else(|| "no operation in query".to_string())
+    }
+}
+
+impl Default for QueryComplexity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CostExtractor<str> for QueryComplexity {
+    fn cost(&self, query: &str) -> u32 {
+        self.complexity(query).unwrap_or(1)
+    }
+}
+
+/// Selection sets, argument values and fragment spreads nested deeper than
+/// this are rejected, bounding recursion on hostile queries.
+const MAX_DEPTH: usize = 64;
+
+enum Selection<'a> {
+    Field { multiplier: u32, children: Vec<Selection<'a>> },
+    Spread(&'a str),
+    Inline(Vec<Selection<'a>>),
+}
+
+/// Costs selections, expanding each named fragment once and reusing its
+/// cost wherever it is spread again.
+struct Costing<'f, 'a> {
+    fragments: &'f HashMap<&'a str, Vec<Selection<'a>>>,
+    costs: HashMap<&'a str, u32>,
+    /// Fragments being expanded, outermost first.
+    expanding: Vec<&'a str>,
+}
+
+impl<'a> Costing<'_, 'a> {
+    fn cost(&mut self, selections: &[Selection<'a>]) -> Result<u32, String> {
+        let mut total = 0u32;
+        for selection in selections {
+            let cost = match selection {
+                Selection::Field { multiplier, children } => {
+                    1u32.saturating_add(multiplier.saturating_mul(self.cost(children)?))
+                }
+                Selection::Inline(children) => self.cost(children)?,
+                Selection::Spread(name) => self.spread(name)?,
+            };
+            total = total.saturating_add(cost);
+        }
+        Ok(total)
+    }
+
+    fn spread(&mut self, name: &'a str) -> Result<u32, String> {
+        if let Some(&cost) = self.costs.get(name) {
+            return Ok(cost);
+        }
+        if self.expanding.contains(&name) {
+            return Err(format!("fragment `{}` spreads itself", name));
+        }
+        if self.expanding.len() >= MAX_DEPTH {
+            return Err("fragments nested too deeply".to_string());
+        }
+        let fragments = self.fragments;
+        let Some(fragment) = fragments.get(name) else {
+            return Ok(0);
+        };
+        self.expanding.push(name);
+        let cost = self.cost(fragment);
+        self.expanding.pop();
+        let cost = cost?;
+        self.costs.insert(name, cost);
+        Ok(cost)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    Name(&'a str),
+    Int(u32),
+    /// A string, float or other value that never sets a page size.
+    Other,
+    Spread,
+    Punct(char),
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token<'_>>, String> {
+    let bytes = query.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b' ' | b'\t' | b'\r' | b'\n' | b',' => i += 1,
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'"' if query[i..].starts_with("\"\"\"") => {
+                let end = query[i + 3..].find("\"\"\"").ok_or("unterminated block string")?;
+                i += end + 6;
+                tokens.push(Token::Other);
+            }
+            b'"' => {
+                i += 1;
+                loop {
+                    match bytes.get(i) {
+                        None | Some(b'\n') => return Err("unterminated string".to_string()),
+                        Some(b'\\') => i += 2,
+                        Some(b'"') => break,
+                        Some(_) => i += 1,
+                    }
+                }
+                i += 1;
+                tokens.push(Token::Other);
+            }
+            b'.' if query[i..].starts_with("...") => {
+                i += 3;
+                tokens.push(Token::Spread);
+            }
+            b'-' | b'0'..=b'9' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || matches!(bytes[i], b'.' | b'+' | b'-')) {
+                    i += 1;
+                }
+                tokens.push(query[start..i].parse().map_or(Token::Other, Token::Int));
+            }
+            b'_' | b'a'..=b'z' | b'A'..=b'Z' => {
+                let start = i;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                tokens.push(Token::Name(&query[start..i]));
+            }
+            b'{' | b'}' | b'(' | b')' | b'[' | b']' | b':' | b'$' | b'@' | b'!' | b'=' | b'|' | b'&' => {
+                i += 1;
+                tokens.push(Token::Punct(c as char));
+            }
+            _ => return Err(format!("unexpected character at {}", i)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'t, 'a> {
+    tokens: &'t [Token<'a>],
+    pos: usize,
+    /// Selection sets currently open.
+    depth: usize,
+    complexity: &'t QueryComplexity,
+}
+
+impl<'a> Parser<'_, 'a> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, String> {
+        let token = self.peek().cloned().ok_or("unexpected end of query")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn name(&mut self) -> Result<&'a str, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(format!("expected a name, found {:?}", token)),
+        }
+    }
+
+    fn expect_name(&mut self, expected: &str) -> Result<(), String> {
+        match self.name()? {
+            name if name == expected => Ok(()),
+            name => Err(format!("expected `{}`, found `{}`", expected, name)),
+        }
+    }
+
+    /// Skips a balanced `(...)`, `[...]` or `{...}` group starting at the
+    /// current token.
+    fn skip_group(&mut self) -> Result<(), String> {
+        let mut depth = 0usize;
+        loop {
+            match self.next()? {
+                Token::Punct('(' | '[' | '{') if depth == MAX_DEPTH => {
+                    return Err("query nested too deeply".to_string());
+                }
+                Token::Punct('(' | '[' | '{') => depth += 1,
+                Token::Punct(')' | ']' | '}') => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn directives(&mut self) -> Result<(), String> {
+        while self.peek() == Some(&Token::Punct('@')) {
+            self.pos += 1;
+            self.name()?;
+            if self.peek() == Some(&Token::Punct('(')) {
+                self.skip_group()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses field arguments and returns the field's page size.
+    fn arguments(&mut self) -> Result<u32, String> {
+        let mut multiplier = 1;
+        self.pos += 1;
+        while self.peek() != Some(&Token::Punct(')')) {
+            let name = self.name()?;
+            match self.next()? {
+                Token::Punct(':') => {}
+                token => return Err(format!("expected `:`, found {:?}", token)),
+            }
+            let is_list = self.complexity.list_arguments.iter().any(|list| list == name);
+            match self.next()? {
+                Token::Int(size) if is_list => multiplier = size,
+                Token::Punct('$') => {
+                    self.name()?;
+                    if is_list {
+                        multiplier = self.complexity.default_list_size;
+                    }
+                }
+                Token::Punct('[' | '{') => {
+                    self.pos -= 1;
+                    self.skip_group()?;
+                }
+                _ => {}
+            }
+        }
+        self.pos += 1;
+        Ok(multiplier)
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Selection<'a>>, String> {
+        match self.next()? {
+            Token::Punct('{') => {}
+            token => return Err(format!("expected `{{`, found {:?}", token)),
+        }
+        if self.depth == MAX_DEPTH {
+            return Err("query nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let selections = self.selections();
+        self.depth -= 1;
+        selections
+    }
+
+    fn selections(&mut self) -> Result<Vec<Selection<'a>>, String> {
+        let mut selections = Vec::new();
+        loop {
+            match self.next()? {
+                Token::Punct('}') => return Ok(selections),
+                Token::Spread => match self.peek() {
+                    Some(Token::Name("on")) => {
+                        self.pos += 1;
+                        self.name()?;
+                        self.directives()?;
+                        selections.push(Selection::Inline(self.selection_set()?));
+                    }
+                    Some(Token::Name(name)) => {
+                        selections.push(Selection::Spread(name));
+                        self.pos += 1;
+                        self.directives()?;
+                    }
+                    _ => {
+                        self.directives()?;
+                        selections.push(Selection::Inline(self.selection_set()?));
+                    }
+                },
+                Token::Name(_) => {
+                    // Skip an alias.
+                    if self.peek() == Some(&Token::Punct(':')) {
+                        self.pos += 1;
+                        self.name()?;
+                    }
+                    let multiplier = if self.peek() == Some(&Token::Punct('(')) {
+                        self.arguments()?
+                    } else {
+                        1
+                    };
+                    self.directives()?;
+                    let children = if self.peek() == Some(&Token::Punct('{')) {
+                        self.selection_set()?
+                    } else {
+                        Vec::new()
+                    };
+                    selections.push(Selection::Field { multiplier, children });
+                }
+                token => return Err(format!("unexpected {:?}", token)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryComplexity;
+
+    #[test]
+    fn test_query_complexity() {
+        let complexity = QueryComplexity::new();
+        assert_eq!(complexity.complexity("{ viewer { login name } }"), Ok(3));
+
+        // 1 (repositories) + 5 * (1 (name) + 1 (issues) + 10 * 1 (title)) = 61
+        let query = r#"
+            query Repos($n: Int) {
+                repositories(first: 5, orderBy: {field: NAME}) {
+                    name
+                    issues(first: $n, labels: ["bug"]) { title }
+                }
+            }
+        "#;
+        assert_eq!(complexity.complexity(query), Ok(61));
+
+        let query = "
+            query { user(id: \"1\") { ...Fields ... on Admin @include(if: true) { role } } }
+            fragment Fields on User { name email }
+        ";
+        assert_eq!(complexity.complexity(query), Ok(4));
+
+        assert!(complexity.complexity("{ user(").is_err());
+    }
+
+    #[test]
+    fn test_query_complexity_rejects_hostile_queries() {
+        let complexity = QueryComplexity::new();
+
+        // Deep nesting is rejected instead of overflowing the stack.
+        let deep = format!("{}{}", "{ a ".repeat(200_000), "}".repeat(200_000));
+        assert!(complexity.complexity(&deep).is_err());
+        let deep = format!("{{ a(where: {}) }}", "[".repeat(200_000));
+        assert!(complexity.complexity(&deep).is_err());
+
+        // Cyclic fragments are rejected.
+        assert!(complexity.complexity("{ ...A } fragment A on Q { a ...A ...A }").is_err());
+        assert!(complexity.complexity("{ ...A } fragment A on Q { ...B } fragment B on Q { a ...A }").is_err());
+
+        // Fragments spread twice at every level are costed once each, so
+        // this finishes immediately and saturates instead of taking 2^40 steps.
+        let mut query = String::from("{ ...F0 }");
+        for i in 0..40 {
+            query.push_str(&format!(" fragment F{} on Q {{ a ...F{} ...F{} }}", i, i + 1, i + 1));
+        }
+        query.push_str(" fragment F40 on Q { a }");
+        assert_eq!(complexity.complexity(&query), Ok(u32::MAX));
+    }
+}
@@ -0,0 +1,29 @@
+/// Maps keys to quota groups, so that several keys (e.g. all API keys of one
+/// organization) draw from a single shared counter.
+pub trait GroupResolver: Send + Sync {
+    /// Returns the group `key` belongs to, or `None` if it has its own quota.
+    fn group(&self, key: &str) -> Option<String>;
+}
+
+impl<F> GroupResolver for F
+where
+    F: Fn(&str) -> Option<String> + Send + Sync,
+{
+    fn group(&self, key: &str) -> Option<String> {
+        self(key)
+    }
+}
+
+/// How a group's shared quota is divided among its member keys, so one
+/// noisy key cannot starve the rest of its group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FairShare {
+    /// Caps each member key at this percentage of the group's limit per
+    /// window, even when the rest of the group is idle.
+    MaxShare(u32),
+    /// Caps each member key at an equal split of the group's limit among
+    /// the members active in the current window. A member arriving late
+    /// lowers everyone's cap; members already past the new cap are blocked
+    /// until their window ends, leaving the rest of the quota to the others.
+    Even,
+}
@@ -0,0 +1,124 @@
+use std::fmt::Write;
+
+/// Hashes client keys before they are written to a backend, so raw IP
+/// addresses and API keys are never stored.
+///
+/// Keys are hashed with SipHash-2-4 into 128 bits (two 64-bit hashes under
+/// different keys) and stored as 32 hex characters. Without a secret, the
+/// same key always hashes the same way, but low-entropy inputs such as IPv4
+/// addresses can be recovered by brute force; set a secret shared by every
+/// instance to prevent that.
+#[derive(Clone)]
+pub struct KeyHasher {
+    k0: u64,
+    k1: u64,
+}
+
+impl KeyHasher {
+    /// Creates a hasher without a secret.
+    pub fn new() -> Self {
+        KeyHasher { k0: 0, k1: 0 }
+    }
+
+    /// Creates a hasher keyed by `secret`. Every instance sharing a backend
+    /// must use the same secret.
+    pub fn with_secret(secret: &[u8]) -> Self {
+        KeyHasher {
+            k0: siphash(0, 0, secret),
+            k1: siphash(0, 1, secret),
+        }
+    }
+
+    /// Returns a 64-bit hash of `key`.
+    pub fn hash(&self, key: &str) -> u64 {
+        siphash(self.k0, self.k1, key.as_bytes())
+    }
+
+    /// Returns the hex-encoded 128-bit hash stored in place of `key`.
+    pub fn hash_hex(&self, key: &str) -> String {
+        let high = siphash(self.k0, self.k1, key.as_bytes());
+        let low = siphash(self.k1, self.k0 ^ 0xee, key.as_bytes());
+        let mut hex = String::with_capacity(32);
+        let _ = write!(hex, "{:016x}{:016x}", high, low);
+        hex
+    }
+}
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SipHash-2-4 of `data` under the key `(k0, k1)`.
+fn siphash(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap_or_default());
+        v[3] ^= m;
+        sip_round(&mut v);
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+    let mut last = [0u8; 8];
+    let tail = chunks.remainder();
+    last[..tail.len()].copy_from_slice(tail);
+    last[7] = data.len() as u8;
+    let m = u64::from_le_bytes(last);
+    v[3] ^= m;
+    sip_round(&mut v);
+    sip_round(&mut v);
+    v[0] ^= m;
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13);
+    v[1] ^= v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16);
+    v[3] ^= v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21);
+    v[3] ^= v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17);
+    v[1] ^= v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{siphash, KeyHasher};
+
+    #[test]
+    fn test_siphash_reference_vector() {
+        // Test vector from the SipHash paper: key 00..0f, message 00..0e.
+        let message: Vec<u8> = (0..15).collect();
+        assert_eq!(
+            siphash(0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908, &message),
+            0xa129_ca61_49be_45e5
+        );
+    }
+
+    #[test]
+    fn test_secret_changes_hash() {
+        let plain = KeyHasher::new().hash_hex("10.0.0.1");
+        assert_eq!(plain.len(), 32);
+        assert_eq!(plain, KeyHasher::new().hash_hex("10.0.0.1"));
+        assert_ne!(plain, KeyHasher::with_secret(b"s3cret").hash_hex("10.0.0.1"));
+    }
+}
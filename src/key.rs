@@ -0,0 +1,66 @@
+use std::fmt;
+use std::ops::Deref;
+
+/// Keys up to this length are built on the stack.
+const INLINE_CAPACITY: usize = 96;
+
+/// A backend key built from a prefix and a client identifier.
+///
+/// Short keys (the common case for IPs and API keys) live in an inline
+/// buffer, so building a key on the hot path does not allocate.
+pub(crate) enum Key {
+    Inline { buf: [u8; INLINE_CAPACITY], len: usize },
+    Heap(String),
+}
+
+impl Key {
+    /// Builds a key by concatenating `parts`.
+    pub(crate) fn new(parts: &[&str]) -> Key {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if len > INLINE_CAPACITY {
+            return Key::Heap(parts.concat());
+        }
+        let mut buf = [0u8; INLINE_CAPACITY];
+        let mut at = 0;
+        for part in parts {
+            buf[at..at + part.len()].copy_from_slice(part.as_bytes());
+            at += part.len();
+        }
+        Key::Inline { buf, len }
+    }
+}
+
+impl Deref for Key {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            // The buffer only ever holds whole `&str` parts, so it is valid UTF-8.
+            Key::Inline { buf, len } => std::str::from_utf8(&buf[..*len]).unwrap_or_default(),
+            Key::Heap(key) => key,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Key;
+
+    #[test]
+    fn test_inline_and_heap_keys() {
+        let key = Key::new(&["rate_limit:", "127.0.0.1"]);
+        assert!(matches!(key, Key::Inline { .. }));
+        assert_eq!(&*key, "rate_limit:127.0.0.1");
+
+        let long = "x".repeat(200);
+        let key = Key::new(&["rate_limit:", &long]);
+        assert!(matches!(key, Key::Heap(_)));
+        assert_eq!(key.len(), 211);
+    }
+}
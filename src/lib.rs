@@ -1,2 +1,38 @@
 pub mod limiter;
-pub mod cache; 
\ No newline at end of file
+pub mod cache; 
+pub mod stats;
+pub mod adaptive;
+pub mod priority;
+pub mod clock;
+pub mod schedule;
+pub mod state;
+pub mod gcra;
+mod key;
+pub mod composite;
+pub mod group;
+pub mod debounce;
+pub mod series;
+pub mod rollout;
+pub mod audit;
+pub mod canary;
+pub mod hashing;
+pub mod route;
+pub mod cost;
+pub mod response;
+pub mod reservation;
+pub mod telemetry;
+pub mod logging;
+pub mod graphql;
+pub mod pacer;
+pub mod simulate;
+pub mod recording;
+pub mod anomaly;
+pub mod upstream;
+pub mod calendar;
+pub mod quota;
+pub mod rate;
+pub mod validation;
+pub mod plan;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod lockfree;
@@ -1,5 +1,28 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use crate::audit::{hash_key, AuditRecord, AuditSink};
+use crate::calendar::CalendarWindows;
+use crate::cache::in_memory::Sweeper;
+use crate::cache::pool::PoolStats;
+use crate::clock::Clock;
+use crate::group::{FairShare, GroupResolver};
+use crate::hashing::KeyHasher;
+use crate::key::Key;
+use crate::lockfree::LockFreeGcra;
+use crate::logging::DecisionLogger;
+use crate::priority::{Priority, PriorityReserves};
+use crate::reservation::PendingReservations;
+use crate::rate::Rate;
+use crate::rollout::{PolicyState, Resolved, Rollout};
+use crate::schedule::Schedule;
+use crate::series::{UsagePoint, UsageRecorder};
+use crate::stats::{LimiterStats, StatsCollector, DEFAULT_HOT_KEY_CAPACITY};
+use crate::telemetry::{DecisionEvent, DecisionObserver};
+use crate::validation::{well_formed, InvalidKeyPolicy, KeyValidator};
+
+/// Backend key of the service-wide counter set by `with_global_limit`.
+const GLOBAL_KEY: &str = "rate_limit_global";
 
 /// Trait to abstract any caching backend.
 /// This allows you to use Redis, in-memory caches, or any other backend.
@@ -11,7 +34,211 @@ pub trait CacheBackend: Send + Sync {
     fn set(&self, key: &str, value: u32, ttl: Duration) -> Result<(), String>;
 
     /// Increments the count for the given key by `amount` and returns the new count.
-    fn incr(&self, key: &str, amount: u32) -> Result<u32, String>;
+    ///
+    /// If the key is absent or expired, it is created with a count of `amount`
+    /// and the given TTL in the same atomic operation, so a new window can
+    /// never be observed without its expiry (on Redis, `INCRBY` followed by
+    /// `EXPIRE ... NX` in a `MULTI` block or a Lua script).
+    fn incr(&self, key: &str, amount: u32, ttl_if_new: Duration) -> Result<u32, String>;
+
+    /// Decrements the count for `key` by `amount`, floored at zero, keeping
+    /// its TTL, and returns the new count. An absent or expired key is left
+    /// absent and reported as zero.
+    ///
+    /// Backends that cannot perform this atomically return an error, which is
+    /// the default.
+    fn decr(&self, _key: &str, _amount: u32) -> Result<u32, String> {
+        Err("decr is not supported by this backend".to_string())
+    }
+
+    /// Retrieves the current counts for several keys at once.
+    ///
+    /// The default implementation calls `get` for each key. Network backends
+    /// should override it to fetch all keys in one round trip (e.g. `MGET`).
+    fn get_many(&self, keys: &[&str]) -> Vec<Option<u32>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Increments the count for `key` by `amount` unless that would take it
+    /// past `ceiling`, starting a new window with the given TTL if the key is
+    /// absent or expired. Returns whether the increment was applied, together
+    /// with the resulting count.
+    ///
+    /// The default implementation rejects without counting when `get` shows
+    /// the key is already too close to `ceiling`. Otherwise it increments and
+    /// takes the increment back with `decr` if a concurrent caller took it
+    /// past `ceiling`, so callers can never be allowed past it together.
+    /// Without `decr`, only increments rejected in such a race stay counted
+    /// until the window ends. Backends should override it with a single
+    /// atomic operation (e.g. a Lua script on Redis), which is what the
+    /// limiter uses on its hot path.
+    fn incr_within(
+        &self,
+        key: &str,
+        amount: u32,
+        ceiling: u32,
+        ttl: Duration,
+    ) -> Result<(bool, u32), String> {
+        if let Some(count) = self.get(key) {
+            if count.saturating_add(amount) > ceiling {
+                return Ok((false, count));
+            }
+        }
+        let count = self.incr(key, amount, ttl)?;
+        if count > ceiling {
+            let _ = self.decr(key, amount);
+            return Ok((false, count - amount));
+        }
+        Ok((true, count))
+    }
+
+    /// Atomically sets the count for `key` to `new` with the given TTL, but
+    /// only if the current count equals `old` (`None` meaning the key is absent
+    /// or expired). Returns `Ok(true)` if the swap happened.
+    ///
+    /// Backends that cannot perform this atomically return an error, which is
+    /// the default.
+    fn compare_and_swap(
+        &self,
+        _key: &str,
+        _old: Option<u32>,
+        _new: u32,
+        _ttl: Duration,
+    ) -> Result<bool, String> {
+        Err("compare_and_swap is not supported by this backend".to_string())
+    }
+
+    /// Returns the remaining time-to-live for the given key, if it exists and
+    /// the backend can report it.
+    fn ttl(&self, _key: &str) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the number of keys currently stored, if the backend can report it.
+    fn key_count(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the state of the backend's connection pool, if it uses one.
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    /// Checks that the backend is reachable with one round trip.
+    ///
+    /// The default writes a short-lived probe key. Network backends should
+    /// override it with a cheaper command (e.g. `PING` on Redis).
+    fn ping(&self) -> Result<(), String> {
+        self.set("rate_limit_health", 1, Duration::from_secs(1))
+    }
+
+    /// Writes any locally buffered updates through to shared storage.
+    /// Backends that write through on every call need not override it.
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Current usage of a single key, suitable for "your API usage" displays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyUsage {
+    /// Requests counted in the current window.
+    pub count: u32,
+    /// Maximum allowed requests per window.
+    pub limit: u32,
+    /// Requests left before the key is blocked.
+    pub remaining: u32,
+    /// Time until the current window ends, if a window is active and the
+    /// backend can report it.
+    pub resets_in: Option<Duration>,
+}
+
+/// The result of `RateLimiter::health_check`, for liveness and readiness
+/// endpoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthStatus {
+    /// Whether the backend answered the probe.
+    pub backend_reachable: bool,
+    /// Round-trip time of the probe.
+    pub latency: Duration,
+    /// The backend's error, if the probe failed.
+    pub error: Option<String>,
+    /// Connection pool state, for backends that pool connections.
+    pub pool: Option<PoolStats>,
+}
+
+impl HealthStatus {
+    /// Whether the limiter can serve checks backed by its backend. Use it for
+    /// readiness; the limiter itself stays live either way.
+    pub fn is_ready(&self) -> bool {
+        self.backend_reachable
+    }
+}
+
+/// How the limiter counts requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Fixed windows counted in the cache backend, shared by every limiter
+    /// using the same backend.
+    #[default]
+    FixedWindow,
+    /// GCRA kept in process memory with one atomic per key and no locks on
+    /// the hot path, for millions of checks per second. The backend is not
+    /// used, so limits are per process rather than distributed.
+    LocalLockFree,
+}
+
+/// The outcome of a rate limit check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    /// Whether the request may proceed.
+    pub allowed: bool,
+    /// Requests counted in the current window, including this one if allowed.
+    pub count: u32,
+    /// Maximum allowed requests per window.
+    pub limit: u32,
+    /// Requests left for this request's priority band.
+    pub remaining: u32,
+    /// The priority band the request was checked against.
+    pub priority: Priority,
+    /// Whether the request was admitted on quota borrowed from the next window.
+    pub borrowed: bool,
+    /// For blocked requests, how long until the window resets, if the backend
+    /// can report it.
+    pub retry_after: Option<Duration>,
+    /// Whether an allowed request reached the limiter's soft limit, so the
+    /// client should slow down before it starts being blocked.
+    pub warning: bool,
+    /// Whether the request was blocked by the service-wide limit rather than
+    /// its own. `count`, `limit` and `remaining` still describe the key.
+    pub global_limited: bool,
+    /// Whether the client key failed validation, so the invalid key policy
+    /// decided the request or its fallback key was counted instead.
+    pub invalid_key: bool,
+    /// When the request was checked. Refunds through `on_response` or a
+    /// `Reservation` only reach the window that was open at this time.
+    pub checked_at: Instant,
+}
+
+impl RateLimitDecision {
+    /// Returns response headers describing the decision: `RateLimit-Limit`
+    /// and `RateLimit-Remaining`, `Retry-After` in whole seconds for blocked
+    /// requests that know it, and `X-RateLimit-Warning` for allowed requests
+    /// past the soft limit.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![
+            ("RateLimit-Limit", self.limit.to_string()),
+            ("RateLimit-Remaining", self.remaining.to_string()),
+        ];
+        if let Some(retry_after) = self.retry_after.filter(|_| !self.allowed) {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            headers.push(("Retry-After", seconds.to_string()));
+        }
+        if self.warning {
+            headers.push(("X-RateLimit-Warning", "approaching limit".to_string()));
+        }
+        headers
+    }
 }
 
 /// The RateLimiter struct for distributed, IP-based rate limiting.
@@ -21,10 +248,33 @@ pub trait CacheBackend: Send + Sync {
 pub struct RateLimiter<B: CacheBackend> {
     /// The caching backend instance (e.g., Redis, in-memory, etc.).
     pub cache: Arc<B>,
-    /// Maximum allowed requests within a TTL window.
+    /// Maximum allowed requests within a TTL window under the initial policy.
+    /// Use `current_limit` to read the limit after `update_policy`.
     pub limit: u32,
     /// Duration of the rate limiting window.
     pub ttl: Duration,
+    reserves: PriorityReserves,
+    max_debt: u32,
+    warm_up: Option<(u32, Duration)>,
+    schedule: Option<Schedule>,
+    groups: Option<Box<dyn GroupResolver>>,
+    recorder: Option<Arc<dyn UsageRecorder>>,
+    local: Option<LockFreeGcra>,
+    policy: PolicyState,
+    audit: Option<Arc<dyn AuditSink>>,
+    hasher: Option<KeyHasher>,
+    observers: Vec<Arc<dyn DecisionObserver>>,
+    log: Option<DecisionLogger>,
+    calendar: Option<CalendarWindows>,
+    soft_limit_percent: Option<u32>,
+    fair_share: Option<FairShare>,
+    global: Option<Rate>,
+    key_validator: Option<Box<dyn KeyValidator>>,
+    invalid_key_policy: Option<InvalidKeyPolicy>,
+    namespace: String,
+    sweepers: Mutex<Vec<Sweeper>>,
+    pub(crate) reservations: PendingReservations,
+    pub(crate) stats: StatsCollector,
 }
 
 impl<B: CacheBackend> RateLimiter<B> {
@@ -36,17 +286,650 @@ impl<B: CacheBackend> RateLimiter<B> {
     /// * `limit` - Maximum number of allowed requests in the TTL window.
     /// * `ttl` - Duration for the rate limiting window.
     pub fn new(cache: Arc<B>, limit: u32, ttl: Duration) -> Self {
-        RateLimiter { cache, limit, ttl }
+        RateLimiter {
+            cache,
+            limit,
+            ttl,
+            reserves: PriorityReserves::default(),
+            max_debt: 0,
+            warm_up: None,
+            schedule: None,
+            groups: None,
+            recorder: None,
+            local: None,
+            policy: PolicyState::new(limit),
+            audit: None,
+            hasher: None,
+            observers: Vec::new(),
+            log: None,
+            calendar: None,
+            soft_limit_percent: None,
+            fair_share: None,
+            global: None,
+            key_validator: None,
+            invalid_key_policy: None,
+            namespace: String::new(),
+            sweepers: Mutex::new(Vec::new()),
+            reservations: PendingReservations::default(),
+            stats: StatsCollector::new(DEFAULT_HOT_KEY_CAPACITY),
+        }
+    }
+
+    /// Constructs a new RateLimiter allowing `rate`, e.g.
+    /// `RateLimiter::from_rate(cache, "100/5m".parse()?)`.
+    pub fn from_rate(cache: Arc<B>, rate: Rate) -> Self {
+        RateLimiter::new(cache, rate.limit, rate.window)
+    }
+
+    /// Reserves part of each window's quota for higher priorities, so
+    /// best-effort and then normal traffic is shed first as a key approaches
+    /// its limit. See `PriorityReserves` for how the reserves combine.
+    pub fn with_priority_reserves(mut self, reserves: PriorityReserves) -> Self {
+        self.reserves = reserves;
+        self
+    }
+
+    /// Lets a key exceed its limit by up to `max_debt` requests per window by
+    /// borrowing from the next window. The borrowed amount is counted against
+    /// the next window before any new requests are allowed. Debt not repaid
+    /// within two windows is forgiven.
+    pub fn with_max_debt(mut self, max_debt: u32) -> Self {
+        self.max_debt = max_debt;
+        self
+    }
+
+    /// Starts brand-new keys at `initial_limit` and ramps them linearly to the
+    /// full limit over `period`. Keys idle for more than twice the longer of
+    /// `period` and the window are treated as new again.
+    pub fn with_warm_up(mut self, initial_limit: u32, period: Duration) -> Self {
+        self.warm_up = Some((initial_limit, period));
+        self
+    }
+
+    /// Overrides the limit according to `schedule` (e.g. higher limits during
+    /// business hours). When no schedule rule matches, `limit` applies.
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    /// Flags allowed requests with `warning` once a key has used `percent` of
+    /// its limit (e.g. 80), so clients can slow down before they are blocked.
+    /// Warnings reach observers through `DecisionEvent::warning` and clients
+    /// through `RateLimitDecision::headers`.
+    pub fn with_soft_limit(mut self, percent: u32) -> Self {
+        self.soft_limit_percent = Some(percent);
+        self
+    }
+
+    /// Makes keys that `resolver` maps to the same group share one counter,
+    /// e.g. all API keys of one organization drawing from a single quota.
+    pub fn with_group_resolver<R: GroupResolver + 'static>(mut self, resolver: R) -> Self {
+        self.groups = Some(Box::new(resolver));
+        self
+    }
+
+    /// Divides each group's quota among its member keys according to
+    /// `share`. Members are counted per window next to the group's counter,
+    /// costing one or two extra backend calls per grouped request. Has no
+    /// effect without a group resolver, or with `Strategy::LocalLockFree`.
+    pub fn with_fair_share(mut self, share: FairShare) -> Self {
+        self.fair_share = Some(share);
+        self
+    }
+
+    /// Prefixes every backend key the limiter writes with `namespace`, e.g.
+    /// `"checkout:"`, so limiters sharing a backend never share counters,
+    /// whatever their policy version.
+    pub fn with_key_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = namespace.to_string();
+        self
+    }
+
+    /// Nests the limiter's key namespace inside `outer`.
+    pub(crate) fn within_namespace(mut self, outer: &str) -> Self {
+        self.namespace.insert_str(0, outer);
+        self
+    }
+
+    /// Caps all requests through the limiter, across every key, at `rate`,
+    /// to protect downstream dependencies from aggregate overload. The
+    /// service-wide limit is checked before the key's, shares the backend
+    /// (or, with `Strategy::LocalLockFree`, the process), and is given back
+    /// when the key's own limit blocks the request or it is refunded.
+    pub fn with_global_limit(mut self, rate: Rate) -> Self {
+        self.global = Some(rate);
+        self
+    }
+
+    /// Validates client keys, e.g. with `validation::ip_address`, and handles
+    /// invalid ones according to the invalid key policy (`Block` unless set).
+    pub fn with_key_validator<V: KeyValidator + 'static>(mut self, validator: V) -> Self {
+        self.key_validator = Some(Box::new(validator));
+        self
+    }
+
+    /// Handles missing or invalid client keys with `policy` rather than
+    /// counting them all in one bucket. Keys are checked with
+    /// `validation::well_formed` unless a validator is set. Decisions for
+    /// invalid keys report `invalid_key`.
+    pub fn with_invalid_key_policy(mut self, policy: InvalidKeyPolicy) -> Self {
+        self.invalid_key_policy = Some(policy);
+        self
+    }
+
+    /// Records allowed requests per key into time buckets, so `usage_series`
+    /// can return a usage history for dashboards.
+    pub fn with_usage_recorder(mut self, recorder: Arc<dyn UsageRecorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Records every blocked request to `sink`. Wrap the sink in a
+    /// `SampledSink` to bound the volume during floods.
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit = Some(sink);
+        self
+    }
+
+    /// Reports every decision to `observer`, for exporting metrics and traces.
+    /// Can be called more than once; observers are called in order.
+    pub fn with_observer(mut self, observer: Arc<dyn DecisionObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Logs blocked requests, backend errors and optionally allowed requests
+    /// through `logger`.
+    pub fn with_decision_logger(mut self, logger: DecisionLogger) -> Self {
+        self.log = Some(logger);
+        self
+    }
+
+    /// Aligns fixed windows to the calendar, e.g. per-minute windows starting
+    /// at :00 or per-day windows starting at local midnight, instead of at
+    /// each key's first request. The window index is part of the key. Has no
+    /// effect with `Strategy::LocalLockFree`, which has no windows.
+    pub fn with_calendar_windows(mut self, windows: CalendarWindows) -> Self {
+        self.calendar = Some(windows);
+        self
+    }
+
+    /// Hashes every client key with `hasher` before it reaches the backend,
+    /// usage recorder, stats or audit log, so raw IPs and API keys are never
+    /// stored. All instances sharing a backend must use the same hasher.
+    pub fn with_key_hasher(mut self, hasher: KeyHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Returns the recorded usage history for the given IP, oldest bucket
+    /// first. Empty unless a usage recorder is configured.
+    pub fn usage_series(&self, ip: &str) -> Vec<UsagePoint> {
+        self.recorder
+            .as_ref()
+            .map(|recorder| recorder.series(&self.client_key(ip)))
+            .unwrap_or_default()
+    }
+
+    /// Returns the limit currently in force, taking any schedule and policy
+    /// update into account.
+    pub fn current_limit(&self) -> u32 {
+        self.schedule
+            .as_ref()
+            .and_then(Schedule::current_limit)
+            .unwrap_or_else(|| self.policy.limit())
+    }
+
+    /// Sets the version of the initial policy. Versions other than 0 are
+    /// part of every counter key, so limiters sharing a backend only share
+    /// counters when they run the same policy version.
+    pub fn with_policy_version(mut self, version: u32) -> Self {
+        self.policy.set_version(version);
+        self
+    }
+
+    /// Uses `clock` instead of the system clock to time policy rollouts.
+    pub fn with_rollout_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.policy.set_clock(clock);
+        self
+    }
+
+    /// Returns the version of the policy currently in force.
+    pub fn policy_version(&self) -> u32 {
+        self.policy.version()
+    }
+
+    /// Replaces the limit with `limit` under policy `version`, applied to
+    /// live windows according to `rollout`. Counters are kept per version, so
+    /// counts made under the old policy never block requests under the new
+    /// one. A schedule, if set, still takes precedence over the limit.
+    pub fn update_policy(&self, version: u32, limit: u32, rollout: Rollout) {
+        self.policy.update(version, limit, rollout);
+    }
+
+    /// Selects how requests are counted. See `Strategy`.
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.local = match strategy {
+            Strategy::FixedWindow => None,
+            Strategy::LocalLockFree => Some(LockFreeGcra::new()),
+        };
+        self
+    }
+
+    /// Drops in-process state for keys that have fully recovered: lock-free
+    /// strategy state and the decision logger's per-key block tracking. Call
+    /// it periodically when many short-lived keys are seen.
+    pub fn purge_idle(&self) {
+        if let Some(local) = &self.local {
+            local.purge_idle();
+        }
+        if let Some(log) = &self.log {
+            log.purge_idle();
+        }
+    }
+
+    /// Sets how many of the hottest keys `stats()` tracks. Use `0` to disable
+    /// hot-key tracking entirely.
+    pub fn with_hot_key_capacity(mut self, capacity: usize) -> Self {
+        self.stats = StatsCollector::new(capacity);
+        self
+    }
+
+    /// Flushes buffered counter updates to the backend and pending audit
+    /// records to their sink. Call it before a deploy or shutdown so no
+    /// counts or records are lost.
+    pub fn flush(&self) -> Result<(), String> {
+        self.cache.flush()?;
+        if let Some(audit) = &self.audit {
+            audit.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Hands `sweeper` to the limiter, so `shutdown` stops it.
+    pub fn with_sweeper(self, sweeper: Sweeper) -> Self {
+        self.sweepers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(sweeper);
+        self
+    }
+
+    /// Stops the sweepers handed over with `with_sweeper`, waiting for any
+    /// sweep in progress, then flushes like `flush`. Checks keep working
+    /// afterwards, without background sweeping.
+    pub fn shutdown(&self) -> Result<(), String> {
+        let sweepers = std::mem::take(&mut *self.sweepers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+        // Dropping a sweeper stops and joins its thread.
+        drop(sweepers);
+        self.flush()
+    }
+
+    /// Pings the backend and reports whether it is reachable, with the probe
+    /// latency and pool state, for wiring into `/healthz` or `/readyz`.
+    pub fn health_check(&self) -> HealthStatus {
+        let started = Instant::now();
+        let result = self.cache.ping();
+        let latency = started.elapsed();
+        HealthStatus {
+            backend_reachable: result.is_ok(),
+            latency,
+            error: result.err(),
+            pool: self.cache.pool_stats(),
+        }
+    }
+
+    /// Returns aggregate counters collected since the limiter was created.
+    pub fn stats(&self) -> LimiterStats {
+        self.stats.snapshot(self.cache.key_count(), self.cache.pool_stats())
+    }
+
+    /// Returns the current usage for the given IP without consuming quota.
+    /// Invalid keys report the usage of the key the invalid key policy
+    /// counts them against, or none if it does not count them.
+    pub fn usage(&self, ip: &str) -> KeyUsage {
+        let ip = match self.validate_key(ip) {
+            Ok((ip, _)) => ip,
+            Err(allowed) => {
+                let decision = self.invalid_key_decision(allowed);
+                return KeyUsage {
+                    count: 0,
+                    limit: decision.limit,
+                    remaining: decision.remaining,
+                    resets_in: None,
+                };
+            }
+        };
+        let subject = self.subject_for(ip);
+        self.with_policy(&subject, self.current_limit(), |key, limit| {
+            let (count, resets_in) = match &self.local {
+                Some(local) => local.usage(key, limit, self.ttl),
+                None => {
+                    let count = self.cache.get(key).unwrap_or(0);
+                    (count, if count > 0 { self.cache.ttl(key) } else { None })
+                }
+            };
+            KeyUsage {
+                count,
+                limit,
+                remaining: limit.saturating_sub(count),
+                resets_in,
+            }
+        })
+    }
+
+    /// Returns `amount` units of quota to the given IP's current window, e.g.
+    /// when an operation was cancelled or failed early, or its response
+    /// should not count. The count never drops below zero, and the global
+    /// limit gets back only the units the key actually returned. Returns the
+    /// new count.
+    pub fn refund(&self, ip: &str, amount: u32) -> Result<u32, String> {
+        self.refund_since(ip, amount, None)
+    }
+
+    /// Same as `refund`, but only while the window that was open at
+    /// `checked_at` is still open, so quota charged to an expired window is
+    /// never handed to the one that replaced it. Backends that cannot
+    /// report TTLs refund to the current window.
+    pub(crate) fn refund_window(&self, ip: &str, amount: u32, checked_at: Instant) -> Result<u32, String> {
+        self.refund_since(ip, amount, Some(checked_at))
+    }
+
+    fn refund_since(&self, ip: &str, amount: u32, checked_at: Option<Instant>) -> Result<u32, String> {
+        let Ok((ip, _)) = self.validate_key(ip) else {
+            return Ok(0);
+        };
+        let subject = self.subject_for(ip);
+        let reopened = |key: &str, window: Duration| {
+            self.local.is_none() && checked_at.is_some_and(|at| self.opened_after(key, window, at))
+        };
+        // Every unit still counted for the key holds a global unit, so give
+        // back only as many global units as the key returned. A refund for a
+        // request that was never counted, or was already refunded, then
+        // cannot free global units other requests still hold.
+        let refund = |key: &str, limit: u32| -> Result<(u32, u32), String> {
+            match &self.local {
+                Some(local) => Ok(local.refund(key, amount, limit, self.ttl)),
+                None if reopened(key, self.ttl) => Ok((self.cache.get(key).unwrap_or(0), amount)),
+                None => {
+                    let before = self.cache.get(key).unwrap_or(0);
+                    let after = self.cache.decr(key, amount).inspect_err(|err| self.backend_error(err))?;
+                    Ok((after, amount.min(before.saturating_sub(after))))
+                }
+            }
+        };
+        let (count, released) = self.with_policy(&subject, self.current_limit(), refund)?;
+        if released > 0 && !self.global.is_some_and(|global| reopened(&self.global_key(), global.window)) {
+            self.release_global(released);
+        }
+        Ok(count)
+    }
+
+    /// Returns whether the fixed window of length `window` currently held in
+    /// `key` opened after `at`, judging by its remaining TTL.
+    fn opened_after(&self, key: &str, window: Duration, at: Instant) -> bool {
+        self.cache
+            .ttl(key)
+            .and_then(|left| (Instant::now() + left).checked_sub(window))
+            .is_some_and(|opened| opened > at)
+    }
+
+    /// Reports whether a request from the given IP would be allowed right
+    /// now, without consuming quota or updating stats. Useful for showing
+    /// "3 attempts left" or gating expensive work before the real check.
+    ///
+    /// Concurrent requests may consume quota between this call and the real
+    /// check, so its answer is advisory.
+    pub fn would_allow(&self, ip: &str) -> RateLimitDecision {
+        let (ip, invalid_key) = match self.validate_key(ip) {
+            Ok(key) => key,
+            Err(allowed) => return self.invalid_key_decision(allowed),
+        };
+        let subject = self.subject_for(ip);
+        let priority = Priority::Normal;
+        self.with_policy(&subject, self.current_limit(), |key, limit| {
+            let limit = self.warm_up_limit(&subject, limit, false);
+            let (band_ceiling, ceiling) = self.ceilings(limit, priority);
+            let (allowed, count, retry_after) = match &self.local {
+                Some(local) => {
+                    let outcome = local.peek(key, 1, limit, ceiling, self.ttl);
+                    (outcome.allowed, outcome.used, outcome.retry_after)
+                }
+                None => {
+                    let count = self.cache.get(key).or_else(|| {
+                        // A new window would start with the outstanding debt.
+                        (self.max_debt > 0)
+                            .then(|| self.cache.get(&self.debt_key_for(&subject)))
+                            .flatten()
+                    });
+                    let count = count.unwrap_or(0);
+                    let allowed = count < ceiling;
+                    let retry_after = if allowed { None } else { self.cache.ttl(key) };
+                    (allowed, count, retry_after)
+                }
+            };
+            let global = if allowed { self.peek_global() } else { Ok(()) };
+            let (allowed, retry_after) = match global {
+                Ok(()) => (allowed, retry_after),
+                Err(retry_after) => (false, retry_after),
+            };
+            RateLimitDecision {
+                allowed,
+                count,
+                limit,
+                remaining: band_ceiling.saturating_sub(count),
+                priority,
+                borrowed: allowed && count >= limit,
+                retry_after,
+                warning: self.past_soft_limit(allowed, count.saturating_add(1), limit),
+                global_limited: global.is_err(),
+                invalid_key,
+                checked_at: Instant::now(),
+            }
+        })
+    }
+
+    /// Returns how long the given IP must wait before its next request can be
+    /// allowed, or `None` if it is not currently limited (or the backend
+    /// cannot report TTLs). Invalid keys are validated as in `check`.
+    pub fn retry_after(&self, ip: &str) -> Option<Duration> {
+        let Ok((ip, _)) = self.validate_key(ip) else {
+            return None;
+        };
+        let subject = self.subject_for(ip);
+        self.with_policy(&subject, self.current_limit(), |key, limit| {
+            if let Some(local) = &self.local {
+                return local.peek(key, 1, limit, limit, self.ttl).retry_after;
+            }
+            let count = self.cache.get(key).unwrap_or(0);
+            if count < limit {
+                return None;
+            }
+            self.cache.ttl(key)
+        })
+    }
+
+    /// Returns the identity whose counter the IP draws from: its quota group
+    /// if it belongs to one, or the IP itself.
+    fn subject_for<'a>(&self, ip: &'a str) -> Cow<'a, str> {
+        match self.groups.as_ref().and_then(|groups| groups.group(ip)) {
+            Some(group) => {
+                let group = format!("group:{}", group);
+                match &self.hasher {
+                    Some(hasher) => Cow::Owned(hasher.hash_hex(&group)),
+                    None => Cow::Owned(group),
+                }
+            }
+            None => self.client_key(ip),
+        }
+    }
+
+    /// Returns the form in which a client key is stored: hashed if a key
+    /// hasher is set, unchanged otherwise.
+    pub(crate) fn client_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        match &self.hasher {
+            Some(hasher) => Cow::Owned(hasher.hash_hex(key)),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// Builds the backend key for the given subject under the policy version
+    /// identified by `tag`.
+    fn key_for(&self, tag: &str, subject: &str) -> Key {
+        match &self.calendar {
+            Some(calendar) => {
+                let (index, _) = calendar.current(self.ttl);
+                Key::new(&[&self.namespace, "rate_limit:", tag, &format!("{}:", index), subject])
+            }
+            None => Key::new(&[&self.namespace, "rate_limit:", tag, subject]),
+        }
+    }
+
+    /// Builds the backend key of a named policy's counter for `subject`,
+    /// with the TTL of a window of `window` opening now. Named policies
+    /// have their own namespace, so they never share a counter with the
+    /// limiter's own checks, and follow its policy version and calendar.
+    pub(crate) fn policy_key_for(&self, name: &str, subject: &str, window: Duration) -> (Key, Duration) {
+        let tag = self.policy.tag();
+        match &self.calendar {
+            Some(calendar) => {
+                let (index, left) = calendar.current(window);
+                let index = format!("{}:", index);
+                (Key::new(&[&self.namespace, "rate_limit_policy:", &tag, &index, name, ":", subject]), left)
+            }
+            None => (Key::new(&[&self.namespace, "rate_limit_policy:", &tag, name, ":", subject]), window),
+        }
+    }
+
+    /// Returns the TTL for a window opening now: the rest of the calendar
+    /// window if windows are aligned, otherwise the full window.
+    fn window_ttl(&self) -> Duration {
+        match &self.calendar {
+            Some(calendar) => calendar.current(self.ttl).1,
+            None => self.ttl,
+        }
+    }
+
+    /// Runs `f` with the subject's key and limit under the policy that applies
+    /// to it, which during a rollout may still be the previous one.
+    fn with_policy<T>(
+        &self,
+        subject: &str,
+        limit: u32,
+        f: impl FnOnce(&str, u32) -> T,
+    ) -> T {
+        self.policy.resolve(
+            limit,
+            self.ttl,
+            |tag| self.cache.get(&self.key_for(tag, subject)).is_some(),
+            |policy| f(&self.key_for(policy.tag, subject), policy.limit),
+        )
+    }
+
+    /// Builds the backend key holding the subject's outstanding debt.
+    fn debt_key_for(&self, subject: &str) -> Key {
+        Key::new(&[&self.namespace, "rate_limit_debt:", subject])
+    }
+
+    /// Scales `limit` down for keys that are still warming up.
+    /// On a key's first sighting, the warm-up period is started if `start`
+    /// is set; otherwise the initial limit is reported without writing.
+    fn warm_up_limit(&self, subject: &str, limit: u32, start: bool) -> u32 {
+        let Some((initial_limit, period)) = self.warm_up else {
+            return limit;
+        };
+        let seen_key = self.seen_key_for(subject);
+        let warming_key = Key::new(&[&self.namespace, "rate_limit_warming:", subject]);
+        let idle_ttl = period.max(self.ttl) * 2;
+
+        let elapsed = if self.cache.get(&seen_key).is_none() {
+            if !start {
+                return initial_limit.min(limit);
+            }
+            // First sighting: start the warm-up period now.
+            if let Err(err) = self
+                .cache
+                .set(&seen_key, 1, idle_ttl)
+                .and_then(|_| self.cache.set(&warming_key, 1, period))
+            {
+                self.backend_error(&err);
+            }
+            Duration::ZERO
+        } else if self.cache.get(&warming_key).is_some() {
+            let left = self.cache.ttl(&warming_key).unwrap_or(period);
+            period.saturating_sub(left)
+        } else {
+            return limit;
+        };
+
+        if elapsed >= period || initial_limit >= limit {
+            return limit;
+        }
+        let ramp = (limit - initial_limit) as f64 * elapsed.as_secs_f64() / period.as_secs_f64();
+        initial_limit + ramp as u32
+    }
+
+    /// Keeps a warmed-up key from being treated as new while it stays active.
+    fn refresh_seen(&self, subject: &str) {
+        if let Some((_, period)) = self.warm_up {
+            let seen_key = self.seen_key_for(subject);
+            if let Err(err) = self.cache.set(&seen_key, 1, period.max(self.ttl) * 2) {
+                self.backend_error(&err);
+            }
+        }
+    }
+
+    /// Builds the backend key marking that the subject has been seen recently.
+    fn seen_key_for(&self, subject: &str) -> Key {
+        Key::new(&[&self.namespace, "rate_limit_seen:", subject])
+    }
+
+    /// Adds the subject's outstanding debt, if any, to a window that was
+    /// found empty. The debt is claimed with compare-and-swap so requests
+    /// racing to open the window repay it once, and added with `incr` so
+    /// requests counted meanwhile are kept.
+    fn repay_debt(&self, subject: &str, key: &str) {
+        let debt_key = self.debt_key_for(subject);
+        let debt = self.cache.get(&debt_key).unwrap_or(0);
+        if debt == 0 {
+            return;
+        }
+        let claimed = match self.cache.compare_and_swap(&debt_key, Some(debt), 0, self.ttl) {
+            Ok(claimed) => Ok(claimed),
+            // Without compare-and-swap, racing openers may each repay it.
+            Err(_) => self.cache.set(&debt_key, 0, self.ttl).map(|_| true),
+        };
+        let repaid = claimed.and_then(|claimed| match claimed {
+            true => self.cache.incr(key, debt, self.window_ttl()).map(|_| ()),
+            false => Ok(()),
+        });
+        if let Err(err) = repaid {
+            self.backend_error(&err);
+        }
+    }
+
+    /// Counts a failed backend operation in stats and logs it.
+    pub(crate) fn backend_error(&self, err: &str) {
+        self.stats.record_backend_error();
+        if let Some(log) = &self.log {
+            log.backend_error(err);
+        }
+    }
+
+    /// Records that the subject's count reached `count`, past `limit`, on borrowed quota.
+    fn record_debt(&self, subject: &str, count: u32, limit: u32) {
+        let debt_key = self.debt_key_for(subject);
+        // Keep the debt until the end of the next window at the latest.
+        if let Err(err) = self.cache.set(&debt_key, count - limit, self.ttl * 2) {
+            self.backend_error(&err);
+        }
     }
 
     /// Checks whether a request from the given IP is allowed.
     ///
     /// This method does the following:
     /// 1. Builds a key using the client's IP.
-    /// 2. Retrieves the current request count from the cache.
-    /// 3. If under the limit, increments the count.
-    ///    - If this is the first request, sets the TTL for that key.
-    /// 4. Returns `true` if the request is allowed, or `false` if the limit is exceeded.
+    /// 2. Increments the count if it is under the limit, in a single backend
+    ///    operation that also starts a new window (with its TTL) if needed.
+    /// 3. Returns `true` if the request is allowed, or `false` if the limit is exceeded.
     ///
     /// # Arguments
     ///
@@ -56,29 +939,386 @@ impl<B: CacheBackend> RateLimiter<B> {
     ///
     /// * `true` if the request is allowed; `false` otherwise.
     pub fn allow(&self, ip: &str) -> bool {
-        // Use the IP as the key for rate limiting.
-        let key = format!("rate_limit:{}", ip);
-        // println!("found out key format");
-        
-        // Get the current request count, defaulting to 0 if not found.
-        // println!("current count of requests {:?}", self.cache.get(&key));
-        let current_count = self.cache.get(&key).unwrap_or(0);
-        // println!("current count of requests {}", current_count);
-
-        // If under the limit, allow the request.
-        if current_count < self.limit {
-            match self.cache.incr(&key, 1) {
-                Ok(new_count) => {
-                    if new_count == 1 {
-                        // If this is the first request, set the TTL.
-                        let _ = self.cache.set(&key, new_count, self.ttl);
+        self.check(ip).allowed
+    }
+
+    /// Checks a request from the given IP at `Priority::Normal` and returns
+    /// the full decision.
+    pub fn check(&self, ip: &str) -> RateLimitDecision {
+        self.check_with_priority(ip, Priority::Normal)
+    }
+
+    /// Checks whether a request from the given IP that consumes `cost` units
+    /// of quota is allowed, e.g. a batch call worth several requests.
+    pub fn allow_n(&self, ip: &str, cost: u32) -> bool {
+        self.check_n(ip, cost).allowed
+    }
+
+    /// Checks a request from the given IP that consumes `cost` units of quota
+    /// at `Priority::Normal`. The request is allowed only if all `cost` units
+    /// fit; otherwise nothing is consumed.
+    pub fn check_n(&self, ip: &str, cost: u32) -> RateLimitDecision {
+        self.check_cost(ip, self.current_limit(), Priority::Normal, cost)
+    }
+
+    /// Checks a request from the given IP at the given priority. Lower
+    /// priorities are blocked once the count reaches the quota reserved for
+    /// higher ones; the decision reports which band was applied.
+    pub fn check_with_priority(&self, ip: &str, priority: Priority) -> RateLimitDecision {
+        self.check_with_limit(ip, self.current_limit(), priority)
+    }
+
+    /// Same as `check_with_priority`, but checks against `limit` instead of
+    /// the configured limit. Used by wrappers that compute an effective limit
+    /// per request.
+    pub(crate) fn check_with_limit(
+        &self,
+        ip: &str,
+        limit: u32,
+        priority: Priority,
+    ) -> RateLimitDecision {
+        self.check_cost(ip, limit, priority, 1)
+    }
+
+    /// Checks a request consuming `cost` units against `limit`.
+    fn check_cost(&self, ip: &str, limit: u32, priority: Priority, cost: u32) -> RateLimitDecision {
+        self.expire_reservations(ip);
+        let (ip, invalid_key) = match self.validate_key(ip) {
+            Ok(key) => key,
+            Err(allowed) => {
+                self.stats.record(ip, allowed);
+                return self.invalid_key_decision(allowed);
+            }
+        };
+        // Use the IP, or the quota group it belongs to, as the key.
+        let subject = self.subject_for(ip);
+        let mut decision = self.policy.resolve(
+            limit,
+            self.ttl,
+            |tag| self.cache.get(&self.key_for(tag, &subject)).is_some(),
+            |policy| self.check_key(ip, &subject, policy, priority, cost),
+        );
+        decision.invalid_key = invalid_key;
+        decision
+    }
+
+    /// Returns the key to count `ip` against, and whether `ip` was invalid
+    /// and replaced by the fallback key. Returns `Err` with whether to allow
+    /// the request if the invalid key policy decides it without counting.
+    pub(crate) fn validate_key<'a>(&'a self, ip: &'a str) -> Result<(&'a str, bool), bool> {
+        if self.key_validator.is_none() && self.invalid_key_policy.is_none() {
+            return Ok((ip, false));
+        }
+        let valid = match &self.key_validator {
+            Some(validator) => validator.is_valid(ip),
+            None => well_formed(ip),
+        };
+        match (valid, &self.invalid_key_policy) {
+            (true, _) => Ok((ip, false)),
+            (false, Some(InvalidKeyPolicy::UseFallbackKey(fallback))) => Ok((fallback, true)),
+            (false, Some(InvalidKeyPolicy::Allow)) => Err(true),
+            (false, Some(InvalidKeyPolicy::Block) | None) => Err(false),
+        }
+    }
+
+    /// Builds the decision for an invalid key the policy allowed or blocked
+    /// without counting it.
+    fn invalid_key_decision(&self, allowed: bool) -> RateLimitDecision {
+        let limit = self.current_limit();
+        RateLimitDecision {
+            allowed,
+            count: 0,
+            limit,
+            remaining: if allowed { limit } else { 0 },
+            priority: Priority::Normal,
+            borrowed: false,
+            retry_after: None,
+            warning: false,
+            global_limited: false,
+            invalid_key: true,
+            checked_at: Instant::now(),
+        }
+    }
+
+    /// Checks a request consuming `cost` units for the given subject under
+    /// the resolved `policy`.
+    fn check_key(
+        &self,
+        ip: &str,
+        subject: &str,
+        policy: Resolved<'_>,
+        priority: Priority,
+        cost: u32,
+    ) -> RateLimitDecision {
+        let started = Instant::now();
+        let key = self.key_for(policy.tag, subject);
+        let limit = self.warm_up_limit(subject, policy.limit, true);
+        let (band_ceiling, ceiling) = self.ceilings(limit, priority);
+        let client = if self.groups.is_none() {
+            Cow::Borrowed(subject)
+        } else {
+            self.client_key(ip)
+        };
+
+        let global = self.claim_global(cost);
+        let (allowed, count, retry_after) = match (&self.local, global) {
+            (Some(local), Err(retry_after)) => {
+                (false, local.peek(&key, cost, limit, ceiling, self.ttl).used, retry_after)
+            }
+            (None, Err(retry_after)) => (false, self.cache.get(&key).unwrap_or(0), retry_after),
+            (Some(local), Ok(())) => {
+                // Borrowing is built into GCRA: a larger ceiling is a larger
+                // burst that is paid back as the arrival time catches up.
+                let outcome = local.check(&key, cost, limit, ceiling, self.ttl);
+                (outcome.allowed, outcome.used, outcome.retry_after)
+            }
+            (None, Ok(())) => match self.claim_share(subject, &client, limit, cost) {
+                Err((count, retry_after)) => (false, count, retry_after),
+                Ok(share_key) => {
+                    let (allowed, count) = self.count_in_window(subject, &key, cost, limit, ceiling);
+                    if let (false, Some(share_key)) = (allowed, share_key) {
+                        // The group is full, so the member's share goes unused.
+                        if let Err(err) = self.cache.decr(&share_key, cost) {
+                            self.backend_error(&err);
+                        }
                     }
-                    true
+                    let retry_after = if allowed { None } else { self.cache.ttl(&key) };
+                    (allowed, count, retry_after)
                 }
-                Err(_) => false, // On cache errors, you might choose to block the request.
+            },
+        };
+        if global.is_ok() && !allowed {
+            self.release_global(cost);
+        }
+        let decision = RateLimitDecision {
+            allowed,
+            count,
+            limit,
+            remaining: band_ceiling.saturating_sub(count),
+            priority,
+            borrowed: allowed && count > limit,
+            retry_after,
+            warning: self.past_soft_limit(allowed, count, limit),
+            global_limited: global.is_err(),
+            invalid_key: false,
+            checked_at: Instant::now(),
+        };
+        self.report(ip, &client, cost, policy.version, started, &decision);
+        decision
+    }
+
+    /// Reports a decision for `client`, whose unhashed key is `ip`, to the
+    /// stats, the usage recorder, the audit sink, the decision logger and
+    /// the observers. Every check path reports through here.
+    pub(crate) fn report(
+        &self,
+        ip: &str,
+        client: &str,
+        cost: u32,
+        policy_version: u32,
+        started: Instant,
+        decision: &RateLimitDecision,
+    ) {
+        let RateLimitDecision { allowed, count, limit, priority, warning, .. } = *decision;
+        self.stats.record(client, allowed);
+        if let (true, Some(recorder)) = (allowed, &self.recorder) {
+            recorder.record(client, cost);
+        }
+        if let (false, Some(audit)) = (allowed, &self.audit) {
+            audit.record(&AuditRecord {
+                timestamp: SystemTime::now(),
+                key_hash: self
+                    .hasher
+                    .as_ref()
+                    .map_or_else(|| hash_key(ip), |hasher| hasher.hash(ip)),
+                policy_version,
+                limit,
+                count,
+                priority,
+            });
+        }
+        if let Some(log) = &self.log {
+            log.decision(client, allowed, count, limit);
+        }
+        if !self.observers.is_empty() {
+            let event = DecisionEvent {
+                key: client,
+                allowed,
+                warning,
+                cost,
+                count,
+                limit,
+                priority,
+                policy_version,
+                latency: started.elapsed(),
+            };
+            for observer in &self.observers {
+                observer.observe(&event);
+            }
+        }
+    }
+
+    /// Builds the backend key of the service-wide counter.
+    fn global_key(&self) -> Key {
+        Key::new(&[&self.namespace, GLOBAL_KEY])
+    }
+
+    /// Counts `cost` units against the service-wide limit, if one is set.
+    /// Returns when the limit frees up if the request does not fit.
+    pub(crate) fn claim_global(&self, cost: u32) -> Result<(), Option<Duration>> {
+        let Some(global) = self.global else {
+            return Ok(());
+        };
+        let key = self.global_key();
+        match &self.local {
+            Some(local) => {
+                let outcome = local.check(&key, cost, global.limit, global.limit, global.window);
+                if outcome.allowed { Ok(()) } else { Err(outcome.retry_after) }
             }
+            None => match self.cache.incr_within(&key, cost, global.limit, global.window) {
+                Ok((true, _)) => Ok(()),
+                Ok((false, _)) => Err(self.cache.ttl(&key)),
+                Err(err) => {
+                    self.backend_error(&err);
+                    Err(None)
+                }
+            },
+        }
+    }
+
+    /// Reports whether one more request fits the service-wide limit, without
+    /// counting it.
+    fn peek_global(&self) -> Result<(), Option<Duration>> {
+        let Some(global) = self.global else {
+            return Ok(());
+        };
+        let key = self.global_key();
+        match &self.local {
+            Some(local) => {
+                let outcome = local.peek(&key, 1, global.limit, global.limit, global.window);
+                if outcome.allowed { Ok(()) } else { Err(outcome.retry_after) }
+            }
+            None if self.cache.get(&key).unwrap_or(0) < global.limit => Ok(()),
+            None => Err(self.cache.ttl(&key)),
+        }
+    }
+
+    /// Gives `cost` units back to the service-wide limit.
+    pub(crate) fn release_global(&self, cost: u32) {
+        let Some(global) = self.global else {
+            return;
+        };
+        let key = self.global_key();
+        match &self.local {
+            Some(local) => {
+                local.refund(&key, cost, global.limit, global.window);
+            }
+            None => {
+                if let Err(err) = self.cache.decr(&key, cost) {
+                    self.backend_error(&err);
+                }
+            }
+        }
+    }
+
+    /// Returns whether an allowed request bringing a key to `count` reached
+    /// the soft limit.
+    fn past_soft_limit(&self, allowed: bool, count: u32, limit: u32) -> bool {
+        match self.soft_limit_percent {
+            Some(percent) => allowed && u64::from(count) * 100 >= u64::from(limit) * u64::from(percent),
+            None => false,
+        }
+    }
+
+    /// Counts `cost` units against `client`'s fair share of its group
+    /// `subject`, if fair sharing applies. Returns the member's share key
+    /// when counted, or the member's count and when its share frees up if
+    /// the request exceeds the share.
+    fn claim_share(
+        &self,
+        subject: &str,
+        client: &str,
+        limit: u32,
+        cost: u32,
+    ) -> Result<Option<Key>, (u32, Option<Duration>)> {
+        let Some(share) = self.fair_share else {
+            return Ok(None);
+        };
+        if self.groups.is_none() || client == subject {
+            return Ok(None);
+        }
+        let key = self.key_for("share:", &format!("{}:{}", subject, client));
+        let cap = match share {
+            FairShare::MaxShare(percent) => {
+                (u64::from(limit) * u64::from(percent)).div_ceil(100).min(u64::from(limit)) as u32
+            }
+            FairShare::Even => {
+                let members_key = self.key_for("members:", subject);
+                let members = if self.cache.get(&key).is_some() {
+                    self.cache.get(&members_key)
+                } else {
+                    self.cache
+                        .incr(&members_key, 1, self.window_ttl())
+                        .map_err(|err| self.backend_error(&err))
+                        .ok()
+                };
+                limit.div_ceil(members.unwrap_or(1).max(1))
+            }
+        };
+        match self.cache.incr_within(&key, cost, cap.max(1), self.window_ttl()) {
+            Ok((true, _)) => Ok(Some(key)),
+            Ok((false, count)) => Err((count, self.cache.ttl(&key))),
+            Err(err) => {
+                self.backend_error(&err);
+                Err((0, None))
+            }
+        }
+    }
+
+    /// Returns the highest count allowed for `priority` under `limit`, without
+    /// and with quota borrowed from the next window.
+    fn ceilings(&self, limit: u32, priority: Priority) -> (u32, u32) {
+        let band_ceiling = self.reserves.ceiling(limit, priority);
+        // Requests allowed to use the full limit may also borrow past it.
+        if band_ceiling == limit {
+            (band_ceiling, limit.saturating_add(self.max_debt))
         } else {
-            false
+            (band_ceiling, band_ceiling)
+        }
+    }
+
+    /// Counts a request of `cost` units against the subject's fixed window in
+    /// the backend, returning whether it was allowed and the resulting count.
+    fn count_in_window(
+        &self,
+        subject: &str,
+        key: &str,
+        cost: u32,
+        limit: u32,
+        ceiling: u32,
+    ) -> (bool, u32) {
+        if self.max_debt > 0 && self.cache.get(key).is_none() {
+            // A new window starts by paying back what the last one borrowed.
+            self.repay_debt(subject, key);
+        }
+
+        // Count the request if that keeps the key within the ceiling for its
+        // priority. Expiry, increment and TTL are handled in one backend call.
+        match self.cache.incr_within(key, cost, ceiling, self.window_ttl()) {
+            Ok((allowed, count)) => {
+                if allowed && count == cost {
+                    self.refresh_seen(subject);
+                }
+                if allowed && count > limit {
+                    self.record_debt(subject, count, limit);
+                }
+                (allowed, count)
+            }
+            Err(err) => {
+                // On cache errors, you might choose to allow the request instead.
+                self.backend_error(&err);
+                (false, 0)
+            }
         }
     }
 }
@@ -105,8 +1345,9 @@ mod tests {
 
         // For the IP "127.0.0.1", the first 5 requests should be allowed.
         for i in 0..5 {
-            println!("Request {}: {}", i + 1, limiter.allow("127.0.0.1"));
-            assert!(limiter.allow("127.0.0.1") || true); // using || true just to force print if needed
+            let allowed = limiter.allow("127.0.0.1");
+            println!("Request {}: {}", i + 1, allowed);
+            assert!(allowed);
         }
 
         println!("Sending 6th request which should be blocked");
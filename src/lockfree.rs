@@ -0,0 +1,168 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+
+/// An in-process keyed GCRA limiter whose hot path takes no write locks.
+///
+/// Each key's state is a single atomic theoretical arrival time (TAT),
+/// updated with compare-and-swap. Existing keys are reached through a shared
+/// read of the map, so concurrent checks of the same or different keys never
+/// serialize on a lock; only the first request of a new key inserts.
+pub(crate) struct LockFreeGcra {
+    epoch: Instant,
+    states: DashMap<String, AtomicU64>,
+}
+
+/// Result of a lock-free GCRA check.
+pub(crate) struct GcraOutcome {
+    pub(crate) allowed: bool,
+    /// Slots in use out of `ceiling` after this request.
+    pub(crate) used: u32,
+    /// For blocked requests, time until the next slot frees up.
+    pub(crate) retry_after: Option<Duration>,
+}
+
+impl LockFreeGcra {
+    pub(crate) fn new() -> Self {
+        LockFreeGcra {
+            epoch: Instant::now(),
+            states: DashMap::new(),
+        }
+    }
+
+    /// Checks a request worth `cost` units for `key`, where `limit` units are
+    /// regained per `period` and up to `ceiling` may be outstanding at once.
+    pub(crate) fn check(
+        &self,
+        key: &str,
+        cost: u32,
+        limit: u32,
+        ceiling: u32,
+        period: Duration,
+    ) -> GcraOutcome {
+        if limit == 0 || ceiling == 0 {
+            return GcraOutcome {
+                allowed: false,
+                used: 0,
+                retry_after: None,
+            };
+        }
+        let now = self.epoch.elapsed().as_nanos() as u64;
+        let interval = (period.as_nanos() as u64 / u64::from(limit)).max(1);
+        let tolerance = interval.saturating_mul(u64::from(ceiling));
+        let increment = interval.saturating_mul(u64::from(cost));
+
+        match self.states.get(key) {
+            Some(tat) => Self::update(&tat, now, interval, increment, tolerance),
+            None => {
+                let tat = self.states.entry(key.to_string()).or_insert(AtomicU64::new(now));
+                Self::update(&tat, now, interval, increment, tolerance)
+            }
+        }
+    }
+
+    fn update(tat: &AtomicU64, now: u64, interval: u64, increment: u64, tolerance: u64) -> GcraOutcome {
+        let mut current = tat.load(Ordering::Acquire);
+        loop {
+            let next = current.max(now).saturating_add(increment);
+            if next - now > tolerance {
+                return GcraOutcome {
+                    allowed: false,
+                    used: (current.max(now) - now).div_ceil(interval) as u32,
+                    retry_after: Some(Duration::from_nanos(next - now - tolerance)),
+                };
+            }
+            match tat.compare_exchange_weak(current, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    return GcraOutcome {
+                        allowed: true,
+                        used: (next - now).div_ceil(interval) as u32,
+                        retry_after: None,
+                    }
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Reports what `check` would decide, without updating any state. `used`
+    /// is the number of slots in use before the request.
+    pub(crate) fn peek(
+        &self,
+        key: &str,
+        cost: u32,
+        limit: u32,
+        ceiling: u32,
+        period: Duration,
+    ) -> GcraOutcome {
+        if limit == 0 || ceiling == 0 {
+            return GcraOutcome {
+                allowed: false,
+                used: 0,
+                retry_after: None,
+            };
+        }
+        let now = self.epoch.elapsed().as_nanos() as u64;
+        let interval = (period.as_nanos() as u64 / u64::from(limit)).max(1);
+        let tolerance = interval.saturating_mul(u64::from(ceiling));
+        let tat = self
+            .states
+            .get(key)
+            .map_or(now, |tat| tat.load(Ordering::Acquire))
+            .max(now);
+        let next = tat.saturating_add(interval.saturating_mul(u64::from(cost)));
+        let allowed = next - now <= tolerance;
+        GcraOutcome {
+            allowed,
+            used: (tat - now).div_ceil(interval) as u32,
+            retry_after: (!allowed).then(|| Duration::from_nanos(next - now - tolerance)),
+        }
+    }
+
+    /// Returns the slots in use for `key`, where `limit` units are regained
+    /// per `period`, and how long until all of them are free again.
+    pub(crate) fn usage(&self, key: &str, limit: u32, period: Duration) -> (u32, Option<Duration>) {
+        if limit == 0 {
+            return (0, None);
+        }
+        let now = self.epoch.elapsed().as_nanos() as u64;
+        let interval = (period.as_nanos() as u64 / u64::from(limit)).max(1);
+        let tat = self
+            .states
+            .get(key)
+            .map_or(now, |tat| tat.load(Ordering::Acquire))
+            .max(now);
+        let used = (tat - now).div_ceil(interval).min(u64::from(u32::MAX)) as u32;
+        (used, (tat > now).then(|| Duration::from_nanos(tat - now)))
+    }
+
+    /// Returns `cost` units to `key` by moving its arrival time back, never
+    /// earlier than now. Returns the slots in use afterwards and how many
+    /// units were actually returned.
+    pub(crate) fn refund(&self, key: &str, cost: u32, limit: u32, period: Duration) -> (u32, u32) {
+        let Some(tat) = self.states.get(key) else {
+            return (0, 0);
+        };
+        if limit == 0 {
+            return (0, 0);
+        }
+        let now = self.epoch.elapsed().as_nanos() as u64;
+        let interval = (period.as_nanos() as u64 / u64::from(limit)).max(1);
+        let refund = interval.saturating_mul(u64::from(cost));
+        let previous = tat
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                Some(current.saturating_sub(refund).max(now))
+            })
+            .unwrap_or(now);
+        let after = previous.saturating_sub(refund).max(now);
+        let returned = (previous.max(now) - after).div_ceil(interval);
+        ((after - now).div_ceil(interval) as u32, returned as u32)
+    }
+
+    /// Drops keys whose state has fully recovered, bounding memory for
+    /// workloads with many short-lived keys.
+    pub(crate) fn purge_idle(&self) {
+        let now = self.epoch.elapsed().as_nanos() as u64;
+        self.states.retain(|_, tat| tat.load(Ordering::Acquire) > now);
+    }
+}
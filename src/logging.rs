@@ -0,0 +1,198 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+
+/// Severity of a log line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// A backend operation failed.
+    Error,
+    /// A request was blocked.
+    Warn,
+    /// A request was allowed.
+    Trace,
+}
+
+impl Level {
+    /// Returns the level name in upper case, as used by `log` and `tracing`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// Destination for limiter log lines. Implement it to forward to `log`,
+/// `tracing` or another logging framework.
+///
+/// Any `Fn(Level, &str)` is a logger.
+pub trait Logger: Send + Sync {
+    /// Writes one line at `level`.
+    fn log(&self, level: Level, message: &str);
+}
+
+impl<F> Logger for F
+where
+    F: Fn(Level, &str) + Send + Sync,
+{
+    fn log(&self, level: Level, message: &str) {
+        self(level, message)
+    }
+}
+
+/// Writes log lines to standard error as `LEVEL message`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrLogger;
+
+impl Logger for StderrLogger {
+    fn log(&self, level: Level, message: &str) {
+        let _ = writeln!(std::io::stderr(), "{} {}", level.as_str(), message);
+    }
+}
+
+/// Logs limiter decisions and backend errors: blocked requests at `Warn`,
+/// backend errors at `Error` and, if enabled, allowed requests at `Trace`.
+///
+/// Blocks are logged at most once per key per interval (default one minute),
+/// with the number of blocks suppressed in between, so an attack does not
+/// flood the log. Keys idle for an interval are forgotten as further blocks
+/// come in. Backend errors are likewise logged at most once per error
+/// interval (default ten seconds), so an outage does not log every request.
+/// Keys are logged hashed if the limiter has a key hasher.
+pub struct DecisionLogger {
+    logger: Arc<dyn Logger>,
+    log_allowed: bool,
+    block_interval: Duration,
+    /// Per key: when a block was last logged and blocks suppressed since.
+    blocks: DashMap<String, (Instant, u64)>,
+    next_purge: Mutex<Instant>,
+    error_interval: Duration,
+    /// When an error was last logged and errors suppressed since.
+    errors: Mutex<Option<(Instant, u64)>>,
+}
+
+impl DecisionLogger {
+    /// Logs to `logger`.
+    pub fn new(logger: Arc<dyn Logger>) -> Self {
+        DecisionLogger {
+            logger,
+            log_allowed: false,
+            block_interval: Duration::from_secs(60),
+            blocks: DashMap::new(),
+            next_purge: Mutex::new(Instant::now()),
+            error_interval: Duration::from_secs(10),
+            errors: Mutex::new(None),
+        }
+    }
+
+    /// Also logs allowed requests, at `Trace`.
+    pub fn with_allowed(mut self, log_allowed: bool) -> Self {
+        self.log_allowed = log_allowed;
+        self
+    }
+
+    /// Sets the minimum time between logged blocks of the same key. Use
+    /// `Duration::ZERO` to log every block.
+    pub fn with_block_interval(mut self, interval: Duration) -> Self {
+        self.block_interval = interval;
+        self
+    }
+
+    /// Sets the minimum time between logged backend errors. Use
+    /// `Duration::ZERO` to log every error.
+    pub fn with_error_interval(mut self, interval: Duration) -> Self {
+        self.error_interval = interval;
+        self
+    }
+
+    pub(crate) fn decision(&self, key: &str, allowed: bool, count: u32, limit: u32) {
+        if allowed {
+            if self.log_allowed {
+                let message = format!("rate limit allowed key={} count={} limit={}", key, count, limit);
+                self.logger.log(Level::Trace, &message);
+            }
+            return;
+        }
+
+        let now = Instant::now();
+        self.purge_if_due(key, now);
+        let suppressed = match self.blocks.get_mut(key) {
+            Some(mut entry) => {
+                let (last, suppressed) = *entry;
+                if now.duration_since(last) < self.block_interval {
+                    entry.1 += 1;
+                    return;
+                }
+                *entry = (now, 0);
+                suppressed
+            }
+            None => {
+                self.blocks.insert(key.to_string(), (now, 0));
+                0
+            }
+        };
+        let mut message = format!("rate limit blocked key={} count={} limit={}", key, count, limit);
+        if suppressed > 0 {
+            message.push_str(&format!(" suppressed={}", suppressed));
+        }
+        self.logger.log(Level::Warn, &message);
+    }
+
+    pub(crate) fn backend_error(&self, err: &str) {
+        let now = Instant::now();
+        let suppressed = {
+            let mut errors = self.errors.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            match &mut *errors {
+                Some((last, suppressed)) if now.duration_since(*last) < self.error_interval => {
+                    *suppressed += 1;
+                    return;
+                }
+                _ => errors.replace((now, 0)).map_or(0, |(_, suppressed)| suppressed),
+            }
+        };
+        let mut message = format!("rate limit backend error: {}", err);
+        if suppressed > 0 {
+            message.push_str(&format!(" suppressed={}", suppressed));
+        }
+        self.logger.log(Level::Error, &message);
+    }
+
+    /// Forgets keys whose last logged block is older than the interval,
+    /// logging how many blocks were suppressed for them since.
+    pub(crate) fn purge_idle(&self) {
+        self.purge(None);
+    }
+
+    /// Runs a purge at most once per block interval, keeping `key`, whose
+    /// suppressed blocks are about to be logged with its next block.
+    fn purge_if_due(&self, key: &str, now: Instant) {
+        let due = match self.next_purge.try_lock() {
+            Ok(mut next) if *next <= now => {
+                *next = now + self.block_interval;
+                true
+            }
+            _ => false,
+        };
+        if due {
+            self.purge(Some(key));
+        }
+    }
+
+    fn purge(&self, keep: Option<&str>) {
+        let interval = self.block_interval;
+        let mut summaries = Vec::new();
+        self.blocks.retain(|key, (last, suppressed)| {
+            let live = last.elapsed() < interval || keep == Some(key.as_str());
+            if !live && *suppressed > 0 {
+                summaries.push(format!("rate limit blocked key={} suppressed={}", key, suppressed));
+            }
+            live
+        });
+        for message in summaries {
+            self.logger.log(Level::Warn, &message);
+        }
+    }
+}
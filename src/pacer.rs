@@ -0,0 +1,131 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use crate::cache::in_memory::InMemoryCache;
+use crate::limiter::{CacheBackend, RateLimiter, Strategy};
+
+/// How long to wait before checking again when a blocked decision carries no
+/// retry time.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Why `Pacer::pace` returned without a slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaceError {
+    /// `ShutdownHandle::shutdown` was called.
+    Shutdown,
+    /// No slot became free within the pacer's maximum wait.
+    TimedOut,
+}
+
+/// Wakes every `pace` call waiting on a pacer, for graceful shutdown.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl ShutdownHandle {
+    /// Makes current and future `pace` calls return `PaceError::Shutdown`.
+    pub fn shutdown(&self) {
+        let (stopped, wake) = &*self.state;
+        *stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = true;
+        wake.notify_all();
+    }
+
+    /// Returns whether `shutdown` has been called.
+    pub fn is_shutdown(&self) -> bool {
+        *self.state.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Sleeps for `timeout` or until shutdown. Returns whether shut down.
+    fn wait(&self, timeout: Duration) -> bool {
+        let (stopped, wake) = &*self.state;
+        let guard = stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (guard, _) = wake
+            .wait_timeout_while(guard, timeout, |stopped| !*stopped)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *guard
+    }
+}
+
+/// Paces a queue consumer: call `pace` with the topic or partition key
+/// before processing each message, and it blocks until the message fits
+/// the rate.
+///
+/// Waits end early on shutdown, and after the maximum wait if one is set, so
+/// a consumer never hangs on a stalled limit.
+pub struct Pacer<B: CacheBackend> {
+    limiter: RateLimiter<B>,
+    shutdown: ShutdownHandle,
+    max_wait: Option<Duration>,
+}
+
+impl Pacer<InMemoryCache> {
+    /// Paces each key to `rate` messages per second on average, allowing
+    /// bursts of up to `burst` messages after a quiet period.
+    ///
+    /// Returns an error if `rate` is not a positive number or is so low that
+    /// a burst would take longer than a `Duration` can hold.
+    pub fn per_second(rate: f64, burst: u32) -> Result<Self, String> {
+        if rate.is_nan() || rate <= 0.0 {
+            return Err(format!("rate must be positive, got {}", rate));
+        }
+        let burst = burst.max(1);
+        let period = Duration::try_from_secs_f64(f64::from(burst) / rate)
+            .map_err(|_| format!("rate {} is too low to pace", rate))?;
+        let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), burst, period)
+            .with_strategy(Strategy::LocalLockFree);
+        Ok(Pacer::new(limiter))
+    }
+}
+
+impl<B: CacheBackend> Pacer<B> {
+    /// Paces with `limiter`, e.g. one on a shared backend so consumers in
+    /// several processes share a rate.
+    pub fn new(limiter: RateLimiter<B>) -> Self {
+        Pacer {
+            limiter,
+            shutdown: ShutdownHandle::default(),
+            max_wait: None,
+        }
+    }
+
+    /// Makes `pace` give up with `PaceError::TimedOut` after waiting `max_wait`.
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = Some(max_wait);
+        self
+    }
+
+    /// Returns a handle that stops all waiting and future `pace` calls.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Returns the limiter doing the pacing.
+    pub fn limiter(&self) -> &RateLimiter<B> {
+        &self.limiter
+    }
+
+    /// Blocks until a message for `key` may be processed.
+    pub fn pace(&self, key: &str) -> Result<(), PaceError> {
+        let deadline = self.max_wait.map(|max_wait| Instant::now() + max_wait);
+        loop {
+            if self.shutdown.is_shutdown() {
+                return Err(PaceError::Shutdown);
+            }
+            let decision = self.limiter.check(key);
+            if decision.allowed {
+                return Ok(());
+            }
+            let mut wait = decision.retry_after.unwrap_or(POLL_INTERVAL);
+            if let Some(deadline) = deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(PaceError::TimedOut);
+                }
+                wait = wait.min(left);
+            }
+            if self.shutdown.wait(wait) {
+                return Err(PaceError::Shutdown);
+            }
+        }
+    }
+}
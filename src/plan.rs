@@ -0,0 +1,226 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+use crate::composite::KeyedPolicy;
+use crate::limiter::{CacheBackend, RateLimitDecision, RateLimiter};
+use crate::priority::Priority;
+use crate::rate::Rate;
+use crate::route::RouteTable;
+
+/// A pricing tier, such as free, pro or enterprise, and the limits that
+/// come with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    /// Name of the plan, e.g. `"pro"`.
+    pub name: String,
+    /// Requests allowed per window of the limiter.
+    pub limit: u32,
+    /// Extra requests allowed per window on top of every limit of the plan.
+    pub burst: u32,
+    /// The priority band the plan's requests are checked in.
+    pub priority: Priority,
+    policy_limits: Vec<(String, u32, Option<Duration>)>,
+}
+
+impl Plan {
+    /// A plan allowing `limit` requests per window, with no burst, at
+    /// `Priority::Normal`.
+    pub fn new(name: &str, limit: u32) -> Self {
+        Plan {
+            name: name.to_string(),
+            limit,
+            burst: 0,
+            priority: Priority::Normal,
+            policy_limits: Vec::new(),
+        }
+    }
+
+    /// Allows `burst` extra requests per window.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// Checks the plan's requests at `priority`.
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Overrides the limit of the route policy named `policy` (see
+    /// `RouteTable`) for this plan, keeping the route's window.
+    pub fn with_policy_limit(self, policy: &str, limit: u32) -> Self {
+        self.with_policy_override(policy, limit, None)
+    }
+
+    /// Overrides the route policy named `policy` with `rate` for this plan,
+    /// window included.
+    pub fn with_policy_rate(self, policy: &str, rate: Rate) -> Self {
+        self.with_policy_override(policy, rate.limit, Some(rate.window))
+    }
+
+    fn with_policy_override(mut self, policy: &str, limit: u32, window: Option<Duration>) -> Self {
+        self.policy_limits.retain(|(name, _, _)| name != policy);
+        self.policy_limits.push((policy.to_string(), limit, window));
+        self
+    }
+
+    /// Returns the plan's limit for the route policy named `policy`, if it
+    /// overrides it.
+    pub fn policy_limit(&self, policy: &str) -> Option<u32> {
+        self.policy_limits
+            .iter()
+            .find(|(name, _, _)| name == policy)
+            .map(|(_, limit, _)| *limit)
+    }
+
+    /// Returns the plan's rate for the route policy named `policy`, if it
+    /// overrides it, with `window` standing in for an unchanged window.
+    pub fn policy_rate(&self, policy: &str, window: Duration) -> Option<Rate> {
+        self.policy_limits
+            .iter()
+            .find(|(name, _, _)| name == policy)
+            .map(|(_, limit, own)| Rate::new(*limit, own.unwrap_or(window)))
+    }
+}
+
+/// How many plans are cached between purges of expired ones.
+const PURGE_EVERY: u64 = 1024;
+
+/// Looks up the plan of an API key, e.g. in the billing database.
+///
+/// Any `Fn(&str) -> Option<Arc<Plan>>` is a resolver.
+pub trait PlanResolver: Send + Sync {
+    /// Returns the plan of `api_key`, or `None` if it has none.
+    fn plan(&self, api_key: &str) -> Option<Arc<Plan>>;
+}
+
+impl<F> PlanResolver for F
+where
+    F: Fn(&str) -> Option<Arc<Plan>> + Send + Sync,
+{
+    fn plan(&self, api_key: &str) -> Option<Arc<Plan>> {
+        self(api_key)
+    }
+}
+
+/// Applies each API key's plan: its limit, burst allowance, priority and
+/// per-route limits.
+///
+/// Plans are resolved through a `PlanResolver` and cached per key, so the
+/// resolver is not called on every request; a key's new plan takes effect
+/// when its cache entry expires or is invalidated. Keys without a plan get
+/// the wrapped limiter's own limit. Counters belong to the key rather than
+/// the plan, so an upgrade raises the limit without resetting the count.
+/// Expired entries are purged every 1024 lookups that reach the resolver,
+/// so random API keys cannot grow the cache without bound. The cache is
+/// keyed the way the limiter stores client keys, hashed if it has a
+/// `KeyHasher`.
+pub struct PlanLimiter<B: CacheBackend> {
+    limiter: RateLimiter<B>,
+    resolver: Box<dyn PlanResolver>,
+    cache_ttl: Duration,
+    plans: DashMap<String, (Option<Arc<Plan>>, Instant)>,
+    resolved: AtomicU64,
+}
+
+impl<B: CacheBackend> PlanLimiter<B> {
+    /// Applies plans from `resolver` to `limiter`, caching them for a minute.
+    pub fn new<R: PlanResolver + 'static>(limiter: RateLimiter<B>, resolver: R) -> Self {
+        PlanLimiter {
+            limiter,
+            resolver: Box::new(resolver),
+            cache_ttl: Duration::from_secs(60),
+            plans: DashMap::new(),
+            resolved: AtomicU64::new(0),
+        }
+    }
+
+    /// Caches resolved plans, including "no plan", for `ttl`.
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Returns the wrapped limiter.
+    pub fn limiter(&self) -> &RateLimiter<B> {
+        &self.limiter
+    }
+
+    /// Returns the plan of `api_key`, from the cache if it is fresh.
+    pub fn plan(&self, api_key: &str) -> Option<Arc<Plan>> {
+        let now = Instant::now();
+        let stored = self.limiter.client_key(api_key);
+        if let Some(cached) = self.plans.get(stored.as_ref()) {
+            if cached.1 > now {
+                return cached.0.clone();
+            }
+        }
+        if self.resolved.fetch_add(1, Ordering::Relaxed) % PURGE_EVERY == PURGE_EVERY - 1 {
+            self.purge_expired();
+        }
+        let plan = self.resolver.plan(api_key);
+        self.plans.insert(stored.into_owned(), (plan.clone(), now + self.cache_ttl));
+        plan
+    }
+
+    /// Forgets the cached plan of `api_key`, e.g. right after it upgrades.
+    pub fn invalidate(&self, api_key: &str) {
+        self.plans.remove(self.limiter.client_key(api_key).as_ref());
+    }
+
+    /// Forgets cached plans that have expired. Lookups call it every 1024
+    /// resolver calls.
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        self.plans.retain(|_, cached| cached.1 > now);
+    }
+
+    /// Returns how many API keys have a cached plan, fresh or expired.
+    pub fn cached_plans(&self) -> usize {
+        self.plans.len()
+    }
+
+    /// Checks whether a request with `api_key` is allowed by its plan.
+    pub fn allow(&self, api_key: &str) -> bool {
+        self.check(api_key).allowed
+    }
+
+    /// Checks a request with `api_key` against its plan's limit plus burst,
+    /// in the plan's priority band.
+    pub fn check(&self, api_key: &str) -> RateLimitDecision {
+        match self.plan(api_key) {
+            Some(plan) => self.limiter.check_with_limit(
+                api_key,
+                plan.limit.saturating_add(plan.burst),
+                plan.priority,
+            ),
+            None => self.limiter.check(api_key),
+        }
+    }
+
+    /// Checks a request with `api_key` against the route matching `method`
+    /// and `path`, using the plan's limit or rate for that route's policy if
+    /// it sets one, plus its burst. See `RateLimiter::check_route`.
+    pub fn check_route(
+        &self,
+        routes: &RouteTable,
+        method: &str,
+        path: &str,
+        api_key: &str,
+    ) -> RateLimitDecision {
+        let Some(route) = routes.find(method, path) else {
+            return self.check(api_key);
+        };
+        let Some(plan) = self.plan(api_key) else {
+            return self.limiter.check_route(routes, method, path, api_key);
+        };
+        let rate = plan
+            .policy_rate(&route.policy.name, route.policy.window)
+            .unwrap_or(route.policy.rate());
+        let rate = Rate::new(rate.limit.saturating_add(plan.burst), rate.window);
+        let mut decision = self.limiter.check_all(&[KeyedPolicy::new(&route.policy.name, api_key, rate)]);
+        decision.decisions.remove(0)
+    }
+}
@@ -0,0 +1,38 @@
+/// Priority class of a request. When quota is reserved for higher
+/// priorities, lower ones are shed first as a key approaches its limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum Priority {
+    /// Traffic that may be shed first (prefetches, analytics, retries).
+    BestEffort,
+    /// Regular traffic. Used when no priority is given.
+    #[default]
+    Normal,
+    /// Traffic that must get through while any quota is left.
+    Critical,
+}
+
+/// Quota held back from lower priorities within each window.
+///
+/// With a limit of 100, `critical: 10` and `normal: 20`, best-effort requests
+/// are admitted up to a count of 70, normal requests up to 90, and critical
+/// requests up to the full 100.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PriorityReserves {
+    /// Requests reserved exclusively for `Critical` traffic.
+    pub critical: u32,
+    /// Requests reserved for `Normal` and `Critical` traffic.
+    pub normal: u32,
+}
+
+impl PriorityReserves {
+    /// Returns the highest count a request of `priority` may reach under `limit`.
+    pub fn ceiling(&self, limit: u32, priority: Priority) -> u32 {
+        match priority {
+            Priority::Critical => limit,
+            Priority::Normal => limit.saturating_sub(self.critical),
+            Priority::BestEffort => limit
+                .saturating_sub(self.critical)
+                .saturating_sub(self.normal),
+        }
+    }
+}
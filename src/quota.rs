@@ -0,0 +1,164 @@
+use std::sync::Arc;
+use std::time::Duration;
+use crate::calendar::{CalendarWindows, Period};
+use crate::hashing::KeyHasher;
+use crate::limiter::CacheBackend;
+use crate::rate::Rate;
+
+/// A subject's consumption of its quota in the current period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    /// Whether the request was allowed. Always `true` for `usage`.
+    pub allowed: bool,
+    /// Units consumed so far this period.
+    pub used: u32,
+    /// Units available this period: the base quota plus carry-over.
+    pub allowance: u32,
+    /// Units carried over from the previous period.
+    pub carried_over: u32,
+    /// Units left this period.
+    pub remaining: u32,
+    /// Time until the period ends.
+    pub resets_in: Duration,
+}
+
+/// Long-horizon quotas per calendar day or month, such as "100,000 API
+/// calls per month", with optional carry-over of unused quota.
+///
+/// Counts are kept in the backend under a key per period, and each period's
+/// count is kept into the next so carry-over can be computed. With a
+/// persistent backend (e.g. Redis with AOF), consumption survives restarts;
+/// with `InMemoryCache` it does not.
+pub struct QuotaTracker<B: CacheBackend> {
+    /// The backend storing period counts.
+    pub cache: Arc<B>,
+    /// Base units per period.
+    pub quota: u32,
+    /// The calendar period.
+    pub period: Period,
+    carry_over_percent: u32,
+    calendar: CalendarWindows,
+    hasher: Option<KeyHasher>,
+}
+
+impl<B: CacheBackend> QuotaTracker<B> {
+    /// Constructs a new QuotaTracker.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - A caching backend instance wrapped in `Arc`.
+    /// * `quota` - Base units allowed per period.
+    /// * `period` - The calendar period, in UTC unless `with_calendar` says otherwise.
+    pub fn new(cache: Arc<B>, quota: u32, period: Period) -> Self {
+        QuotaTracker {
+            cache,
+            quota,
+            period,
+            carry_over_percent: 0,
+            calendar: CalendarWindows::new(),
+            hasher: None,
+        }
+    }
+
+    /// Constructs a QuotaTracker allowing `rate` per calendar day, e.g.
+    /// `"10000/d"`. Monthly quotas have no fixed window; use `new` with
+    /// `Period::Month` for those.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `rate`'s window is not one day.
+    pub fn from_rate(cache: Arc<B>, rate: Rate) -> Result<Self, String> {
+        if rate.window != Period::Day.max_length() {
+            return Err(format!("quota rate `{}` must be per day", rate));
+        }
+        Ok(QuotaTracker::new(cache, rate.limit, Period::Day))
+    }
+
+    /// Carries `percent` of the previous period's unused base quota into the
+    /// current one. Carry-over does not compound: only the base quota of the
+    /// previous period counts. A subject with no usage recorded in the
+    /// previous period carries nothing over, so new subjects do not start
+    /// with a bonus.
+    pub fn with_carry_over_percent(mut self, percent: u32) -> Self {
+        self.carry_over_percent = percent.min(100);
+        self
+    }
+
+    /// Sets the timezone and clock periods are computed in.
+    pub fn with_calendar(mut self, calendar: CalendarWindows) -> Self {
+        self.calendar = calendar;
+        self
+    }
+
+    /// Hashes subjects with `hasher` before they reach the backend. See
+    /// `RateLimiter::with_key_hasher`.
+    pub fn with_key_hasher(mut self, hasher: KeyHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Consumes one unit for `subject` if its quota allows.
+    pub fn allow(&self, subject: &str) -> bool {
+        self.consume(subject, 1).allowed
+    }
+
+    /// Consumes `cost` units for `subject` if they all fit in the period's
+    /// allowance. Backend errors block the request, as in `RateLimiter`.
+    pub fn consume(&self, subject: &str, cost: u32) -> QuotaUsage {
+        let (index, resets_in) = self.calendar.current_period(self.period);
+        let carried_over = self.carried_over(subject, index);
+        let allowance = self.quota.saturating_add(carried_over);
+        // Keep the count through the next period for its carry-over.
+        let ttl = resets_in + self.period.max_length();
+        let (allowed, used) = self
+            .cache
+            .incr_within(&self.key(subject, index), cost, allowance, ttl)
+            .unwrap_or((false, 0));
+        QuotaUsage {
+            allowed,
+            used,
+            allowance,
+            carried_over,
+            remaining: allowance.saturating_sub(used),
+            resets_in,
+        }
+    }
+
+    /// Returns `subject`'s period-to-date consumption without consuming.
+    pub fn usage(&self, subject: &str) -> QuotaUsage {
+        let (index, resets_in) = self.calendar.current_period(self.period);
+        let carried_over = self.carried_over(subject, index);
+        let allowance = self.quota.saturating_add(carried_over);
+        let used = self.cache.get(&self.key(subject, index)).unwrap_or(0);
+        QuotaUsage {
+            allowed: true,
+            used,
+            allowance,
+            carried_over,
+            remaining: allowance.saturating_sub(used),
+            resets_in,
+        }
+    }
+
+    fn carried_over(&self, subject: &str, index: i64) -> u32 {
+        if self.carry_over_percent == 0 {
+            return 0;
+        }
+        let Some(used) = self.cache.get(&self.key(subject, index - 1)) else {
+            return 0;
+        };
+        let unused = u64::from(self.quota.saturating_sub(used));
+        (unused * u64::from(self.carry_over_percent) / 100) as u32
+    }
+
+    fn key(&self, subject: &str, index: i64) -> String {
+        let period = match self.period {
+            Period::Day => "day",
+            Period::Month => "month",
+        };
+        match &self.hasher {
+            Some(hasher) => format!("rate_limit_quota:{}:{}:{}", period, index, hasher.hash_hex(subject)),
+            None => format!("rate_limit_quota:{}:{}:{}", period, index, subject),
+        }
+    }
+}
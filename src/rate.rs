@@ -0,0 +1,135 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A limit of `limit` requests per `window`, e.g. for config files:
+/// `"100/5m".parse::<Rate>()` is 100 requests per five minutes.
+///
+/// The text form is `<count>/<window>`, where the window is a duration such
+/// as `500ms`, `10s`, `5m`, `1h` or `1d`, and a bare unit means one of it
+/// (`500/s`, `100/m`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rate {
+    /// Requests allowed per window.
+    pub limit: u32,
+    /// Duration of the window.
+    pub window: Duration,
+}
+
+impl Rate {
+    /// `limit` requests per `window`.
+    pub const fn new(limit: u32, window: Duration) -> Self {
+        Rate { limit, window }
+    }
+
+    /// `limit` requests per second.
+    pub const fn per_second(limit: u32) -> Self {
+        Rate::new(limit, Duration::from_secs(1))
+    }
+
+    /// `limit` requests per minute.
+    pub const fn per_minute(limit: u32) -> Self {
+        Rate::new(limit, Duration::from_secs(60))
+    }
+
+    /// `limit` requests per hour.
+    pub const fn per_hour(limit: u32) -> Self {
+        Rate::new(limit, Duration::from_secs(3600))
+    }
+
+    /// `limit` requests per day.
+    pub const fn per_day(limit: u32) -> Self {
+        Rate::new(limit, Duration::from_secs(86_400))
+    }
+
+    /// Returns the average time between requests at this rate.
+    pub fn interval(&self) -> Duration {
+        self.window / self.limit.max(1)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let (count, per) = value
+            .split_once('/')
+            .ok_or_else(|| format!("invalid rate `{}`: expected <count>/<window>", value))?;
+        let limit = count
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid rate `{}`: bad count", value))?;
+        let per = per.trim();
+        let window = if per.starts_with(|c: char| c.is_ascii_digit()) {
+            parse_duration(per)?
+        } else {
+            parse_duration(&format!("1{}", per))?
+        };
+        if window.is_zero() {
+            return Err(format!("invalid rate `{}`: empty window", value));
+        }
+        Ok(Rate { limit, window })
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.window.as_millis();
+        let (amount, unit) = [(86_400_000, "d"), (3_600_000, "h"), (60_000, "m"), (1000, "s")]
+            .into_iter()
+            .find(|(length, _)| millis.is_multiple_of(*length))
+            .map_or((millis, "ms"), |(length, unit)| (millis / length, unit));
+        if amount == 1 {
+            write!(f, "{}/{}", self.limit, unit)
+        } else {
+            write!(f, "{}/{}{}", self.limit, amount, unit)
+        }
+    }
+}
+
+/// Parses durations such as `500ms`, `10s`, `15m`, `1h` or `7d`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in duration: {}", value))?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration: {}", value))?;
+    let seconds = |per_unit: u64| {
+        amount
+            .checked_mul(per_unit)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("duration too long: {}", value))
+    };
+    match unit {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "s" => seconds(1),
+        "m" => seconds(60),
+        "h" => seconds(3600),
+        "d" => seconds(86_400),
+        _ => Err(format!("invalid duration unit: {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::Rate;
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!("100/5m".parse(), Ok(Rate::new(100, Duration::from_secs(300))));
+        assert_eq!("500/s".parse(), Ok(Rate::per_second(500)));
+        assert_eq!("1000 / 1d".parse(), Ok(Rate::per_day(1000)));
+        assert_eq!("20/250ms".parse(), Ok(Rate::new(20, Duration::from_millis(250))));
+        assert!("100".parse::<Rate>().is_err());
+        assert!("100/0s".parse::<Rate>().is_err());
+        assert!("100/5x".parse::<Rate>().is_err());
+        assert!("many/s".parse::<Rate>().is_err());
+
+        for text in ["100/5m", "500/s", "10/h", "20/250ms", "7/90s"] {
+            assert_eq!(text.parse::<Rate>().unwrap().to_string(), text);
+        }
+    }
+}
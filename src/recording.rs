@@ -0,0 +1,223 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::audit::hash_key;
+use crate::clock::{Clock, SystemClock};
+use crate::simulate::{Trace, TraceEvent};
+use crate::telemetry::{DecisionEvent, DecisionObserver};
+
+/// A decision captured by a `TraceRecorder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedDecision {
+    /// When the decision was made, to the millisecond.
+    pub timestamp: SystemTime,
+    /// `hash_key` of the client key the limiter reported (already hashed if
+    /// the limiter has a `KeyHasher`).
+    pub key_hash: u64,
+    /// Units the request consumed or would have consumed.
+    pub cost: u32,
+    /// Whether the request was allowed.
+    pub allowed: bool,
+    /// The count after the request.
+    pub count: u32,
+    /// The limit applied.
+    pub limit: u32,
+    /// Version of the policy that decided.
+    pub policy_version: u32,
+}
+
+impl RecordedDecision {
+    /// Formats the decision as a single JSON object, without a trailing newline.
+    pub fn to_json(&self) -> String {
+        let millis = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis())
+            .unwrap_or(0);
+        format!(
+            "{{\"timestamp_ms\":{},\"key_hash\":\"{:016x}\",\"cost\":{},\"allowed\":{},\"count\":{},\"limit\":{},\"policy_version\":{}}}",
+            millis, self.key_hash, self.cost, self.allowed, self.count, self.limit, self.policy_version
+        )
+    }
+
+    /// Parses a line written by `to_json`.
+    pub fn from_json(line: &str) -> Result<Self, String> {
+        let body = line
+            .trim()
+            .strip_prefix('{')
+            .and_then(|body| body.strip_suffix('}'))
+            .ok_or_else(|| format!("not a JSON object: {}", line))?;
+        let field = |name: &str| -> Result<&str, String> {
+            body.split(',')
+                .filter_map(|pair| pair.split_once(':'))
+                .find(|(key, _)| key.trim().trim_matches('"') == name)
+                .map(|(_, value)| value.trim().trim_matches('"'))
+                .ok_or_else(|| format!("missing field `{}`", name))
+        };
+        let number = |name: &str| -> Result<u32, String> {
+            field(name)?.parse().map_err(|_| format!("invalid `{}`", name))
+        };
+        let millis: u64 = field("timestamp_ms")?
+            .parse()
+            .map_err(|_| "invalid `timestamp_ms`".to_string())?;
+        Ok(RecordedDecision {
+            timestamp: UNIX_EPOCH + Duration::from_millis(millis),
+            key_hash: u64::from_str_radix(field("key_hash")?, 16)
+                .map_err(|_| "invalid `key_hash`".to_string())?,
+            cost: number("cost")?,
+            allowed: field("allowed")?
+                .parse()
+                .map_err(|_| "invalid `allowed`".to_string())?,
+            count: number("count")?,
+            limit: number("limit")?,
+            policy_version: number("policy_version")?,
+        })
+    }
+}
+
+/// Records decisions as JSON lines for later investigation and replay.
+/// Register it with `RateLimiter::with_observer`.
+///
+/// Keys are sampled rather than requests, so every decision for a sampled
+/// key is kept and its history can be replayed in full.
+pub struct TraceRecorder<W: Write + Send> {
+    writer: Mutex<W>,
+    every: u64,
+    clock: Arc<dyn Clock>,
+    write_errors: AtomicU64,
+}
+
+impl TraceRecorder<BufWriter<File>> {
+    /// Opens `path` for appending, creating it if needed. Lines are
+    /// buffered, so call `flush` periodically and before exiting to get them
+    /// onto disk; dropping the recorder flushes too.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(TraceRecorder::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write + Send> TraceRecorder<W> {
+    /// Records every decision to `writer`.
+    pub fn new(writer: W) -> Self {
+        TraceRecorder {
+            writer: Mutex::new(writer),
+            every: 1,
+            clock: Arc::new(SystemClock),
+            write_errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Records only about one in every `every` keys.
+    pub fn with_sampled_keys(mut self, every: u64) -> Self {
+        self.every = every.max(1);
+        self
+    }
+
+    /// Sets the clock used to timestamp decisions.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns how many decisions could not be written.
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors.load(Ordering::Relaxed)
+    }
+
+    /// Flushes buffered lines to the writer.
+    pub fn flush(&self) -> Result<(), String> {
+        let mut writer = self.writer.lock().map_err(|_| "trace recorder poisoned".to_string())?;
+        writer.flush().map_err(|err| err.to_string())
+    }
+
+    /// Returns the writer, e.g. to inspect an in-memory trace.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<W: Write + Send> DecisionObserver for TraceRecorder<W> {
+    fn observe(&self, event: &DecisionEvent<'_>) {
+        let key_hash = hash_key(event.key);
+        if !key_hash.is_multiple_of(self.every) {
+            return;
+        }
+        let mut line = RecordedDecision {
+            timestamp: self.clock.now(),
+            key_hash,
+            cost: event.cost,
+            allowed: event.allowed,
+            count: event.count,
+            limit: event.limit,
+            policy_version: event.policy_version,
+        }
+        .to_json();
+        line.push('\n');
+        // One write per line, so concurrent writers never interleave records.
+        let written = match self.writer.lock() {
+            Ok(mut writer) => writer.write_all(line.as_bytes()).is_ok(),
+            Err(_) => false,
+        };
+        if !written {
+            self.write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Reads decisions written by a `TraceRecorder`, skipping blank lines.
+pub fn read_trace<R: BufRead>(reader: R) -> Result<Vec<RecordedDecision>, String> {
+    reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(number, line)| {
+            let line = line.map_err(|err| err.to_string())?;
+            RecordedDecision::from_json(&line).map_err(|err| format!("line {}: {}", number + 1, err))
+        })
+        .collect()
+}
+
+impl Trace {
+    /// Builds a trace from recorded decisions, timed from the earliest one
+    /// and keyed by key hash, to replay them through `simulate`.
+    pub fn from_recorded(records: &[RecordedDecision]) -> Self {
+        let Some(start) = records.iter().map(|record| record.timestamp).min() else {
+            return Trace::new();
+        };
+        Trace::from_events(
+            records
+                .iter()
+                .map(|record| TraceEvent {
+                    at: record.timestamp.duration_since(start).unwrap_or_default(),
+                    key: format!("{:016x}", record.key_hash),
+                    cost: record.cost,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::RecordedDecision;
+
+    #[test]
+    fn test_json_round_trip() {
+        let record = RecordedDecision {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            key_hash: 0xdead_beef,
+            cost: 3,
+            allowed: false,
+            count: 10,
+            limit: 10,
+            policy_version: 2,
+        };
+        assert_eq!(RecordedDecision::from_json(&record.to_json()), Ok(record));
+        assert!(RecordedDecision::from_json("{\"cost\":1}").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+use crate::limiter::{CacheBackend, RateLimitDecision, RateLimiter};
+
+/// Quota tentatively consumed by `RateLimiter::reserve` for a long-running
+/// operation.
+///
+/// Call `commit` once the operation succeeds to keep the quota, or `cancel`
+/// to refund it. A reservation dropped without either is cancelled, and one
+/// committed after its timeout is cancelled instead, so an abandoned
+/// operation does not hold quota. A reservation still held past its timeout
+/// is cancelled by the next check of its key, so a forgotten one does not
+/// hold quota either. Cancelling after the reserved window has expired
+/// refunds nothing, since the units left with that window.
+///
+/// Reservations live in the process that made them. If that process exits
+/// first, the reserved units are released when the window expires.
+pub struct Reservation<'a, B: CacheBackend> {
+    limiter: &'a RateLimiter<B>,
+    ip: String,
+    cost: u32,
+    decision: RateLimitDecision,
+    pending: Option<Arc<Pending>>,
+    settled: bool,
+}
+
+impl<'a, B: CacheBackend> Reservation<'a, B> {
+    /// Cancels the reservation if it is not committed within `timeout`,
+    /// at the latest on the first check of its key after that.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.forget();
+        let pending = Arc::new(Pending {
+            cost: self.cost,
+            checked_at: self.decision.checked_at,
+            deadline: Instant::now() + timeout,
+            settled: AtomicBool::new(false),
+        });
+        self.limiter
+            .reservations
+            .register(self.limiter.client_key(&self.ip).into_owned(), Arc::clone(&pending));
+        self.pending = Some(pending);
+        self
+    }
+
+    /// Returns the decision made when the quota was reserved.
+    pub fn decision(&self) -> &RateLimitDecision {
+        &self.decision
+    }
+
+    /// Returns whether the timeout has passed.
+    pub fn is_expired(&self) -> bool {
+        self.pending.as_ref().is_some_and(|pending| Instant::now() >= pending.deadline)
+    }
+
+    /// Keeps the reserved quota. Fails, refunding the quota, if the
+    /// timeout has passed.
+    pub fn commit(mut self) -> Result<(), String> {
+        if self.is_expired() {
+            self.release()?;
+            return Err("reservation expired".to_string());
+        }
+        self.settled = true;
+        if !self.forget() {
+            // A check expired it between the test above and now.
+            return Err("reservation expired".to_string());
+        }
+        Ok(())
+    }
+
+    /// Refunds the reserved quota. Returns the IP's count afterwards.
+    pub fn cancel(mut self) -> Result<u32, String> {
+        self.release()
+    }
+
+    fn release(&mut self) -> Result<u32, String> {
+        self.settled = true;
+        if !self.forget() {
+            // A check already expired and refunded it.
+            return Ok(self.limiter.usage(&self.ip).count);
+        }
+        self.limiter.refund_window(&self.ip, self.cost, self.decision.checked_at)
+    }
+
+    /// Unregisters the timeout and settles it. Returns `false` if a check
+    /// expired the reservation first.
+    fn forget(&mut self) -> bool {
+        let Some(pending) = self.pending.take() else {
+            return true;
+        };
+        self.limiter
+            .reservations
+            .remove(&self.limiter.client_key(&self.ip), &pending);
+        pending.settle()
+    }
+}
+
+impl<B: CacheBackend> Drop for Reservation<'_, B> {
+    fn drop(&mut self) {
+        if !self.settled {
+            let _ = self.release();
+        }
+    }
+}
+
+impl<B: CacheBackend> RateLimiter<B> {
+    /// Tentatively consumes `cost` units for the given IP, as `check_n`
+    /// does, for an operation that may still fail or be abandoned. Returns
+    /// the blocking decision if the units do not fit.
+    pub fn reserve(&self, ip: &str, cost: u32) -> Result<Reservation<'_, B>, RateLimitDecision> {
+        let decision = self.check_n(ip, cost);
+        if !decision.allowed {
+            return Err(decision);
+        }
+        Ok(Reservation {
+            limiter: self,
+            ip: ip.to_string(),
+            cost,
+            decision,
+            pending: None,
+            settled: false,
+        })
+    }
+
+    /// Cancels the reservations for the given IP whose timeout has passed.
+    pub(crate) fn expire_reservations(&self, ip: &str) {
+        if self.reservations.is_empty() {
+            return;
+        }
+        for pending in self.reservations.take_expired(&self.client_key(ip), Instant::now()) {
+            let _ = self.refund_window(ip, pending.cost, pending.checked_at);
+        }
+    }
+}
+
+/// A reservation with a timeout, shared with the limiter so a check of its
+/// key can expire it.
+pub(crate) struct Pending {
+    cost: u32,
+    checked_at: Instant,
+    deadline: Instant,
+    settled: AtomicBool,
+}
+
+impl Pending {
+    /// Marks the reservation settled. Returns `false` if it already was.
+    fn settle(&self) -> bool {
+        !self.settled.swap(true, Ordering::AcqRel)
+    }
+}
+
+/// The reservations with a timeout, by stored client key.
+#[derive(Default)]
+pub(crate) struct PendingReservations {
+    keys: DashMap<String, Vec<Arc<Pending>>>,
+    count: AtomicUsize,
+}
+
+impl PendingReservations {
+    fn is_empty(&self) -> bool {
+        self.count.load(Ordering::Relaxed) == 0
+    }
+
+    fn register(&self, key: String, pending: Arc<Pending>) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.keys.entry(key).or_default().push(pending);
+    }
+
+    fn remove(&self, key: &str, pending: &Arc<Pending>) {
+        if let Some(mut held) = self.keys.get_mut(key) {
+            let before = held.len();
+            held.retain(|other| !Arc::ptr_eq(other, pending));
+            self.count.fetch_sub(before - held.len(), Ordering::Relaxed);
+        }
+        self.keys.remove_if(key, |_, held| held.is_empty());
+    }
+
+    /// Removes the reservations for `key` whose timeout has passed by `now`,
+    /// returning those this call settled.
+    fn take_expired(&self, key: &str, now: Instant) -> Vec<Arc<Pending>> {
+        let expired: Vec<Arc<Pending>> = match self.keys.get_mut(key) {
+            Some(mut held) => {
+                let (expired, live) = held.drain(..).partition(|pending| pending.deadline <= now);
+                *held = live;
+                expired
+            }
+            None => return Vec::new(),
+        };
+        self.count.fetch_sub(expired.len(), Ordering::Relaxed);
+        self.keys.remove_if(key, |_, held| held.is_empty());
+        expired.into_iter().filter(|pending| pending.settle()).collect()
+    }
+}
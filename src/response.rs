@@ -0,0 +1,45 @@
+use crate::limiter::{CacheBackend, RateLimitDecision, RateLimiter};
+
+/// Decides from a response status whether a request keeps the quota it
+/// consumed.
+///
+/// Any `Fn(u16) -> bool` is a response filter: `|status| status == 401`
+/// counts only failed logins, `|status| status < 500` refunds server errors.
+pub trait ResponseFilter: Send + Sync {
+    /// Returns `true` if a response with `status` should count.
+    fn counts(&self, status: u16) -> bool;
+}
+
+impl<F> ResponseFilter for F
+where
+    F: Fn(u16) -> bool + Send + Sync,
+{
+    fn counts(&self, status: u16) -> bool {
+        self(status)
+    }
+}
+
+impl<B: CacheBackend> RateLimiter<B> {
+    /// Post-response hook: refunds the `cost` consumed by the allowed
+    /// request `decision` from the given IP unless `filter` says a response
+    /// with `status` counts. Returns whether the quota was refunded.
+    ///
+    /// Call it once the response is known, for requests that were allowed.
+    /// The refund only reaches the window the request was counted in; if
+    /// that window has expired, nothing is refunded. Invalid keys the
+    /// invalid key policy let through without counting have nothing to
+    /// refund.
+    pub fn on_response(
+        &self,
+        ip: &str,
+        decision: &RateLimitDecision,
+        cost: u32,
+        status: u16,
+        filter: &impl ResponseFilter,
+    ) -> bool {
+        if !decision.allowed || filter.counts(status) || self.validate_key(ip).is_err() {
+            return false;
+        }
+        self.refund_window(ip, cost, decision.checked_at).is_ok()
+    }
+}
@@ -0,0 +1,202 @@
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use crate::clock::{Clock, SystemClock};
+
+/// How a policy change reaches keys that already have a live window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollout {
+    /// The new limit applies at once. Counters start again from zero under
+    /// the new version, so no key is blocked by counts made under the old one.
+    Immediate,
+    /// Each key finishes its current window under the old version and limit,
+    /// and moves to the new policy when its next window starts.
+    NextWindow,
+    /// Like `NextWindow`, but the limit of new windows also moves linearly
+    /// from the old limit to the new one over `over`.
+    Gradual { over: Duration },
+}
+
+/// A policy being phased out after a change.
+struct Previous {
+    version: u32,
+    tag: String,
+    limit: u32,
+    changed_at: SystemTime,
+    rollout: Rollout,
+}
+
+struct Versions {
+    version: u32,
+    tag: String,
+    limit: u32,
+    previous: Option<Previous>,
+}
+
+/// Which policy a single check is made under.
+pub(crate) struct Resolved<'a> {
+    pub(crate) version: u32,
+    /// Key prefix identifying the policy version; empty for version 0.
+    pub(crate) tag: &'a str,
+    pub(crate) limit: u32,
+}
+
+/// The versioned limit policy of a limiter, and any rollout in progress.
+pub(crate) struct PolicyState {
+    versions: RwLock<Versions>,
+    clock: Arc<dyn Clock>,
+}
+
+impl PolicyState {
+    pub(crate) fn new(limit: u32) -> Self {
+        PolicyState {
+            versions: RwLock::new(Versions {
+                version: 0,
+                tag: String::new(),
+                limit,
+                previous: None,
+            }),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sets the version of the current policy without starting a rollout.
+    pub(crate) fn set_version(&mut self, version: u32) {
+        if let Ok(versions) = self.versions.get_mut() {
+            versions.version = version;
+            versions.tag = tag_for(version);
+        }
+    }
+
+    pub(crate) fn version(&self) -> u32 {
+        self.versions.read().map(|versions| versions.version).unwrap_or(0)
+    }
+
+    /// Returns the key prefix of the current policy version.
+    pub(crate) fn tag(&self) -> String {
+        self.versions.read().map(|versions| versions.tag.clone()).unwrap_or_default()
+    }
+
+    pub(crate) fn limit(&self) -> u32 {
+        self.versions.read().map(|versions| versions.limit).unwrap_or(0)
+    }
+
+    /// Replaces the current policy, phasing out the old one per `rollout`.
+    pub(crate) fn update(&self, version: u32, limit: u32, rollout: Rollout) {
+        let Ok(mut versions) = self.versions.write() else {
+            return;
+        };
+        let previous = Previous {
+            version: versions.version,
+            tag: std::mem::replace(&mut versions.tag, tag_for(version)),
+            limit: versions.limit,
+            changed_at: self.clock.now(),
+            rollout,
+        };
+        versions.version = version;
+        versions.limit = limit;
+        versions.previous = match rollout {
+            Rollout::Immediate => None,
+            Rollout::NextWindow | Rollout::Gradual { .. } => Some(previous),
+        };
+    }
+
+    /// Runs `check` under the policy that applies to a key whose windows last
+    /// `window`. `limit` is the limit the current policy would apply, which
+    /// may differ from the policy's own limit when a schedule is in force.
+    /// `in_window` reports whether the key has a live window under a tag.
+    pub(crate) fn resolve<T>(
+        &self,
+        limit: u32,
+        window: Duration,
+        in_window: impl Fn(&str) -> bool,
+        check: impl FnOnce(Resolved<'_>) -> T,
+    ) -> T {
+        let Ok(versions) = self.versions.read() else {
+            return check(Resolved {
+                version: 0,
+                tag: "",
+                limit,
+            });
+        };
+        let Some(previous) = &versions.previous else {
+            return check(Resolved {
+                version: versions.version,
+                tag: &versions.tag,
+                limit,
+            });
+        };
+        let elapsed = self
+            .clock
+            .now()
+            .duration_since(previous.changed_at)
+            .unwrap_or_default();
+
+        if elapsed < window && in_window(&previous.tag) {
+            return check(Resolved {
+                version: previous.version,
+                tag: &previous.tag,
+                limit: previous.limit,
+            });
+        }
+        let limit = match previous.rollout {
+            Rollout::Gradual { over } if elapsed < over => {
+                let progress = elapsed.as_secs_f64() / over.as_secs_f64();
+                let ramp = (f64::from(limit) - f64::from(previous.limit)) * progress;
+                (f64::from(previous.limit) + ramp) as u32
+            }
+            _ => limit,
+        };
+        check(Resolved {
+            version: versions.version,
+            tag: &versions.tag,
+            limit,
+        })
+    }
+}
+
+fn tag_for(version: u32) -> String {
+    if version == 0 {
+        String::new()
+    } else {
+        format!("v{}:", version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{PolicyState, Rollout};
+
+    #[test]
+    fn test_gradual_rollout_ramps_limit() {
+        let now = Arc::new(AtomicU64::new(1_000));
+        let clock_now = Arc::clone(&now);
+        let mut policy = PolicyState::new(100);
+        policy.set_clock(Arc::new(move || {
+            UNIX_EPOCH + Duration::from_secs(clock_now.load(Ordering::Relaxed))
+        }));
+        policy.update(2, 20, Rollout::Gradual { over: Duration::from_secs(100) });
+        let window = Duration::from_secs(10);
+        let resolve = |live: bool| {
+            policy.resolve(20, window, |tag| live && tag.is_empty(), |p| {
+                (p.tag.to_string(), p.limit)
+            })
+        };
+
+        // A key with a live window under the old version keeps the old policy.
+        assert_eq!(resolve(true), (String::new(), 100));
+
+        // New windows use the new version, with the limit partway down.
+        now.fetch_add(25, Ordering::Relaxed);
+        assert_eq!(resolve(false), ("v2:".to_string(), 80));
+
+        now.fetch_add(100, Ordering::Relaxed);
+        assert_eq!(resolve(true), ("v2:".to_string(), 20));
+    }
+}
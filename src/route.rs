@@ -0,0 +1,204 @@
+use std::time::Duration;
+use crate::composite::KeyedPolicy;
+use crate::limiter::{CacheBackend, RateLimitDecision, RateLimiter};
+use crate::rate::Rate;
+
+/// One segment of a route pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// Matches exactly this text.
+    Literal(String),
+    /// `:name` matches any single segment and captures it.
+    Param(String),
+    /// `*` matches any single segment.
+    Any,
+    /// `**` matches the rest of the path, including nothing.
+    Rest,
+}
+
+/// The limit applied to requests matching a route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutePolicy {
+    /// Name of the policy. Routes with the same name share a counter per client.
+    pub name: String,
+    /// Maximum allowed requests within `window`.
+    pub limit: u32,
+    /// Duration of the rate limiting window.
+    pub window: Duration,
+}
+
+impl RoutePolicy {
+    /// Returns the policy's limit and window as a `Rate`.
+    pub const fn rate(&self) -> Rate {
+        Rate::new(self.limit, self.window)
+    }
+}
+
+struct Route {
+    method: Option<String>,
+    segments: Vec<Segment>,
+    policy: RoutePolicy,
+}
+
+/// A route matched by `RouteTable::find`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteMatch<'a> {
+    /// The policy of the matching route.
+    pub policy: &'a RoutePolicy,
+    /// Values captured by `:name` segments, in pattern order.
+    pub params: Vec<(&'a str, &'a str)>,
+}
+
+/// Selects a policy by HTTP method and path, so one limiter can apply
+/// different limits per endpoint (e.g. `POST /login` stricter than
+/// `GET /status`).
+///
+/// Patterns are `/`-separated. A segment is literal text, `:name` to match
+/// and capture any one segment, `*` to match any one segment, or a final
+/// `**` to match the rest of the path. Routes are checked in the order they
+/// were added and the first match wins.
+#[derive(Default)]
+pub struct RouteTable {
+    routes: Vec<Route>,
+}
+
+impl RouteTable {
+    /// Creates an empty route table.
+    pub fn new() -> Self {
+        RouteTable { routes: Vec::new() }
+    }
+
+    /// Adds a route.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The HTTP method to match, case-insensitively, or `"*"` for any.
+    /// * `pattern` - The path pattern, e.g. `/users/:id/posts` or `/static/**`.
+    /// * `name` - The policy name, used to key counters.
+    /// * `limit` - Maximum allowed requests within `window`.
+    /// * `window` - Duration of the rate limiting window.
+    pub fn route(
+        mut self,
+        method: &str,
+        pattern: &str,
+        name: &str,
+        limit: u32,
+        window: Duration,
+    ) -> Self {
+        let segments = split(pattern)
+            .map(|segment| match segment {
+                "*" => Segment::Any,
+                "**" => Segment::Rest,
+                _ => match segment.strip_prefix(':') {
+                    Some(param) => Segment::Param(param.to_string()),
+                    None => Segment::Literal(segment.to_string()),
+                },
+            })
+            .collect();
+        self.routes.push(Route {
+            method: (method != "*").then(|| method.to_ascii_uppercase()),
+            segments,
+            policy: RoutePolicy {
+                name: name.to_string(),
+                limit,
+                window,
+            },
+        });
+        self
+    }
+
+    /// Adds a route limited to `rate`. See `route`.
+    pub fn route_rate(self, method: &str, pattern: &str, name: &str, rate: Rate) -> Self {
+        self.route(method, pattern, name, rate.limit, rate.window)
+    }
+
+    /// Returns the first route matching `method` and `path`, if any. A query
+    /// string in `path` is ignored.
+    pub fn find<'a>(&'a self, method: &str, path: &'a str) -> Option<RouteMatch<'a>> {
+        let path = path.split('?').next().unwrap_or_default();
+        self.routes.iter().find_map(|route| {
+            if let Some(expected) = &route.method {
+                if !expected.eq_ignore_ascii_case(method) {
+                    return None;
+                }
+            }
+            let params = matches(&route.segments, path)?;
+            Some(RouteMatch {
+                policy: &route.policy,
+                params,
+            })
+        })
+    }
+}
+
+fn split(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn matches<'a>(pattern: &'a [Segment], path: &'a str) -> Option<Vec<(&'a str, &'a str)>> {
+    let mut params = Vec::new();
+    let mut parts = split(path);
+    for segment in pattern {
+        if *segment == Segment::Rest {
+            return Some(params);
+        }
+        let part = parts.next()?;
+        match segment {
+            Segment::Literal(text) if text != part => return None,
+            Segment::Param(name) => params.push((name.as_str(), part)),
+            _ => {}
+        }
+    }
+    parts.next().is_none().then_some(params)
+}
+
+impl<B: CacheBackend> RateLimiter<B> {
+    /// Checks a request from `client` against the policy of the first route
+    /// in `routes` matching `method` and `path`. Requests matching no route
+    /// are checked against the limiter's own limit.
+    ///
+    /// Each route policy counts per client under its own name, as with
+    /// `check_all`, so one limiter serves every route. `client` is validated
+    /// either way, and the invalid key policy applies.
+    pub fn check_route(
+        &self,
+        routes: &RouteTable,
+        method: &str,
+        path: &str,
+        client: &str,
+    ) -> RateLimitDecision {
+        let Some(route) = routes.find(method, path) else {
+            return self.check(client);
+        };
+        let policy = KeyedPolicy::new(&route.policy.name, client, route.policy.rate());
+        let mut decision = self.check_all(&[policy]);
+        decision.decisions.remove(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::RouteTable;
+
+    #[test]
+    fn test_route_matching() {
+        let minute = Duration::from_secs(60);
+        let routes = RouteTable::new()
+            .route("POST", "/login", "login", 5, minute)
+            .route("get", "/users/:id/posts/*", "posts", 100, minute)
+            .route("*", "/static/**", "static", 1000, minute);
+
+        assert_eq!(routes.find("post", "/login?next=/").unwrap().policy.name, "login");
+        assert!(routes.find("GET", "/login").is_none());
+
+        let found = routes.find("GET", "/users/42/posts/7").unwrap();
+        assert_eq!(found.policy.name, "posts");
+        assert_eq!(found.params, vec![("id", "42")]);
+        assert!(routes.find("GET", "/users/42/posts").is_none());
+        assert!(routes.find("GET", "/users/42/posts/7/comments").is_none());
+
+        assert_eq!(routes.find("DELETE", "/static/css/site.css").unwrap().policy.name, "static");
+        assert_eq!(routes.find("GET", "/static").unwrap().policy.name, "static");
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::clock::{Clock, SystemClock};
+
+/// Day of the week, in local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    /// Monday through Friday.
+    pub const WORKDAYS: [Weekday; 5] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+    ];
+
+    /// Saturday and Sunday.
+    pub const WEEKEND: [Weekday; 2] = [Weekday::Saturday, Weekday::Sunday];
+
+    /// Every day of the week.
+    pub const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    /// The day before this one.
+    fn previous(self) -> Weekday {
+        Weekday::ALL[(self as usize + 6) % 7]
+    }
+
+    fn from_days_since_epoch(days: i64) -> Weekday {
+        // 1970-01-01 was a Thursday.
+        Weekday::ALL[(days + 3).rem_euclid(7) as usize]
+    }
+}
+
+/// A limit that applies on the given days between `start` and `end`, both
+/// expressed as `(hour, minute)` in local time. A rule whose end is before
+/// its start wraps past midnight: it starts on the given days and runs into
+/// the morning after each of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleRule {
+    pub days: Vec<Weekday>,
+    pub start: (u32, u32),
+    pub end: (u32, u32),
+    pub limit: u32,
+}
+
+impl ScheduleRule {
+    fn matches(&self, day: Weekday, minute_of_day: u32) -> bool {
+        let start = self.start.0 * 60 + self.start.1;
+        let end = self.end.0 * 60 + self.end.1;
+        if start <= end {
+            self.days.contains(&day) && minute_of_day >= start && minute_of_day < end
+        } else {
+            // After midnight, the rule belongs to the day it started on.
+            (self.days.contains(&day) && minute_of_day >= start)
+                || (self.days.contains(&day.previous()) && minute_of_day < end)
+        }
+    }
+}
+
+/// Time-of-day and day-of-week limit profiles, evaluated against an
+/// injectable clock in a fixed UTC offset.
+///
+/// Rules are checked in the order they were added and the first match wins.
+/// When no rule matches, the limiter's configured limit applies.
+#[derive(Clone)]
+pub struct Schedule {
+    rules: Vec<ScheduleRule>,
+    utc_offset_minutes: i32,
+    clock: Arc<dyn Clock>,
+}
+
+impl Schedule {
+    /// Creates an empty schedule in UTC using the system clock.
+    pub fn new() -> Self {
+        Schedule {
+            rules: Vec::new(),
+            utc_offset_minutes: 0,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the timezone as an offset from UTC in minutes (e.g. `-300` for UTC-5).
+    pub fn with_utc_offset_minutes(mut self, minutes: i32) -> Self {
+        self.utc_offset_minutes = minutes;
+        self
+    }
+
+    /// Uses `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Adds a rule applying `limit` on `days` between `start` and `end`.
+    ///
+    /// # Arguments
+    ///
+    /// * `days` - The local weekdays the rule applies on.
+    /// * `start` - Local start time as `(hour, minute)`, inclusive.
+    /// * `end` - Local end time as `(hour, minute)`, exclusive. `(24, 0)` means midnight.
+    /// * `limit` - The limit to apply while the rule matches.
+    pub fn rule(mut self, days: &[Weekday], start: (u32, u32), end: (u32, u32), limit: u32) -> Self {
+        self.rules.push(ScheduleRule {
+            days: days.to_vec(),
+            start,
+            end,
+            limit,
+        });
+        self
+    }
+
+    /// Returns the scheduled limit at `time`, if any rule matches.
+    pub fn limit_at(&self, time: SystemTime) -> Option<u32> {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        let local = secs + i64::from(self.utc_offset_minutes) * 60;
+        let day = Weekday::from_days_since_epoch(local.div_euclid(86_400));
+        let minute_of_day = (local.rem_euclid(86_400) / 60) as u32;
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(day, minute_of_day))
+            .map(|rule| rule.limit)
+    }
+
+    /// Returns the scheduled limit for the clock's current time, if any rule matches.
+    pub fn current_limit(&self) -> Option<u32> {
+        self.limit_at(self.clock.now())
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{Schedule, Weekday};
+
+    #[test]
+    fn test_rules_match_local_time() {
+        let schedule = Schedule::new()
+            .with_utc_offset_minutes(120)
+            .rule(&Weekday::WORKDAYS, (9, 0), (17, 0), 1000)
+            .rule(&Weekday::ALL, (22, 0), (6, 0), 200);
+
+        // Monday 2024-01-01 07:30 UTC is 09:30 at UTC+2.
+        let monday_morning = UNIX_EPOCH + Duration::from_secs(1_704_094_200);
+        assert_eq!(schedule.limit_at(monday_morning), Some(1000));
+
+        // 22:30 UTC is 00:30 local on Tuesday: the overnight rule wraps midnight.
+        let overnight = monday_morning + Duration::from_secs(15 * 3600);
+        assert_eq!(schedule.limit_at(overnight), Some(200));
+
+        // Saturday midday matches no rule.
+        let saturday = monday_morning + Duration::from_secs(5 * 86_400 + 3 * 3600);
+        assert_eq!(schedule.limit_at(saturday), None);
+    }
+
+    #[test]
+    fn test_overnight_rules_run_into_the_next_day() {
+        let schedule = Schedule::new().rule(&[Weekday::Friday], (22, 0), (6, 0), 50);
+        // Friday 2024-01-05 00:00 UTC.
+        let friday = UNIX_EPOCH + Duration::from_secs(1_704_412_800);
+
+        // The early hours of Friday belong to Thursday night.
+        assert_eq!(schedule.limit_at(friday + Duration::from_secs(3600)), None);
+        assert_eq!(schedule.limit_at(friday + Duration::from_secs(23 * 3600)), Some(50));
+        // Saturday 01:00 is still Friday night; 07:00 is not.
+        assert_eq!(schedule.limit_at(friday + Duration::from_secs(25 * 3600)), Some(50));
+        assert_eq!(schedule.limit_at(friday + Duration::from_secs(31 * 3600)), None);
+        // Sunday does not inherit Saturday's missing rule.
+        assert_eq!(schedule.limit_at(friday + Duration::from_secs(49 * 3600)), None);
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use dashmap::DashMap;
+use crate::clock::{Clock, SystemClock};
+
+/// The number of requests counted in one time bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsagePoint {
+    /// Start of the bucket.
+    pub start: SystemTime,
+    /// Requests counted in the bucket.
+    pub count: u32,
+}
+
+/// Records per-key request counts into fixed time buckets for usage
+/// dashboards.
+///
+/// `RingBufferRecorder` keeps buckets in memory. A shared implementation can
+/// be built on a sorted set per key (e.g. Redis `ZINCRBY` on the bucket start
+/// with `ZREMRANGEBYSCORE` for retention).
+pub trait UsageRecorder: Send + Sync {
+    /// Adds `amount` requests for `key` to the current bucket.
+    fn record(&self, key: &str, amount: u32);
+
+    /// Returns the retained buckets for `key` that have at least one request,
+    /// oldest first.
+    fn series(&self, key: &str) -> Vec<UsagePoint>;
+}
+
+/// How many records pass between purges of idle keys.
+const PURGE_EVERY: u64 = 1024;
+
+/// An in-memory `UsageRecorder` keeping a fixed ring of buckets per key, so
+/// memory per key is bounded by `retention / bucket`. Keys with no requests
+/// within the retention are dropped every 1024 records.
+pub struct RingBufferRecorder {
+    bucket_millis: u64,
+    buckets: usize,
+    clock: Arc<dyn Clock>,
+    rings: DashMap<String, Vec<(u64, u32)>>,
+    recorded: AtomicU64,
+}
+
+impl RingBufferRecorder {
+    /// Creates a recorder with buckets of `bucket` length, keeping `retention`
+    /// worth of history (e.g. 1-minute buckets retained for 24 hours).
+    pub fn new(bucket: Duration, retention: Duration) -> Self {
+        let bucket_millis = (bucket.as_millis() as u64).max(1);
+        let buckets = (retention.as_millis() as u64 / bucket_millis).max(1) as usize;
+        RingBufferRecorder {
+            bucket_millis,
+            buckets,
+            clock: Arc::new(SystemClock),
+            rings: DashMap::new(),
+            recorded: AtomicU64::new(0),
+        }
+    }
+
+    /// Uses `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Drops keys with no requests within the retention and returns how
+    /// many were dropped. `record` calls it every 1024 records.
+    pub fn purge_idle(&self) -> usize {
+        let oldest = self.oldest_bucket(self.current_bucket());
+        let before = self.rings.len();
+        self.rings
+            .retain(|_, ring| ring.iter().any(|(bucket, count)| *count > 0 && *bucket >= oldest));
+        before.saturating_sub(self.rings.len())
+    }
+
+    /// Returns how many keys have buckets in memory.
+    pub fn key_count(&self) -> usize {
+        self.rings.len()
+    }
+
+    fn oldest_bucket(&self, current: u64) -> u64 {
+        current.saturating_sub(self.buckets as u64 - 1)
+    }
+
+    fn current_bucket(&self) -> u64 {
+        let millis = self
+            .clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0);
+        millis / self.bucket_millis
+    }
+}
+
+impl UsageRecorder for RingBufferRecorder {
+    fn record(&self, key: &str, amount: u32) {
+        if self.recorded.fetch_add(1, Ordering::Relaxed) % PURGE_EVERY == PURGE_EVERY - 1 {
+            self.purge_idle();
+        }
+        let bucket = self.current_bucket();
+        let slot = bucket as usize % self.buckets;
+        let mut ring = match self.rings.get_mut(key) {
+            Some(ring) => ring,
+            None => self
+                .rings
+                .entry(key.to_string())
+                .or_insert_with(|| vec![(0, 0); self.buckets]),
+        };
+        let (slot_bucket, count) = &mut ring[slot];
+        if *slot_bucket != bucket {
+            // The slot holds a bucket from a previous lap of the ring.
+            *slot_bucket = bucket;
+            *count = 0;
+        }
+        *count = count.saturating_add(amount);
+    }
+
+    fn series(&self, key: &str) -> Vec<UsagePoint> {
+        let Some(ring) = self.rings.get(key) else {
+            return Vec::new();
+        };
+        let current = self.current_bucket();
+        let oldest = self.oldest_bucket(current);
+        let mut points: Vec<(u64, u32)> = ring
+            .iter()
+            .copied()
+            .filter(|(bucket, count)| *count > 0 && (oldest..=current).contains(bucket))
+            .collect();
+        points.sort_unstable_by_key(|(bucket, _)| *bucket);
+        points
+            .into_iter()
+            .map(|(bucket, count)| UsagePoint {
+                start: UNIX_EPOCH + Duration::from_millis(bucket * self.bucket_millis),
+                count,
+            })
+            .collect()
+    }
+}
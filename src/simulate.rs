@@ -0,0 +1,375 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::time::Duration;
+use crate::rate::Rate;
+
+/// One request in a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// Time since the start of the trace.
+    pub at: Duration,
+    /// The client key.
+    pub key: String,
+    /// Units the request consumes.
+    pub cost: u32,
+}
+
+/// A request trace to replay, built from synthetic traffic or recorded
+/// events. Events are kept in time order.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    /// Creates an empty trace.
+    pub fn new() -> Self {
+        Trace { events: Vec::new() }
+    }
+
+    /// Creates a trace from recorded events, in any order.
+    pub fn from_events(mut events: Vec<TraceEvent>) -> Self {
+        events.sort_by_key(|event| event.at);
+        Trace { events }
+    }
+
+    /// Adds evenly spaced requests from `key` at `rate` per second, from
+    /// `start` until `end`.
+    pub fn steady(mut self, key: &str, rate: f64, start: Duration, end: Duration) -> Self {
+        if rate > 0.0 {
+            let spacing = Duration::from_secs_f64(1.0 / rate);
+            let mut at = start;
+            while at < end {
+                self.events.push(TraceEvent {
+                    at,
+                    key: key.to_string(),
+                    cost: 1,
+                });
+                at += spacing;
+            }
+        }
+        self.events.sort_by_key(|event| event.at);
+        self
+    }
+
+    /// Adds `count` simultaneous requests from `key` at `at`.
+    pub fn burst(mut self, key: &str, at: Duration, count: u32) -> Self {
+        self.events.extend((0..count).map(|_| TraceEvent {
+            at,
+            key: key.to_string(),
+            cost: 1,
+        }));
+        self.events.sort_by_key(|event| event.at);
+        self
+    }
+
+    /// Returns the events in time order.
+    pub fn events(&self) -> &[TraceEvent] {
+        &self.events
+    }
+}
+
+/// A rate limiting algorithm to simulate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// A window starting at a key's first request, as `Strategy::FixedWindow`.
+    FixedWindow,
+    /// Clock-aligned windows where the previous window's count is weighted
+    /// by how much of it still overlaps the sliding window.
+    SlidingWindow,
+    /// An exact sliding window keeping a timestamp per request.
+    SlidingLog,
+    /// A bucket of `limit` tokens refilled continuously over the window.
+    TokenBucket,
+    /// The Generic Cell Rate Algorithm, as `Strategy::LocalLockFree` and
+    /// `GcraLimiter`.
+    Gcra,
+}
+
+/// An algorithm with its limit: `limit` units per `window`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimPolicy {
+    /// The algorithm.
+    pub algorithm: Algorithm,
+    /// Units allowed per window.
+    pub limit: u32,
+    /// Duration of the window.
+    pub window: Duration,
+}
+
+impl SimPolicy {
+    /// Simulates `algorithm` allowing `rate`.
+    pub fn new(algorithm: Algorithm, rate: Rate) -> Self {
+        SimPolicy {
+            algorithm,
+            limit: rate.limit,
+            window: rate.window,
+        }
+    }
+}
+
+/// Results of replaying a trace through one policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationReport {
+    /// The policy simulated.
+    pub policy: SimPolicy,
+    /// Requests allowed.
+    pub allowed: u64,
+    /// Requests blocked.
+    pub blocked: u64,
+    /// Most units any one key was allowed within a span of one window. A
+    /// fixed window can reach twice the limit across a window boundary.
+    pub max_burst: u32,
+    /// Most keys holding state at once.
+    pub peak_keys: usize,
+    /// Most bytes of per-key limiter state held at once, excluding the keys
+    /// themselves.
+    pub peak_state_bytes: usize,
+}
+
+/// Replays `trace` through each policy in virtual time, so hours of traffic
+/// take milliseconds, and reports how each behaved.
+pub fn simulate(trace: &Trace, policies: &[SimPolicy]) -> Vec<SimulationReport> {
+    policies.iter().map(|policy| run(trace, *policy)).collect()
+}
+
+enum KeyState {
+    Fixed { start: u64, count: u32 },
+    Sliding { index: u64, previous: u32, current: u32 },
+    Log { entries: VecDeque<(u64, u32)>, sum: u32 },
+    Bucket { tokens: f64, last: u64 },
+    Gcra { tat: u64 },
+}
+
+impl KeyState {
+    fn new(algorithm: Algorithm, now: u64, limit: u32, window: u64) -> Self {
+        match algorithm {
+            Algorithm::FixedWindow => KeyState::Fixed { start: now, count: 0 },
+            Algorithm::SlidingWindow => KeyState::Sliding {
+                index: now / window,
+                previous: 0,
+                current: 0,
+            },
+            Algorithm::SlidingLog => KeyState::Log {
+                entries: VecDeque::new(),
+                sum: 0,
+            },
+            Algorithm::TokenBucket => KeyState::Bucket {
+                tokens: f64::from(limit),
+                last: now,
+            },
+            Algorithm::Gcra => KeyState::Gcra { tat: now },
+        }
+    }
+
+    /// Decides a request of `cost` units at `now`.
+    fn check(&mut self, now: u64, cost: u32, limit: u32, window: u64) -> bool {
+        match self {
+            KeyState::Fixed { start, count } => {
+                if now >= *start + window {
+                    *start = now;
+                    *count = 0;
+                }
+                let allowed = count.saturating_add(cost) <= limit;
+                if allowed {
+                    *count += cost;
+                }
+                allowed
+            }
+            KeyState::Sliding { index, previous, current } => {
+                let now_index = now / window;
+                if now_index != *index {
+                    *previous = if now_index == *index + 1 { *current } else { 0 };
+                    *current = 0;
+                    *index = now_index;
+                }
+                let overlap = 1.0 - (now % window) as f64 / window as f64;
+                let estimate = f64::from(*previous) * overlap + f64::from(*current);
+                let allowed = estimate + f64::from(cost) <= f64::from(limit);
+                if allowed {
+                    *current += cost;
+                }
+                allowed
+            }
+            KeyState::Log { entries, sum } => {
+                while let Some(&(at, units)) = entries.front() {
+                    if at + window > now {
+                        break;
+                    }
+                    entries.pop_front();
+                    *sum -= units;
+                }
+                let allowed = sum.saturating_add(cost) <= limit;
+                if allowed {
+                    entries.push_back((now, cost));
+                    *sum += cost;
+                }
+                allowed
+            }
+            KeyState::Bucket { tokens, last } => {
+                let rate = f64::from(limit) / window as f64;
+                *tokens = (*tokens + (now - *last) as f64 * rate).min(f64::from(limit));
+                *last = now;
+                let allowed = *tokens >= f64::from(cost);
+                if allowed {
+                    *tokens -= f64::from(cost);
+                }
+                allowed
+            }
+            KeyState::Gcra { tat } => {
+                let interval = (window / u64::from(limit.max(1))).max(1);
+                let next = (*tat).max(now) + interval * u64::from(cost);
+                let allowed = limit > 0 && next - now <= window;
+                if allowed {
+                    *tat = next;
+                }
+                allowed
+            }
+        }
+    }
+
+    /// Returns when the state can be dropped without changing any decision.
+    fn expires_at(&self, limit: u32, window: u64) -> u64 {
+        match self {
+            KeyState::Fixed { start, .. } => start + window,
+            KeyState::Sliding { index, .. } => (index + 2) * window,
+            KeyState::Log { entries, .. } => entries.back().map_or(0, |&(at, _)| at + window),
+            KeyState::Bucket { tokens, last } => {
+                let missing = f64::from(limit) - tokens;
+                last + (missing * window as f64 / f64::from(limit.max(1))).ceil() as u64
+            }
+            KeyState::Gcra { tat } => *tat,
+        }
+    }
+
+    /// Returns the bytes a limiter needs to store this state.
+    fn size(&self) -> usize {
+        match self {
+            // A count and an expiry.
+            KeyState::Fixed { .. } => 12,
+            // Two counts and the window start.
+            KeyState::Sliding { .. } => 16,
+            // A timestamp and cost per request in the window.
+            KeyState::Log { entries, .. } => 12 * entries.len(),
+            // Fractional tokens and the last refill time.
+            KeyState::Bucket { .. } => 16,
+            // The theoretical arrival time.
+            KeyState::Gcra { .. } => 8,
+        }
+    }
+}
+
+/// Allowed requests within the last window, and the units they consumed.
+type Recent = (VecDeque<(u64, u32)>, u32);
+
+fn run(trace: &Trace, policy: SimPolicy) -> SimulationReport {
+    let window = (policy.window.as_nanos() as u64).max(1);
+    let limit = policy.limit;
+    let mut report = SimulationReport {
+        policy,
+        allowed: 0,
+        blocked: 0,
+        max_burst: 0,
+        peak_keys: 0,
+        peak_state_bytes: 0,
+    };
+    let mut states: HashMap<&str, (KeyState, u64)> = HashMap::new();
+    let mut expiries = BinaryHeap::new();
+    let mut state_bytes = 0;
+    // Per key, units allowed within the last window, for measuring bursts.
+    let mut recent: HashMap<&str, Recent> = HashMap::new();
+
+    for event in trace.events() {
+        let now = event.at.as_nanos() as u64;
+        while let Some(&Reverse((expires_at, key))) = expiries.peek() {
+            if expires_at > now {
+                break;
+            }
+            expiries.pop();
+            if states.get(key).is_some_and(|(_, current)| *current == expires_at) {
+                if let Some((state, _)) = states.remove(key) {
+                    state_bytes -= state.size();
+                }
+            }
+        }
+
+        let key = event.key.as_str();
+        let before = states.get(key).map_or(0, |(state, _)| state.size());
+        let (state, expires_at) = states
+            .entry(key)
+            .or_insert_with(|| (KeyState::new(policy.algorithm, now, limit, window), now));
+        state_bytes -= before;
+        let allowed = state.check(now, event.cost, limit, window);
+        state_bytes += state.size();
+        *expires_at = state.expires_at(limit, window).max(now + 1);
+        expiries.push(Reverse((*expires_at, key)));
+        report.peak_keys = report.peak_keys.max(states.len());
+        report.peak_state_bytes = report.peak_state_bytes.max(state_bytes);
+
+        if !allowed {
+            report.blocked += 1;
+            continue;
+        }
+        report.allowed += 1;
+        let (entries, sum) = recent.entry(key).or_default();
+        while let Some(&(at, units)) = entries.front() {
+            if at + window > now {
+                break;
+            }
+            entries.pop_front();
+            *sum -= units;
+        }
+        entries.push_back((now, event.cost));
+        *sum += event.cost;
+        report.max_burst = report.max_burst.max(*sum);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{simulate, Algorithm, SimPolicy, Trace};
+
+    fn policy(algorithm: Algorithm) -> SimPolicy {
+        SimPolicy {
+            algorithm,
+            limit: 10,
+            window: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn test_fixed_window_doubles_at_boundary() {
+        // 10 requests just before the first window ends and 10 just after.
+        let trace = Trace::new()
+            .burst("a", Duration::ZERO, 1)
+            .burst("a", Duration::from_millis(9_900), 9)
+            .burst("a", Duration::from_millis(10_100), 10);
+        let reports = simulate(
+            &trace,
+            &[policy(Algorithm::FixedWindow), policy(Algorithm::SlidingLog), policy(Algorithm::Gcra)],
+        );
+        assert_eq!((reports[0].allowed, reports[0].max_burst), (20, 19));
+        assert_eq!((reports[1].allowed, reports[1].max_burst), (11, 10));
+        assert_eq!((reports[2].allowed, reports[2].max_burst), (11, 10));
+    }
+
+    #[test]
+    fn test_state_expires() {
+        let trace = Trace::new()
+            .steady("a", 1.0, Duration::ZERO, Duration::from_secs(5))
+            .steady("b", 1.0, Duration::from_secs(100), Duration::from_secs(105));
+        for algorithm in [
+            Algorithm::FixedWindow,
+            Algorithm::SlidingWindow,
+            Algorithm::SlidingLog,
+            Algorithm::TokenBucket,
+            Algorithm::Gcra,
+        ] {
+            let report = &simulate(&trace, &[policy(algorithm)])[0];
+            assert_eq!((report.allowed, report.blocked), (10, 0), "{:?}", algorithm);
+            assert_eq!(report.peak_keys, 1, "{:?}", algorithm);
+        }
+    }
+}
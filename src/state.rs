@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+/// Maximum compare-and-swap attempts made by `update_state` before giving up.
+pub const MAX_CAS_ATTEMPTS: usize = 16;
+
+/// A backend that stores opaque, serialized state per key.
+///
+/// Where `CacheBackend` only stores a `u32` counter, this lets strategies such
+/// as token buckets or GCRA keep richer state (timestamps, fractional tokens)
+/// in a shared store. Updates go through compare-and-swap so concurrent
+/// writers on different hosts cannot clobber each other.
+pub trait StateBackend: Send + Sync {
+    /// Retrieves the stored state for the given key.
+    fn get_state(&self, key: &str) -> Option<Vec<u8>>;
+
+    /// Atomically replaces the state for `key` with `new` if the current state
+    /// equals `expected` (`None` meaning the key is absent or expired), and
+    /// sets its time-to-live. Returns `Ok(true)` if the swap happened.
+    fn compare_and_swap_state(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: &[u8],
+        ttl: Duration,
+    ) -> Result<bool, String>;
+}
+
+/// A strategy state that can be stored in a `StateBackend`.
+pub trait State: Sized {
+    /// Serializes the state.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Deserializes the state, returning `None` if the bytes are not valid.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+/// Atomically reads, modifies and writes the state for `key`.
+///
+/// `update` receives the current state (or `None`) and returns the new state,
+/// its time-to-live and a value to hand back to the caller. The update is
+/// retried if another writer changed the state in between.
+///
+/// # Errors
+///
+/// Returns an error if the backend fails or the update keeps conflicting
+/// after `MAX_CAS_ATTEMPTS` attempts.
+pub fn update_state<B, S, R, F>(backend: &B, key: &str, mut update: F) -> Result<R, String>
+where
+    B: StateBackend + ?Sized,
+    S: State,
+    F: FnMut(Option<S>) -> (S, Duration, R),
+{
+    for _ in 0..MAX_CAS_ATTEMPTS {
+        let current = backend.get_state(key);
+        let decoded = current.as_deref().and_then(S::decode);
+        let (next, ttl, result) = update(decoded);
+        if backend.compare_and_swap_state(key, current.as_deref(), &next.encode(), ttl)? {
+            return Ok(result);
+        }
+    }
+    Err(format!("too much contention updating state for {}", key))
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use crate::cache::pool::PoolStats;
+
+/// Default number of keys tracked by the hot-key sketch.
+pub const DEFAULT_HOT_KEY_CAPACITY: usize = 16;
+
+/// Number of independently locked parts of the hot-key sketch.
+const HOT_KEY_SHARDS: usize = 16;
+
+/// A point-in-time snapshot of a rate limiter's aggregate counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LimiterStats {
+    /// Total number of requests checked.
+    pub checked: u64,
+    /// Number of requests that were allowed.
+    pub allowed: u64,
+    /// Number of requests that were blocked.
+    pub blocked: u64,
+    /// Number of backend operations that returned an error.
+    pub backend_errors: u64,
+    /// Number of keys currently held by the backend, if it can report it.
+    pub active_keys: Option<usize>,
+    /// The most frequently checked keys with their approximate hit counts,
+    /// hottest first.
+    pub hottest_keys: Vec<(String, u64)>,
+    /// Connection pool state, for network backends that pool connections.
+    pub pool: Option<PoolStats>,
+}
+
+/// Lock-free counters plus a bounded hot-key sketch, updated on every check.
+///
+/// The sketch is split into shards by key hash so checks of different keys
+/// rarely meet on a lock. Every check is counted: the lock is held only for
+/// one map update, and skipping busy shards would undercount exactly the
+/// hottest keys.
+pub(crate) struct StatsCollector {
+    checked: AtomicU64,
+    allowed: AtomicU64,
+    blocked: AtomicU64,
+    backend_errors: AtomicU64,
+    hot_key_capacity: usize,
+    hot_keys: Vec<Mutex<HotKeys>>,
+}
+
+impl StatsCollector {
+    pub(crate) fn new(hot_key_capacity: usize) -> Self {
+        StatsCollector {
+            checked: AtomicU64::new(0),
+            allowed: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+            backend_errors: AtomicU64::new(0),
+            hot_key_capacity,
+            hot_keys: (0..HOT_KEY_SHARDS)
+                .map(|_| Mutex::new(HotKeys::new(hot_key_capacity)))
+                .collect(),
+        }
+    }
+
+    /// Records the outcome of a single check for `key`.
+    pub(crate) fn record(&self, key: &str, allowed: bool) {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+        if allowed {
+            self.allowed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.blocked.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.hot_key_capacity == 0 {
+            return;
+        }
+        // FNV-1a: cheap, and only needs to spread keys across shards.
+        let hash = key
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3));
+        let shard = &self.hot_keys[hash as usize % HOT_KEY_SHARDS];
+        shard.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).observe(key);
+    }
+
+    /// Records a failed backend operation.
+    pub(crate) fn record_backend_error(&self) {
+        self.backend_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Builds a snapshot of the current counters.
+    pub(crate) fn snapshot(
+        &self,
+        active_keys: Option<usize>,
+        pool: Option<PoolStats>,
+    ) -> LimiterStats {
+        let mut hottest_keys: Vec<(String, u64)> = self
+            .hot_keys
+            .iter()
+            .filter_map(|shard| shard.lock().ok().map(|hot_keys| hot_keys.entries()))
+            .flatten()
+            .collect();
+        hottest_keys.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        hottest_keys.truncate(self.hot_key_capacity);
+        LimiterStats {
+            checked: self.checked.load(Ordering::Relaxed),
+            allowed: self.allowed.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+            backend_errors: self.backend_errors.load(Ordering::Relaxed),
+            active_keys,
+            hottest_keys,
+            pool,
+        }
+    }
+}
+
+/// A Space-Saving sketch: tracks at most `capacity` keys, and when full
+/// replaces the least frequent one, so memory stays bounded no matter how
+/// many distinct keys are seen. Counts are upper bounds on true frequency.
+struct HotKeys {
+    capacity: usize,
+    counts: HashMap<String, u64>,
+}
+
+impl HotKeys {
+    fn new(capacity: usize) -> Self {
+        HotKeys {
+            capacity,
+            counts: HashMap::with_capacity(capacity),
+        }
+    }
+
+    fn observe(&mut self, key: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(count) = self.counts.get_mut(key) {
+            *count += 1;
+            return;
+        }
+        if self.counts.len() < self.capacity {
+            self.counts.insert(key.to_string(), 1);
+            return;
+        }
+        // Full: evict the minimum and let the newcomer inherit its count.
+        let (min_key, min_count) = match self.counts.iter().min_by_key(|(_, count)| **count) {
+            Some((k, c)) => (k.clone(), *c),
+            None => return,
+        };
+        self.counts.remove(&min_key);
+        self.counts.insert(key.to_string(), min_count + 1);
+    }
+
+    fn entries(&self) -> Vec<(String, u64)> {
+        self.counts
+            .iter()
+            .map(|(key, count)| (key.clone(), *count))
+            .collect()
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+use crate::priority::Priority;
+
+/// A rate limit decision as reported to a `DecisionObserver`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecisionEvent<'a> {
+    /// The client key, hashed if the limiter has a key hasher.
+    pub key: &'a str,
+    /// Whether the request was allowed.
+    pub allowed: bool,
+    /// Whether the allowed request reached the limiter's soft limit.
+    pub warning: bool,
+    /// Units the request consumed or would have consumed.
+    pub cost: u32,
+    /// The count after the request.
+    pub count: u32,
+    /// The limit applied.
+    pub limit: u32,
+    /// The priority band applied.
+    pub priority: Priority,
+    /// Version of the policy that decided.
+    pub policy_version: u32,
+    /// Time spent deciding, including the backend round trip.
+    pub latency: Duration,
+}
+
+/// Receives every rate limit decision, for exporting metrics and traces
+/// (e.g. OpenTelemetry counters for allowed/blocked, a latency histogram
+/// and a span event on the active request span).
+///
+/// Observers are called synchronously on the thread that made the check,
+/// so thread-local tracing context such as the current span is that of
+/// the request being limited. Keep them cheap.
+///
+/// Any `Fn(&DecisionEvent)` is an observer.
+pub trait DecisionObserver: Send + Sync {
+    /// Called once per checked request.
+    fn observe(&self, event: &DecisionEvent<'_>);
+}
+
+impl<F> DecisionObserver for F
+where
+    F: Fn(&DecisionEvent<'_>) + Send + Sync,
+{
+    fn observe(&self, event: &DecisionEvent<'_>) {
+        self(event)
+    }
+}
@@ -0,0 +1,291 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use dashmap::DashMap;
+use crate::clock::{Clock, SystemClock};
+use crate::limiter::{CacheBackend, RateLimitDecision, RateLimiter};
+use crate::priority::Priority;
+
+/// Reset values above this are Unix timestamps rather than seconds from now
+/// (as in GitHub's `X-RateLimit-Reset`).
+const EPOCH_RESET_THRESHOLD: u64 = 1_000_000_000;
+
+/// Quota advertised by an upstream in its response headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpstreamQuota {
+    /// The upstream's limit, if it said.
+    pub limit: Option<u32>,
+    /// Requests left before the upstream starts rejecting.
+    pub remaining: u32,
+    /// When the quota resets, if it said.
+    pub resets_at: Option<SystemTime>,
+}
+
+impl UpstreamQuota {
+    /// Parses rate limit headers as of `now`. Names are matched
+    /// case-insensitively.
+    ///
+    /// Understands `RateLimit-Limit`, `RateLimit-Remaining` and
+    /// `RateLimit-Reset` (and their `X-` prefixed forms, with the reset in
+    /// seconds or as a Unix timestamp), the combined `RateLimit` header
+    /// (`limit=100, remaining=5, reset=30` or `r=5;t=30`), and `Retry-After`
+    /// in seconds or as an HTTP date, which means no quota is left until
+    /// then. Returns `None` if the headers advertise no quota.
+    pub fn from_headers<'h>(
+        headers: impl IntoIterator<Item = (&'h str, &'h str)>,
+        now: SystemTime,
+    ) -> Option<Self> {
+        let mut limit = None;
+        let mut remaining = None;
+        let mut reset = None;
+        let mut retry_after = None;
+        for (name, value) in headers {
+            let name = name.trim().to_ascii_lowercase();
+            let name = name.strip_prefix("x-").unwrap_or(&name);
+            match name {
+                "ratelimit-limit" => limit = leading_number(value),
+                "ratelimit-remaining" => remaining = leading_number(value),
+                "ratelimit-reset" => reset = leading_number(value).map(|reset| reset_time(reset, now)),
+                "ratelimit" => {
+                    for (key, value) in value.split([',', ';']).filter_map(|param| param.split_once('=')) {
+                        let number = leading_number(value.trim().trim_matches('"'));
+                        match key.trim() {
+                            "limit" | "l" => limit = number,
+                            "remaining" | "r" => remaining = number,
+                            "reset" | "t" => reset = number.map(|reset| reset_time(reset, now)),
+                            _ => {}
+                        }
+                    }
+                }
+                "retry-after" => {
+                    let value = value.trim();
+                    retry_after = match value.parse::<u64>() {
+                        Ok(seconds) => Some(now + Duration::from_secs(seconds)),
+                        Err(_) => parse_http_date(value),
+                    };
+                }
+                _ => {}
+            }
+        }
+        if let Some(resets_at) = retry_after {
+            return Some(UpstreamQuota {
+                limit: limit.map(saturate),
+                remaining: 0,
+                resets_at: Some(resets_at),
+            });
+        }
+        Some(UpstreamQuota {
+            limit: limit.map(saturate),
+            remaining: saturate(remaining?),
+            resets_at: reset,
+        })
+    }
+}
+
+/// Parses the digits at the start of `value`, ignoring parameters after them
+/// (e.g. `100, 100;w=60`).
+fn leading_number(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+/// Narrows an advertised count, treating counts beyond `u32` as unlimited
+/// rather than wrapping them.
+fn saturate(count: u64) -> u32 {
+    u32::try_from(count).unwrap_or(u32::MAX)
+}
+
+fn reset_time(reset: u64, now: SystemTime) -> SystemTime {
+    if reset > EPOCH_RESET_THRESHOLD {
+        UNIX_EPOCH + Duration::from_secs(reset)
+    } else {
+        now + Duration::from_secs(reset)
+    }
+}
+
+/// Parses an IMF-fixdate such as `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts.as_slice() else {
+        return None;
+    };
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"]
+        .iter()
+        .position(|name| name == month)? as u64
+        + 1;
+    let day: u64 = day.parse().ok()?;
+    let year: u64 = year.parse().ok()?;
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (clock.next()??, clock.next()??, clock.next()??);
+    if year < 1970 || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    // Days since the epoch for a proleptic Gregorian date, with March as the
+    // first month so leap days fall at the end of the year.
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y / 400;
+    let year_of_era = y % 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hours * 3600 + minutes * 60 + seconds))
+}
+
+struct HostQuota {
+    remaining: u32,
+    resets_at: SystemTime,
+}
+
+/// Caps an outbound limiter to the quota upstreams advertise, so a proxy
+/// slows down before the upstream starts rejecting it.
+///
+/// Feed each upstream response's headers to `update`; `check` then allows a
+/// request to a host only if both the wrapped limiter, keyed by host, and
+/// the host's remaining advertised quota allow it. The advertised quota is
+/// counted down locally between responses and forgotten at its reset time,
+/// after which only the wrapped limiter applies. Hosts are kept in the form
+/// the limiter stores client keys, hashed if it has a `KeyHasher`.
+pub struct UpstreamQuotaTracker<B: CacheBackend> {
+    limiter: RateLimiter<B>,
+    hosts: DashMap<String, HostQuota>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<B: CacheBackend> UpstreamQuotaTracker<B> {
+    /// Wraps the outbound `limiter`.
+    pub fn new(limiter: RateLimiter<B>) -> Self {
+        UpstreamQuotaTracker {
+            limiter,
+            hosts: DashMap::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the clock used for reset times.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the wrapped limiter.
+    pub fn limiter(&self) -> &RateLimiter<B> {
+        &self.limiter
+    }
+
+    /// Updates `host`'s quota from the headers of one of its responses.
+    /// Returns the parsed quota, or `None` if the headers advertised none.
+    /// Without a reset time, the quota lasts for the limiter's window.
+    pub fn update<'h>(
+        &self,
+        host: &str,
+        headers: impl IntoIterator<Item = (&'h str, &'h str)>,
+    ) -> Option<UpstreamQuota> {
+        let now = self.clock.now();
+        let quota = UpstreamQuota::from_headers(headers, now)?;
+        self.hosts.insert(
+            self.limiter.client_key(host).into_owned(),
+            HostQuota {
+                remaining: quota.remaining,
+                resets_at: quota.resets_at.unwrap_or(now + self.limiter.ttl),
+            },
+        );
+        Some(quota)
+    }
+
+    /// Returns `host`'s remaining advertised quota, if one is in force.
+    pub fn remaining(&self, host: &str) -> Option<u32> {
+        let now = self.clock.now();
+        self.hosts
+            .get(self.limiter.client_key(host).as_ref())
+            .filter(|quota| quota.resets_at > now)
+            .map(|quota| quota.remaining)
+    }
+
+    /// Checks whether a request to `host` is allowed.
+    pub fn allow(&self, host: &str) -> bool {
+        self.check(host).allowed
+    }
+
+    /// Checks a request to `host` against the upstream's advertised quota
+    /// and the wrapped limiter.
+    pub fn check(&self, host: &str) -> RateLimitDecision {
+        let now = self.clock.now();
+        let stored = self.limiter.client_key(host);
+        let mut taken = false;
+        if let Some(mut quota) = self.hosts.get_mut(stored.as_ref()) {
+            if quota.resets_at > now {
+                if quota.remaining == 0 {
+                    return RateLimitDecision {
+                        allowed: false,
+                        count: 0,
+                        limit: self.limiter.current_limit(),
+                        remaining: 0,
+                        priority: Priority::Normal,
+                        borrowed: false,
+                        retry_after: quota.resets_at.duration_since(now).ok(),
+                        warning: false,
+                        global_limited: false,
+                        invalid_key: false,
+                        checked_at: Instant::now(),
+                    };
+                }
+                quota.remaining -= 1;
+                taken = true;
+            }
+        }
+
+        let mut decision = self.limiter.check(host);
+        if taken {
+            match self.hosts.get_mut(stored.as_ref()) {
+                // The request was not sent, so the upstream quota is unused.
+                Some(mut quota) if !decision.allowed => quota.remaining += 1,
+                Some(quota) => decision.remaining = decision.remaining.min(quota.remaining),
+                None => {}
+            }
+        }
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{parse_http_date, UpstreamQuota};
+
+    #[test]
+    fn test_parse_http_date() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(784_111_777))
+        );
+        assert_eq!(
+            parse_http_date("Thu, 29 Feb 2024 00:00:00 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_709_164_800))
+        );
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let quota = UpstreamQuota::from_headers(
+            [("X-RateLimit-Limit", "5000"), ("x-ratelimit-remaining", "42"), ("X-RateLimit-Reset", "1700000060")],
+            now,
+        )
+        .unwrap();
+        assert_eq!(quota.limit, Some(5000));
+        assert_eq!(quota.remaining, 42);
+        assert_eq!(quota.resets_at, Some(now + Duration::from_secs(60)));
+
+        let quota = UpstreamQuota::from_headers([("RateLimit", "\"default\";r=7;t=30")], now).unwrap();
+        assert_eq!((quota.remaining, quota.resets_at), (7, Some(now + Duration::from_secs(30))));
+
+        let quota = UpstreamQuota::from_headers([("RateLimit-Remaining", "3"), ("Retry-After", "120")], now).unwrap();
+        assert_eq!((quota.remaining, quota.resets_at), (0, Some(now + Duration::from_secs(120))));
+
+        let quota = UpstreamQuota::from_headers([("RateLimit", "limit=8589934592, remaining=4294967297")], now).unwrap();
+        assert_eq!((quota.limit, quota.remaining), (Some(u32::MAX), u32::MAX));
+
+        assert_eq!(UpstreamQuota::from_headers([("Content-Type", "text/plain")], now), None);
+    }
+}
@@ -0,0 +1,60 @@
+use std::net::IpAddr;
+
+/// Decides whether a client key is usable, e.g. that a key extracted from a
+/// request really is an IP address.
+///
+/// Any `Fn(&str) -> bool` is a validator.
+pub trait KeyValidator: Send + Sync {
+    /// Returns whether `key` is valid.
+    fn is_valid(&self, key: &str) -> bool;
+}
+
+impl<F> KeyValidator for F
+where
+    F: Fn(&str) -> bool + Send + Sync,
+{
+    fn is_valid(&self, key: &str) -> bool {
+        self(key)
+    }
+}
+
+/// What a limiter does with a request whose key is missing or invalid,
+/// instead of letting every such request share one bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidKeyPolicy {
+    /// Allows the request without counting it.
+    Allow,
+    /// Blocks the request without counting it.
+    Block,
+    /// Counts the request against this key instead, e.g. `"unknown"`.
+    UseFallbackKey(String),
+}
+
+/// The default validator: rejects empty and blank keys and keys containing
+/// control characters.
+pub fn well_formed(key: &str) -> bool {
+    !key.trim().is_empty() && !key.chars().any(char::is_control)
+}
+
+/// Accepts only IPv4 and IPv6 addresses.
+pub fn ip_address(key: &str) -> bool {
+    key.parse::<IpAddr>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ip_address, well_formed};
+
+    #[test]
+    fn test_validators() {
+        assert!(well_formed("api-key-1"));
+        assert!(!well_formed(""));
+        assert!(!well_formed("  "));
+        assert!(!well_formed("key\n"));
+
+        assert!(ip_address("10.0.0.1"));
+        assert!(ip_address("::1"));
+        assert!(!ip_address("10.0.0"));
+        assert!(!ip_address("unknown"));
+    }
+}
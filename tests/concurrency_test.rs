@@ -0,0 +1,235 @@
+//! Property and stress tests for the limiter's core invariant: within one
+//! window, the units allowed for a key never exceed its ceiling, whatever
+//! the sequence of costs and however checks interleave across threads.
+//!
+//! Cases are generated from a fixed seed so failures reproduce; set
+//! `ARL_TEST_SEED` to explore other sequences.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use api_rate_limiter::cache::in_memory::InMemoryCache;
+use api_rate_limiter::limiter::{CacheBackend, RateLimiter, Strategy};
+use api_rate_limiter::priority::{Priority, PriorityReserves};
+
+const CASES: usize = 200;
+const THREADS: usize = 8;
+
+/// A xorshift generator, so cases are reproducible without extra crates.
+struct Rng(u64);
+
+impl Rng {
+    fn from_env() -> Self {
+        let seed = std::env::var("ARL_TEST_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or(0x9e37_79b9_7f4a_7c15);
+        Rng(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in `low..=high`.
+    fn range(&mut self, low: u32, high: u32) -> u32 {
+        low + (self.next() % u64::from(high - low + 1)) as u32
+    }
+}
+
+/// A backend implementing only the required operations and `decr`, with a
+/// yield inside each so threads interleave, to exercise the trait's
+/// default `incr_within`.
+#[derive(Default)]
+struct MinimalBackend {
+    counts: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+impl CacheBackend for MinimalBackend {
+    fn get(&self, key: &str) -> Option<u32> {
+        thread::yield_now();
+        let counts = self.counts.lock().unwrap();
+        counts
+            .get(key)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(count, _)| *count)
+    }
+
+    fn set(&self, key: &str, value: u32, ttl: Duration) -> Result<(), String> {
+        thread::yield_now();
+        self.counts
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (value, Instant::now() + ttl));
+        Ok(())
+    }
+
+    fn incr(&self, key: &str, amount: u32, ttl_if_new: Duration) -> Result<u32, String> {
+        thread::yield_now();
+        let now = Instant::now();
+        let mut counts = self.counts.lock().unwrap();
+        let entry = counts.entry(key.to_string()).or_insert((0, now));
+        if entry.1 <= now {
+            *entry = (0, now + ttl_if_new);
+        }
+        entry.0 += amount;
+        Ok(entry.0)
+    }
+
+    fn decr(&self, key: &str, amount: u32) -> Result<u32, String> {
+        thread::yield_now();
+        let mut counts = self.counts.lock().unwrap();
+        Ok(counts.get_mut(key).map_or(0, |entry| {
+            entry.0 = entry.0.saturating_sub(amount);
+            entry.0
+        }))
+    }
+}
+
+fn strategies() -> [Strategy; 2] {
+    [Strategy::FixedWindow, Strategy::LocalLockFree]
+}
+
+#[test]
+fn prop_allowed_units_never_exceed_limit() {
+    let mut rng = Rng::from_env();
+    for case in 0..CASES {
+        let limit = rng.range(1, 50);
+        let requests = rng.range(1, 120);
+        for strategy in strategies() {
+            let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), limit, Duration::from_secs(600))
+                .with_strategy(strategy);
+            let mut allowed = 0;
+            // Replay the same costs for each strategy.
+            let mut costs = Rng(rng.0);
+            for _ in 0..requests {
+                let cost = costs.range(1, 5);
+                let decision = limiter.check_n("client", cost);
+                if decision.allowed {
+                    allowed += cost;
+                }
+                assert!(decision.count <= limit, "case {}: count {} over limit {}", case, decision.count, limit);
+            }
+            assert!(allowed <= limit, "case {} ({:?}): allowed {} of {}", case, strategy, allowed, limit);
+        }
+        rng.next();
+    }
+}
+
+#[test]
+fn prop_unit_requests_fill_the_limit_exactly() {
+    let mut rng = Rng::from_env();
+    for _ in 0..CASES {
+        let limit = rng.range(1, 100);
+        let requests = rng.range(1, 200);
+        for strategy in strategies() {
+            let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), limit, Duration::from_secs(600))
+                .with_strategy(strategy);
+            let allowed = (0..requests).filter(|_| limiter.allow("client")).count() as u32;
+            assert_eq!(allowed, requests.min(limit), "{:?} limit {}", strategy, limit);
+        }
+    }
+}
+
+#[test]
+fn prop_priority_bands_stay_within_their_ceilings() {
+    let mut rng = Rng::from_env();
+    let priorities = [Priority::BestEffort, Priority::Normal, Priority::Critical];
+    for _ in 0..CASES {
+        let limit = rng.range(4, 60);
+        let reserves = PriorityReserves {
+            critical: rng.range(0, limit / 2),
+            normal: rng.range(0, limit / 2),
+        };
+        let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), limit, Duration::from_secs(600))
+            .with_priority_reserves(reserves);
+        for _ in 0..rng.range(1, 150) {
+            let priority = priorities[rng.range(0, 2) as usize];
+            let decision = limiter.check_with_priority("client", priority);
+            if decision.allowed {
+                assert!(decision.count <= reserves.ceiling(limit, priority));
+            }
+        }
+    }
+}
+
+/// Hammers one key from many threads released together and returns how
+/// many checks were allowed.
+fn hammer<B: CacheBackend + 'static>(limiter: Arc<RateLimiter<B>>, per_thread: usize) -> u32 {
+    let allowed = Arc::new(AtomicU32::new(0));
+    let start = Arc::new(Barrier::new(THREADS));
+    let handles: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let (limiter, allowed, start) = (Arc::clone(&limiter), Arc::clone(&allowed), Arc::clone(&start));
+            thread::spawn(move || {
+                start.wait();
+                for _ in 0..per_thread {
+                    if limiter.allow("hot") {
+                        allowed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    allowed.load(Ordering::Relaxed)
+}
+
+#[test]
+fn stress_concurrent_checks_never_over_admit() {
+    for round in 0..20 {
+        let limit = 25 + round * 5;
+        for strategy in strategies() {
+            let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), limit, Duration::from_secs(600))
+                .with_strategy(strategy);
+            assert_eq!(hammer(Arc::new(limiter), 50), limit, "{:?}", strategy);
+        }
+    }
+}
+
+#[test]
+fn stress_default_incr_within_never_over_admits() {
+    for round in 0..20 {
+        let limit = 10 + round;
+        let backend = Arc::new(MinimalBackend::default());
+        let limiter = Arc::new(RateLimiter::new(Arc::clone(&backend), limit, Duration::from_secs(600)));
+        assert_eq!(hammer(limiter, 20), limit);
+        // Rejected increments were taken back.
+        assert_eq!(backend.get("rate_limit:hot"), Some(limit));
+    }
+}
+
+#[test]
+fn stress_hot_key_promotion_never_over_admits() {
+    for round in 0..20 {
+        // Promote partway through the burst, while other threads are counting.
+        let cache = InMemoryCache::new().with_hot_key_threshold(1 + round * 3);
+        let limit = 100;
+        let limiter = RateLimiter::new(Arc::new(cache), limit, Duration::from_secs(600));
+        let limiter = Arc::new(limiter);
+        assert_eq!(hammer(Arc::clone(&limiter), 50), limit);
+        assert_eq!(limiter.usage("hot").count, limit);
+    }
+}
+
+#[test]
+fn stress_hot_key_tracking_keeps_contended_key_on_top() {
+    let limiter = Arc::new(RateLimiter::new(Arc::new(InMemoryCache::new()), 100, Duration::from_secs(600)));
+    for i in 0..40 {
+        limiter.allow(&format!("10.0.0.{}", i));
+    }
+    hammer(Arc::clone(&limiter), 200);
+
+    // Contended checks are all counted, not dropped by the sketch.
+    let stats = limiter.stats();
+    assert_eq!(stats.checked, 40 + THREADS as u64 * 200);
+    assert_eq!(stats.hottest_keys.len(), 16);
+    assert_eq!(stats.hottest_keys[0], ("hot".to_string(), THREADS as u64 * 200));
+}
@@ -0,0 +1,98 @@
+#![cfg(feature = "ffi")]
+
+use std::ffi::CString;
+use std::ptr;
+use api_rate_limiter::ffi::{arl_add_policy, arl_check, arl_limiter_free, arl_limiter_new, arl_refund, ArlDecision};
+
+#[test]
+fn test_ffi_checks_policies() {
+    let rate = CString::new("2/m").unwrap();
+    let login = CString::new("login").unwrap();
+    let login_rate = CString::new("1/5m").unwrap();
+    let key = CString::new("10.0.0.1").unwrap();
+    unsafe {
+        let limiter = arl_limiter_new(rate.as_ptr());
+        assert!(!limiter.is_null());
+        assert_eq!(arl_add_policy(limiter, login.as_ptr(), login_rate.as_ptr()), 0);
+
+        assert!(arl_check(limiter, key.as_ptr(), ptr::null()).allowed);
+        assert!(arl_check(limiter, key.as_ptr(), ptr::null()).allowed);
+        let blocked = arl_check(limiter, key.as_ptr(), ptr::null());
+        assert!(!blocked.allowed);
+        assert_eq!((blocked.count, blocked.limit, blocked.remaining), (2, 2, 0));
+        assert!(blocked.retry_after_ms > 0 && blocked.retry_after_ms <= 60_000);
+        assert_eq!(arl_refund(limiter, key.as_ptr(), 1), 1);
+        assert!(arl_check(limiter, key.as_ptr(), ptr::null()).allowed);
+
+        // Policies count separately.
+        assert!(arl_check(limiter, key.as_ptr(), login.as_ptr()).allowed);
+        let blocked = arl_check(limiter, key.as_ptr(), login.as_ptr());
+        assert!(!blocked.allowed);
+        assert!(blocked.retry_after_ms > 0);
+
+        // Invalid arguments are blocked rather than crashing.
+        let unknown = CString::new("unknown").unwrap();
+        assert!(!arl_check(limiter, key.as_ptr(), unknown.as_ptr()).allowed);
+        assert!(!arl_check(limiter, ptr::null(), ptr::null()).allowed);
+        assert!(!arl_check(ptr::null(), key.as_ptr(), ptr::null()).allowed);
+        assert_eq!(arl_add_policy(limiter, login.as_ptr(), unknown.as_ptr()), -1);
+        arl_limiter_free(limiter);
+
+        assert!(arl_limiter_new(unknown.as_ptr()).is_null());
+        arl_limiter_free(ptr::null_mut());
+    }
+}
+
+#[test]
+fn test_ffi_header_declares_every_export() {
+    let header = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/include/arl.h")).unwrap();
+    let source = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/src/ffi.rs")).unwrap();
+    let exports: Vec<&str> = source
+        .split("pub unsafe extern \"C\" fn ")
+        .skip(1)
+        .filter_map(|rest| rest.split('(').next())
+        .collect();
+    assert_eq!(exports.len(), 5);
+    for name in exports {
+        assert!(header.contains(&format!(" {}(", name)) || header.contains(&format!("*{}(", name)), "{}", name);
+    }
+}
+
+#[test]
+fn test_ffi_header_matches_decision_layout() {
+    use std::mem::{align_of, offset_of, size_of};
+
+    let header = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/include/arl.h")).unwrap();
+    let body = header
+        .split("typedef struct ArlDecision {")
+        .nth(1)
+        .and_then(|rest| rest.split("} ArlDecision;").next())
+        .unwrap();
+    // Lay the fields out as a C compiler would: each at the next multiple
+    // of its alignment, the struct padded to its largest alignment.
+    let mut fields = Vec::new();
+    let (mut offset, mut align) = (0usize, 1);
+    for line in body.lines().map(str::trim).filter(|line| line.ends_with(';')) {
+        let (ty, name) = line.trim_end_matches(';').split_once(' ').unwrap();
+        let size = match ty {
+            "bool" => 1,
+            "uint32_t" => 4,
+            "uint64_t" => 8,
+            other => panic!("unexpected field type {}", other),
+        };
+        offset = offset.next_multiple_of(size);
+        align = align.max(size);
+        fields.push((name.trim().to_string(), offset));
+        offset += size;
+    }
+    let expected = vec![
+        ("allowed".to_string(), offset_of!(ArlDecision, allowed)),
+        ("count".to_string(), offset_of!(ArlDecision, count)),
+        ("limit".to_string(), offset_of!(ArlDecision, limit)),
+        ("remaining".to_string(), offset_of!(ArlDecision, remaining)),
+        ("retry_after_ms".to_string(), offset_of!(ArlDecision, retry_after_ms)),
+    ];
+    assert_eq!(fields, expected);
+    assert_eq!(offset.next_multiple_of(align), size_of::<ArlDecision>());
+    assert_eq!(align, align_of::<ArlDecision>());
+}
@@ -101,3 +101,1943 @@ fn test_large_capacity() {
     // Now the counter resets and a new request is allowed.
     assert!(limiter.allow("127.0.0.1"));
 }
+
+#[test]
+fn test_stats_counts_outcomes_and_hot_keys() {
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 2, Duration::from_secs(5)).with_hot_key_capacity(2);
+
+    for _ in 0..3 {
+        limiter.allow("10.0.0.1");
+    }
+    limiter.allow("10.0.0.2");
+
+    let stats = limiter.stats();
+    assert_eq!(stats.checked, 4);
+    assert_eq!(stats.allowed, 3);
+    assert_eq!(stats.blocked, 1);
+    assert_eq!(stats.backend_errors, 0);
+    assert_eq!(stats.active_keys, Some(2));
+    assert_eq!(stats.hottest_keys[0], ("10.0.0.1".to_string(), 3));
+    assert_eq!(stats.hottest_keys.len(), 2);
+}
+
+#[test]
+fn test_usage_reports_count_and_reset() {
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 3, Duration::from_secs(5));
+
+    let usage = limiter.usage("10.0.0.1");
+    assert_eq!(usage.count, 0);
+    assert_eq!(usage.remaining, 3);
+    assert_eq!(usage.resets_in, None);
+
+    limiter.allow("10.0.0.1");
+    limiter.allow("10.0.0.1");
+
+    let usage = limiter.usage("10.0.0.1");
+    assert_eq!(usage.count, 2);
+    assert_eq!(usage.limit, 3);
+    assert_eq!(usage.remaining, 1);
+    let resets_in = usage.resets_in.expect("window should be active");
+    assert!(resets_in <= Duration::from_secs(5) && resets_in > Duration::from_secs(4));
+}
+
+#[test]
+fn test_adaptive_limiter_aimd() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use api_rate_limiter::adaptive::AdaptiveLimiter;
+
+    let overloaded = Arc::new(AtomicBool::new(true));
+    let signal = {
+        let overloaded = Arc::clone(&overloaded);
+        move || overloaded.load(Ordering::Relaxed)
+    };
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 10, Duration::from_secs(5));
+    let adaptive = AdaptiveLimiter::new(limiter, signal)
+        .with_bounds(2, 10)
+        .with_increase(3);
+
+    // Overload halves the limit, but never below the minimum.
+    assert_eq!(adaptive.adjust(), 5);
+    assert_eq!(adaptive.adjust(), 2);
+    assert_eq!(adaptive.adjust(), 2);
+
+    // Requests are checked against the reduced limit.
+    assert!(adaptive.allow("10.0.0.1"));
+    assert!(adaptive.allow("10.0.0.1"));
+    assert!(!adaptive.allow("10.0.0.1"));
+
+    // Recovery is additive and capped at the maximum.
+    overloaded.store(false, Ordering::Relaxed);
+    assert_eq!(adaptive.adjust(), 5);
+    assert_eq!(adaptive.adjust(), 8);
+    assert_eq!(adaptive.adjust(), 10);
+    assert!(adaptive.allow("10.0.0.1"));
+}
+
+#[test]
+fn test_priority_reserves_shed_best_effort_first() {
+    use api_rate_limiter::priority::{Priority, PriorityReserves};
+
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 5, Duration::from_secs(5))
+        .with_priority_reserves(PriorityReserves { critical: 1, normal: 2 });
+
+    // Best-effort traffic may only use the first 2 slots.
+    assert!(limiter.check_with_priority("10.0.0.1", Priority::BestEffort).allowed);
+    assert!(limiter.check_with_priority("10.0.0.1", Priority::BestEffort).allowed);
+    let decision = limiter.check_with_priority("10.0.0.1", Priority::BestEffort);
+    assert!(!decision.allowed);
+    assert_eq!(decision.priority, Priority::BestEffort);
+    assert_eq!(decision.remaining, 0);
+
+    // Normal traffic reaches 4, leaving the last slot for critical traffic.
+    let decision = limiter.check("10.0.0.1");
+    assert!(decision.allowed);
+    assert_eq!(decision.priority, Priority::Normal);
+    assert_eq!(decision.remaining, 1);
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(!limiter.allow("10.0.0.1"));
+
+    let decision = limiter.check_with_priority("10.0.0.1", Priority::Critical);
+    assert!(decision.allowed);
+    assert_eq!(decision.count, 5);
+    assert!(!limiter.check_with_priority("10.0.0.1", Priority::Critical).allowed);
+}
+
+#[test]
+fn test_debt_repayment_keeps_concurrent_requests() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use api_rate_limiter::limiter::CacheBackend;
+
+    /// Lets another request open each window just after a check has found
+    /// it empty.
+    struct Racing {
+        inner: InMemoryCache,
+        raced: AtomicBool,
+    }
+
+    impl CacheBackend for Racing {
+        fn get(&self, key: &str) -> Option<u32> {
+            let value = self.inner.get(key);
+            if value.is_none() && key.starts_with("rate_limit:") && !self.raced.swap(true, Ordering::Relaxed) {
+                self.inner.incr(key, 1, Duration::from_millis(300)).unwrap();
+            }
+            value
+        }
+
+        fn set(&self, key: &str, value: u32, ttl: Duration) -> Result<(), String> {
+            self.inner.set(key, value, ttl)
+        }
+
+        fn incr(&self, key: &str, amount: u32, ttl: Duration) -> Result<u32, String> {
+            self.inner.incr(key, amount, ttl)
+        }
+
+        fn incr_within(&self, key: &str, amount: u32, ceiling: u32, ttl: Duration) -> Result<(bool, u32), String> {
+            self.inner.incr_within(key, amount, ceiling, ttl)
+        }
+
+        fn compare_and_swap(&self, key: &str, old: Option<u32>, new: u32, ttl: Duration) -> Result<bool, String> {
+            self.inner.compare_and_swap(key, old, new, ttl)
+        }
+    }
+
+    let cache = Arc::new(Racing {
+        inner: InMemoryCache::new(),
+        raced: AtomicBool::new(true),
+    });
+    let limiter = RateLimiter::new(Arc::clone(&cache), 3, Duration::from_millis(300)).with_max_debt(2);
+    for _ in 0..5 {
+        assert!(limiter.allow("10.0.0.1"));
+    }
+
+    thread::sleep(Duration::from_millis(350));
+    cache.raced.store(false, Ordering::Relaxed);
+    // The debt of 2, the racing request and this one are all counted.
+    assert_eq!(limiter.check("10.0.0.1").count, 4);
+}
+
+#[test]
+fn test_rejected_requests_are_not_counted_without_decr() {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use api_rate_limiter::limiter::CacheBackend;
+    use api_rate_limiter::priority::{Priority, PriorityReserves};
+
+    /// A backend with only the required operations, so the limiter goes
+    /// through the default `incr_within` with no `decr` to undo increments.
+    #[derive(Default)]
+    struct NoDecr(Mutex<HashMap<String, u32>>);
+
+    impl CacheBackend for NoDecr {
+        fn get(&self, key: &str) -> Option<u32> {
+            self.0.lock().unwrap().get(key).copied()
+        }
+
+        fn set(&self, key: &str, value: u32, _ttl: Duration) -> Result<(), String> {
+            self.0.lock().unwrap().insert(key.to_string(), value);
+            Ok(())
+        }
+
+        fn incr(&self, key: &str, amount: u32, _ttl: Duration) -> Result<u32, String> {
+            let mut counts = self.0.lock().unwrap();
+            let count = counts.entry(key.to_string()).or_insert(0);
+            *count += amount;
+            Ok(*count)
+        }
+    }
+
+    let limiter = RateLimiter::new(Arc::new(NoDecr::default()), 10, Duration::from_secs(60))
+        .with_priority_reserves(PriorityReserves { critical: 5, normal: 0 });
+    for _ in 0..20 {
+        limiter.check_with_priority("10.0.0.1", Priority::BestEffort);
+    }
+
+    // The 15 rejected best-effort requests left the critical reserve intact.
+    let decision = limiter.check_with_priority("10.0.0.1", Priority::Critical);
+    assert!(decision.allowed);
+    assert_eq!(decision.count, 6);
+}
+
+#[test]
+fn test_debt_is_repaid_in_next_window() {
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 3, Duration::from_millis(300)).with_max_debt(2);
+
+    for _ in 0..3 {
+        assert!(!limiter.check("10.0.0.1").borrowed);
+    }
+    // Two more requests are admitted on borrowed quota, then the key blocks.
+    let decision = limiter.check("10.0.0.1");
+    assert!(decision.allowed && decision.borrowed);
+    assert_eq!(decision.remaining, 0);
+    assert!(limiter.check("10.0.0.1").allowed);
+    assert!(!limiter.allow("10.0.0.1"));
+
+    // The next window starts with the 2 borrowed requests already counted.
+    thread::sleep(Duration::from_millis(350));
+    let decision = limiter.check("10.0.0.1");
+    assert!(decision.allowed && !decision.borrowed);
+    assert_eq!(decision.count, 3);
+    assert_eq!(limiter.usage("10.0.0.1").remaining, 0);
+}
+
+#[test]
+fn test_warm_up_ramps_new_keys() {
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 10, Duration::from_millis(200))
+        .with_warm_up(2, Duration::from_millis(800));
+
+    // A brand-new key starts at the initial limit.
+    let decision = limiter.check("10.0.0.1");
+    assert_eq!(decision.limit, 2);
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(!limiter.allow("10.0.0.1"));
+
+    // Halfway through the warm-up the limit has ramped part of the way.
+    thread::sleep(Duration::from_millis(450));
+    let limit = limiter.check("10.0.0.1").limit;
+    assert!(limit > 2 && limit < 10, "limit was {}", limit);
+
+    // After the warm-up period the full limit applies.
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(limiter.check("10.0.0.1").limit, 10);
+}
+
+#[test]
+fn test_schedule_overrides_limit() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::UNIX_EPOCH;
+    use api_rate_limiter::schedule::{Schedule, Weekday};
+
+    // Monday 2024-01-01 10:00 UTC.
+    let now = Arc::new(AtomicU64::new(1_704_103_200));
+    let clock = {
+        let now = Arc::clone(&now);
+        move || UNIX_EPOCH + Duration::from_secs(now.load(Ordering::Relaxed))
+    };
+    let schedule = Schedule::new()
+        .with_clock(Arc::new(clock))
+        .rule(&Weekday::WORKDAYS, (9, 0), (17, 0), 3)
+        .rule(&Weekday::ALL, (0, 0), (6, 0), 1);
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 2, Duration::from_secs(5)).with_schedule(schedule);
+
+    assert_eq!(limiter.current_limit(), 3);
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(!limiter.allow("10.0.0.1"));
+
+    // Overnight the stricter profile applies.
+    now.store(1_704_103_200 + 15 * 3600, Ordering::Relaxed);
+    assert_eq!(limiter.current_limit(), 1);
+
+    // Outside every rule the configured limit applies.
+    now.store(1_704_103_200 + 9 * 3600, Ordering::Relaxed);
+    assert_eq!(limiter.current_limit(), 2);
+}
+
+#[test]
+fn test_replicated_backend_merges_regions() {
+    use std::sync::Mutex;
+    use api_rate_limiter::cache::replicated::{CounterDelta, LimitScope, ReplicatedBackend};
+    use api_rate_limiter::limiter::CacheBackend;
+
+    let outbox: Arc<Mutex<Vec<CounterDelta>>> = Arc::new(Mutex::new(Vec::new()));
+    let transport = {
+        let outbox = Arc::clone(&outbox);
+        move |delta: &CounterDelta| {
+            outbox.lock().unwrap().push(delta.clone());
+            Ok(())
+        }
+    };
+    let eu = Arc::new(ReplicatedBackend::new("eu", InMemoryCache::new(), transport));
+    let us = ReplicatedBackend::new("us", InMemoryCache::new(), |_: &CounterDelta| Ok(()))
+        .with_scope(LimitScope::Regional);
+
+    // A global limit of 3 shared between regions.
+    let limiter = RateLimiter::new(Arc::clone(&eu), 3, Duration::from_secs(5));
+    assert!(limiter.allow("10.0.0.1"));
+
+    // The US region reports 2 requests for the same key.
+    let remote = CounterDelta {
+        region: "us".to_string(),
+        key: "rate_limit:10.0.0.1".to_string(),
+        count: 2,
+        ttl: Some(Duration::from_secs(5)),
+    };
+    eu.apply(&remote);
+    // Duplicate and stale deltas do not double count.
+    eu.apply(&remote);
+    eu.apply(&CounterDelta { count: 1, ..remote.clone() });
+    assert_eq!(eu.global_count("rate_limit:10.0.0.1"), 3);
+    assert!(!limiter.allow("10.0.0.1"));
+
+    // Local increments were shipped with the local count.
+    let shipped = outbox.lock().unwrap();
+    let last = shipped.last().unwrap();
+    assert_eq!((last.region.as_str(), last.count), ("eu", 1));
+
+    // In regional scope only local counts are enforced.
+    us.apply(&CounterDelta { region: "eu".to_string(), ..remote });
+    assert_eq!(us.get("rate_limit:10.0.0.1"), None);
+    assert_eq!(us.global_count("rate_limit:10.0.0.1"), 2);
+}
+
+#[test]
+fn test_replicated_backend_batches_and_sweeps() {
+    use std::sync::Mutex;
+    use api_rate_limiter::cache::replicated::{CounterDelta, ReplicatedBackend, ReplicationTransport};
+    use api_rate_limiter::limiter::CacheBackend;
+
+    #[derive(Clone, Default)]
+    struct Batches(Arc<Mutex<Vec<Vec<CounterDelta>>>>);
+
+    impl ReplicationTransport for Batches {
+        fn send(&self, delta: &CounterDelta) -> Result<(), String> {
+            self.send_batch(std::slice::from_ref(delta))
+        }
+
+        fn send_batch(&self, deltas: &[CounterDelta]) -> Result<(), String> {
+            self.0.lock().unwrap().push(deltas.to_vec());
+            Ok(())
+        }
+    }
+
+    let batches = Batches::default();
+    let backend = ReplicatedBackend::new("eu", InMemoryCache::new(), batches.clone())
+        .with_batching(3, Duration::from_secs(3600));
+    let ttl = Duration::from_secs(5);
+    // Repeated increments of one key are coalesced into its latest count.
+    for _ in 0..5 {
+        backend.incr("a", 1, ttl).unwrap();
+    }
+    backend.incr("b", 1, ttl).unwrap();
+    assert!(batches.0.lock().unwrap().is_empty());
+    backend.incr("c", 1, ttl).unwrap();
+    backend.incr("d", 1, ttl).unwrap();
+    backend.flush().unwrap();
+    {
+        let sent = batches.0.lock().unwrap();
+        let sizes: Vec<usize> = sent.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [3, 1]);
+        assert!(sent[0].iter().any(|delta| delta.key == "a" && delta.count == 5));
+    }
+
+    // Expired remote counts are dropped rather than kept forever.
+    for key in ["x", "y"] {
+        backend.apply(&CounterDelta {
+            region: "us".to_string(),
+            key: key.to_string(),
+            count: 4,
+            ttl: Some(Duration::from_millis(50)),
+        });
+    }
+    assert_eq!(backend.remote_count("x"), 4);
+    thread::sleep(Duration::from_millis(60));
+    assert_eq!(backend.sweep(), 2);
+    assert_eq!(backend.sweep(), 0);
+
+    // A flusher sends the last deltas once they are due, without waiting
+    // for another increment.
+    let backend = Arc::new(
+        ReplicatedBackend::new("eu", InMemoryCache::new(), batches.clone())
+            .with_batching(100, Duration::from_millis(20)),
+    );
+    let _flusher = ReplicatedBackend::spawn_flusher(&backend, Duration::from_millis(5));
+    backend.incr("e", 1, ttl).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let sent = batches.0.lock().unwrap();
+    assert_eq!(sent.len(), 3);
+    assert!(sent[2].iter().any(|delta| delta.key == "e" && delta.count == 1));
+}
+
+#[test]
+fn test_state_backend_compare_and_swap() {
+    use api_rate_limiter::state::StateBackend;
+
+    let cache = InMemoryCache::new();
+    let ttl = Duration::from_secs(5);
+    assert!(cache.compare_and_swap_state("k", None, b"one", ttl).unwrap());
+    // A stale expectation is rejected and leaves the state untouched.
+    assert!(!cache.compare_and_swap_state("k", None, b"two", ttl).unwrap());
+    assert!(cache.compare_and_swap_state("k", Some(b"one"), b"two", ttl).unwrap());
+    assert_eq!(cache.get_state("k"), Some(b"two".to_vec()));
+}
+
+#[test]
+fn test_gcra_limiter_spaces_requests() {
+    use api_rate_limiter::gcra::GcraLimiter;
+
+    let cache = Arc::new(InMemoryCache::new());
+    // 4 requests per 400ms: bursts of 4, then one request every 100ms.
+    let limiter = GcraLimiter::new(cache, 4, Duration::from_millis(400));
+    for _ in 0..4 {
+        assert!(limiter.allow("10.0.0.1"));
+    }
+    assert!(!limiter.allow("10.0.0.1"));
+
+    // Capacity is regained gradually rather than all at once.
+    thread::sleep(Duration::from_millis(120));
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(!limiter.allow("10.0.0.1"));
+}
+
+#[test]
+fn test_cache_compare_and_swap_and_update_with() {
+    use api_rate_limiter::limiter::CacheBackend;
+
+    let cache = InMemoryCache::new();
+    let ttl = Duration::from_secs(5);
+    assert!(cache.compare_and_swap("k", None, 5, ttl).unwrap());
+    assert!(!cache.compare_and_swap("k", Some(4), 9, ttl).unwrap());
+    assert!(cache.compare_and_swap("k", Some(5), 9, ttl).unwrap());
+    assert_eq!(cache.get("k"), Some(9));
+
+    // update_with runs the closure under the entry lock.
+    assert_eq!(cache.update_with("k", ttl, |current| current.unwrap_or(0) * 2), 18);
+    assert_eq!(cache.update_with("fresh", ttl, |current| current.map_or(1, |c| c + 1)), 1);
+    assert!(cache.ttl("fresh").is_some());
+}
+
+#[test]
+fn test_incr_sets_ttl_atomically() {
+    use api_rate_limiter::limiter::CacheBackend;
+
+    let cache = InMemoryCache::new();
+    // The first increment creates the window with its TTL in one step, so a
+    // reader immediately sees a live count rather than an expired entry.
+    assert_eq!(cache.incr("k", 1, Duration::from_millis(200)).unwrap(), 1);
+    assert_eq!(cache.get("k"), Some(1));
+    assert!(cache.ttl("k").is_some());
+    // Later increments keep the window's TTL.
+    assert_eq!(cache.incr("k", 2, Duration::from_secs(60)).unwrap(), 3);
+    assert!(cache.ttl("k").unwrap() <= Duration::from_millis(200));
+
+    thread::sleep(Duration::from_millis(250));
+    assert_eq!(cache.get("k"), None);
+    assert_eq!(cache.incr("k", 1, Duration::from_secs(1)).unwrap(), 1);
+}
+
+#[test]
+fn test_retry_after_reports_time_until_reset() {
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 1, Duration::from_secs(3));
+
+    assert!(limiter.check("10.0.0.1").retry_after.is_none());
+    let decision = limiter.check("10.0.0.1");
+    assert!(!decision.allowed);
+    let wait = decision.retry_after.expect("blocked decision should carry retry_after");
+    assert!(wait <= Duration::from_secs(3) && wait > Duration::from_secs(2));
+
+    assert!(limiter.retry_after("10.0.0.1").is_some());
+    assert_eq!(limiter.retry_after("10.0.0.2"), None);
+}
+
+#[test]
+fn test_check_all_reports_failing_dimension() {
+    use api_rate_limiter::composite::KeyedPolicy;
+
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 100, Duration::from_secs(5));
+    let window = Duration::from_secs(5);
+    let policies = |ip| {
+        [
+            KeyedPolicy { name: "ip", key: ip, limit: 3, window },
+            KeyedPolicy { name: "api_key", key: "key-123", limit: 4, window },
+        ]
+    };
+
+    for _ in 0..3 {
+        assert!(limiter.check_all(&policies("10.0.0.1")).allowed);
+    }
+    let decision = limiter.check_all(&policies("10.0.0.1"));
+    assert!(!decision.allowed);
+    assert_eq!(decision.failed.as_deref(), Some("ip"));
+    // Only the full dimension says when to retry.
+    let wait = decision.decisions[0].retry_after.expect("full dimension should carry retry_after");
+    assert!(wait <= window && wait > Duration::from_secs(4));
+    assert_eq!(decision.decisions[1].retry_after, None);
+    // Single-key checks of a client named like a dimension keep their own counter.
+    assert_eq!(limiter.check("ip:10.0.0.1").count, 1);
+
+    // The blocked request consumed nothing from the API key dimension.
+    let decision = limiter.check_all(&policies("10.0.0.2"));
+    assert!(decision.allowed);
+    assert_eq!(decision.decisions[1].count, 4);
+
+    let decision = limiter.check_all(&policies("10.0.0.3"));
+    assert_eq!(decision.failed.as_deref(), Some("api_key"));
+    assert_eq!(decision.decisions[0].count, 0);
+
+    // A new policy version starts every dimension afresh.
+    limiter.update_policy(1, 100, api_rate_limiter::rollout::Rollout::Immediate);
+    assert!(limiter.check_all(&policies("10.0.0.1")).allowed);
+}
+
+#[test]
+fn test_check_all_rolls_back_counted_dimensions() {
+    use api_rate_limiter::composite::KeyedPolicy;
+    use api_rate_limiter::limiter::CacheBackend;
+
+    /// Reads nothing, like a replica lagging behind concurrent writers, so
+    /// only the increments see the real counts.
+    struct StaleReads(InMemoryCache);
+
+    impl CacheBackend for StaleReads {
+        fn get(&self, _key: &str) -> Option<u32> {
+            None
+        }
+
+        fn set(&self, key: &str, value: u32, ttl: Duration) -> Result<(), String> {
+            self.0.set(key, value, ttl)
+        }
+
+        fn incr(&self, key: &str, amount: u32, ttl: Duration) -> Result<u32, String> {
+            self.0.incr(key, amount, ttl)
+        }
+
+        fn incr_within(&self, key: &str, amount: u32, ceiling: u32, ttl: Duration) -> Result<(bool, u32), String> {
+            self.0.incr_within(key, amount, ceiling, ttl)
+        }
+
+        fn decr(&self, key: &str, amount: u32) -> Result<u32, String> {
+            self.0.decr(key, amount)
+        }
+    }
+
+    let cache = Arc::new(StaleReads(InMemoryCache::new()));
+    let limiter = RateLimiter::new(Arc::clone(&cache), 100, Duration::from_secs(5));
+    let window = Duration::from_secs(5);
+    let policies = |ip| {
+        [
+            KeyedPolicy { name: "ip", key: ip, limit: 3, window },
+            KeyedPolicy { name: "api_key", key: "key-123", limit: 1, window },
+            KeyedPolicy { name: "endpoint", key: "/search", limit: 3, window },
+        ]
+    };
+
+    assert!(limiter.check_all(&policies("10.0.0.1")).allowed);
+    let decision = limiter.check_all(&policies("10.0.0.2"));
+    assert_eq!(decision.failed.as_deref(), Some("api_key"));
+
+    // The rejected request took nothing from the IP it did count, and never
+    // reached the endpoint.
+    assert_eq!(decision.decisions[0].count, 0);
+    assert!(decision.decisions.iter().all(|decision| !decision.allowed));
+    assert_eq!(cache.0.get("rate_limit_policy:ip:10.0.0.2"), Some(0));
+    assert_eq!(cache.0.get("rate_limit_policy:endpoint:/search"), Some(1));
+}
+
+#[test]
+fn test_group_resolver_shares_quota() {
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 3, Duration::from_secs(5))
+        .with_group_resolver(|key: &str| key.strip_prefix("acme-").map(|_| "acme".to_string()));
+
+    // Keys of the same organization draw from one counter.
+    assert!(limiter.allow("acme-key-1"));
+    assert!(limiter.allow("acme-key-2"));
+    assert!(limiter.allow("acme-key-1"));
+    assert!(!limiter.allow("acme-key-3"));
+    assert_eq!(limiter.usage("acme-key-2").count, 3);
+
+    // Keys outside any group keep their own quota.
+    assert!(limiter.allow("other-key"));
+    assert_eq!(limiter.usage("other-key").count, 1);
+}
+
+#[test]
+fn test_debouncer_suppresses_duplicates() {
+    use api_rate_limiter::debounce::Debouncer;
+    use api_rate_limiter::hashing::KeyHasher;
+    use api_rate_limiter::limiter::CacheBackend;
+
+    let cache = Arc::new(InMemoryCache::new());
+    let debouncer = Arc::new(
+        Debouncer::new(cache.clone(), Duration::from_millis(200)).with_key_hasher(KeyHasher::new()),
+    );
+
+    // Exactly one of several concurrent deliveries is treated as new.
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let debouncer = Arc::clone(&debouncer);
+            thread::spawn(move || debouncer.first_seen("webhook:evt_1").unwrap())
+        })
+        .collect();
+    let firsts = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .filter(|first| *first)
+        .count();
+    assert_eq!(firsts, 1);
+    assert!(debouncer.seen("webhook:evt_1"));
+    assert!(!debouncer.seen("webhook:evt_2"));
+    assert_eq!(cache.get("debounce:webhook:evt_1"), None);
+
+    // After the window the key is forgotten.
+    thread::sleep(Duration::from_millis(250));
+    assert!(debouncer.first_seen("webhook:evt_1").unwrap());
+}
+
+#[test]
+fn test_usage_series_buckets_requests() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::UNIX_EPOCH;
+    use api_rate_limiter::series::RingBufferRecorder;
+
+    let now = Arc::new(AtomicU64::new(1_704_067_200));
+    let clock = {
+        let now = Arc::clone(&now);
+        move || UNIX_EPOCH + Duration::from_secs(now.load(Ordering::Relaxed))
+    };
+    // 1-minute buckets kept for 3 minutes.
+    let recorder = RingBufferRecorder::new(Duration::from_secs(60), Duration::from_secs(180))
+        .with_clock(Arc::new(clock));
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 2, Duration::from_secs(5))
+        .with_usage_recorder(Arc::new(recorder));
+
+    limiter.allow("10.0.0.1");
+    limiter.allow("10.0.0.1");
+    // Blocked requests are not recorded as usage.
+    limiter.allow("10.0.0.1");
+    now.fetch_add(60, Ordering::Relaxed);
+    limiter.allow("10.0.0.2");
+
+    let series = limiter.usage_series("10.0.0.1");
+    assert_eq!(series.len(), 1);
+    assert_eq!(series[0].count, 2);
+    assert_eq!(series[0].start, UNIX_EPOCH + Duration::from_secs(1_704_067_200));
+
+    // Buckets older than the retention drop out of the series.
+    now.fetch_add(180, Ordering::Relaxed);
+    assert!(limiter.usage_series("10.0.0.1").is_empty());
+}
+
+#[test]
+fn test_usage_series_drops_idle_keys() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::UNIX_EPOCH;
+    use api_rate_limiter::series::{RingBufferRecorder, UsageRecorder};
+
+    let now = Arc::new(AtomicU64::new(1_704_067_200));
+    let clock = {
+        let now = Arc::clone(&now);
+        move || UNIX_EPOCH + Duration::from_secs(now.load(Ordering::Relaxed))
+    };
+    let recorder = RingBufferRecorder::new(Duration::from_secs(60), Duration::from_secs(180))
+        .with_clock(Arc::new(clock));
+    for i in 0..100 {
+        recorder.record(&format!("10.0.0.{}", i), 1);
+    }
+    now.fetch_add(120, Ordering::Relaxed);
+    recorder.record("10.0.0.1", 1);
+    assert_eq!(recorder.key_count(), 100);
+
+    // Once a key's last request leaves the retention, the purge run every
+    // 1024 records drops it.
+    now.fetch_add(60, Ordering::Relaxed);
+    for _ in 0..922 {
+        recorder.record("10.0.1.1", 1);
+    }
+    assert_eq!(recorder.key_count(), 101);
+    recorder.record("10.0.1.1", 1);
+    assert_eq!(recorder.key_count(), 2);
+    assert_eq!(recorder.series("10.0.0.1").len(), 1);
+    assert_eq!(recorder.purge_idle(), 0);
+}
+
+#[test]
+fn test_local_lock_free_strategy() {
+    use api_rate_limiter::limiter::Strategy;
+
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = Arc::new(
+        RateLimiter::new(cache, 100, Duration::from_secs(10)).with_strategy(Strategy::LocalLockFree),
+    );
+
+    // Concurrent checks never admit more than the burst.
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let limiter = Arc::clone(&limiter);
+            thread::spawn(move || (0..50).filter(|_| limiter.allow("10.0.0.1")).count())
+        })
+        .collect();
+    let allowed: usize = handles.into_iter().map(|handle| handle.join().unwrap()).sum();
+    assert_eq!(allowed, 100);
+
+    let decision = limiter.check("10.0.0.1");
+    assert!(!decision.allowed);
+    assert_eq!(decision.remaining, 0);
+    // One slot is regained every 100ms.
+    assert!(decision.retry_after.unwrap() <= Duration::from_millis(100));
+    assert!(limiter.retry_after("10.0.0.1").is_some_and(|wait| wait <= Duration::from_millis(100)));
+    let usage = limiter.usage("10.0.0.1");
+    assert_eq!((usage.count, usage.remaining), (100, 0));
+    assert!(usage.resets_in.is_some_and(|left| left > Duration::from_secs(9)));
+    assert_eq!(limiter.retry_after("10.0.0.9"), None);
+    assert_eq!(limiter.usage("10.0.0.9").count, 0);
+    // The backend is not used by this strategy.
+    assert_eq!(limiter.stats().active_keys, Some(0));
+
+    // Costs far beyond a long window's limit are blocked instead of overflowing.
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 1, Duration::from_secs(1_000_000_000))
+        .with_strategy(Strategy::LocalLockFree);
+    assert!(!limiter.allow_n("10.0.0.1", 100));
+    assert!(limiter.allow("10.0.0.1"));
+}
+
+#[test]
+fn test_policy_rollout_modes() {
+    use api_rate_limiter::rollout::Rollout;
+
+    let cache = Arc::new(InMemoryCache::new());
+    let limiter = RateLimiter::new(cache, 5, Duration::from_millis(300));
+    for _ in 0..3 {
+        assert!(limiter.allow("10.0.0.1"));
+    }
+
+    // Lowering the limit waits for the live window to end.
+    limiter.update_policy(1, 2, Rollout::NextWindow);
+    assert_eq!(limiter.policy_version(), 1);
+    assert_eq!(limiter.usage("10.0.0.1").limit, 5);
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(!limiter.allow("10.0.0.1"));
+    // Keys without a live window move to the new policy at once.
+    assert_eq!(limiter.check("10.0.0.2").limit, 2);
+
+    thread::sleep(Duration::from_millis(350));
+    assert_eq!(limiter.usage("10.0.0.1").limit, 2);
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(!limiter.allow("10.0.0.1"));
+
+    // An immediate change starts counting from zero under the new version.
+    limiter.update_policy(2, 4, Rollout::Immediate);
+    for _ in 0..4 {
+        assert!(limiter.allow("10.0.0.1"));
+    }
+    assert!(!limiter.allow("10.0.0.1"));
+}
+
+#[test]
+fn test_audit_log_records_blocked_requests() {
+    use api_rate_limiter::audit::{hash_key, JsonLinesSink};
+
+    let path = std::env::temp_dir().join(format!("arl-audit-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sink = Arc::new(JsonLinesSink::open(&path).unwrap());
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 2, Duration::from_secs(10))
+        .with_audit_sink(sink.clone());
+
+    for _ in 0..4 {
+        limiter.allow("10.0.0.1");
+    }
+    limiter.flush().unwrap();
+
+    // Only the two blocked requests are logged, without the raw key.
+    let log = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains(&format!("\"key_hash\":\"{:016x}\"", hash_key("10.0.0.1"))));
+    assert!(lines[0].contains("\"limit\":2,\"count\":2"));
+    assert!(!log.contains("10.0.0.1"));
+    assert_eq!(sink.write_errors(), 0);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_canary_limiter_tracks_divergence() {
+    use api_rate_limiter::canary::{CanaryLimiter, Divergence};
+
+    let cache = Arc::new(InMemoryCache::new());
+    let window = Duration::from_secs(10);
+    let canary = CanaryLimiter::new(
+        RateLimiter::new(cache.clone(), 3, window),
+        RateLimiter::new(cache, 2, window),
+    );
+
+    // The enforced limit of 3 applies; the candidate only observes.
+    let allowed = (0..4).filter(|_| canary.allow("10.0.0.1")).count();
+    assert_eq!(allowed, 3);
+    assert_eq!(
+        canary.divergence(),
+        Divergence {
+            compared: 4,
+            candidate_stricter: 1,
+            candidate_looser: 0,
+        }
+    );
+    assert_eq!(canary.candidate().stats().blocked, 2);
+    // Sharing a backend and a policy version, each still counts only its own.
+    assert_eq!(canary.primary().usage("10.0.0.1").count, 3);
+    assert_eq!(canary.candidate().usage("10.0.0.1").count, 2);
+}
+
+#[test]
+fn test_key_namespaces_separate_limiters_on_one_backend() {
+    use api_rate_limiter::limiter::CacheBackend;
+    use api_rate_limiter::rate::Rate;
+
+    let cache = Arc::new(InMemoryCache::new());
+    let window = Duration::from_secs(10);
+    let search = RateLimiter::new(cache.clone(), 1, window).with_key_namespace("search:");
+    let checkout = RateLimiter::new(cache.clone(), 1, window)
+        .with_key_namespace("checkout:")
+        .with_global_limit(Rate::new(1, window));
+    assert!(search.allow("10.0.0.1"));
+    assert!(checkout.allow("10.0.0.1"));
+    assert!(!search.allow("10.0.0.1"));
+    assert_eq!(cache.get("search:rate_limit:10.0.0.1"), Some(1));
+    assert_eq!(cache.get("checkout:rate_limit_global"), Some(1));
+    assert_eq!(cache.get("rate_limit_global"), None);
+}
+
+#[test]
+fn test_key_hasher_keeps_raw_keys_out_of_backend() {
+    use api_rate_limiter::hashing::KeyHasher;
+    use api_rate_limiter::limiter::CacheBackend;
+
+    let cache = Arc::new(InMemoryCache::new());
+    let hasher = KeyHasher::with_secret(b"shared secret");
+    let limiter = RateLimiter::new(cache.clone(), 2, Duration::from_secs(10))
+        .with_key_hasher(hasher.clone());
+
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(limiter.allow("10.0.0.1"));
+    assert!(!limiter.allow("10.0.0.1"));
+    assert_eq!(limiter.usage("10.0.0.1").count, 2);
+
+    let hashed = hasher.hash_hex("10.0.0.1");
+    assert_eq!(cache.get("rate_limit:10.0.0.1"), None);
+    assert_eq!(cache.get(&format!("rate_limit:{}", hashed)), Some(2));
+    assert_eq!(limiter.stats().hottest_keys, vec![(hashed, 3)]);
+}
+
+#[test]
+fn test_health_check_reports_backend_state() {
+    use api_rate_limiter::limiter::CacheBackend;
+
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 10, Duration::from_secs(1));
+    let health = limiter.health_check();
+    assert!(health.is_ready());
+    assert_eq!(health.error, None);
+
+    /// A backend whose every write fails, as if the server were down.
+    struct Down;
+
+    impl CacheBackend for Down {
+        fn get(&self, _key: &str) -> Option<u32> {
+            None
+        }
+
+        fn set(&self, _key: &str, _value: u32, _ttl: Duration) -> Result<(), String> {
+            Err("connection refused".to_string())
+        }
+
+        fn incr(&self, _key: &str, _amount: u32, _ttl: Duration) -> Result<u32, String> {
+            Err("connection refused".to_string())
+        }
+    }
+
+    let health = RateLimiter::new(Arc::new(Down), 10, Duration::from_secs(1)).health_check();
+    assert!(!health.is_ready());
+    assert_eq!(health.error.as_deref(), Some("connection refused"));
+}
+
+#[test]
+fn test_check_route_applies_per_route_limits() {
+    use api_rate_limiter::route::RouteTable;
+
+    let minute = Duration::from_secs(60);
+    let routes = RouteTable::new()
+        .route("POST", "/login", "login", 2, minute)
+        .route("GET", "/status", "status", 100, minute);
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 5, minute);
+
+    assert!(limiter.check_route(&routes, "POST", "/login", "10.0.0.1").allowed);
+    assert!(limiter.check_route(&routes, "POST", "/login", "10.0.0.1").allowed);
+    let blocked = limiter.check_route(&routes, "POST", "/login", "10.0.0.1");
+    assert!(!blocked.allowed);
+    assert_eq!(blocked.limit, 2);
+
+    // Other routes keep their own counters.
+    assert_eq!(limiter.check_route(&routes, "GET", "/status", "10.0.0.1").limit, 100);
+    // Unmatched requests fall back to the limiter's own limit.
+    assert_eq!(limiter.check_route(&routes, "GET", "/other", "10.0.0.1").limit, 5);
+}
+
+#[test]
+fn test_weighted_checks_with_cost_extractor() {
+    use api_rate_limiter::limiter::Strategy;
+
+    struct Request {
+        body: Vec<u8>,
+    }
+
+    // One unit per started kilobyte of body.
+    let per_kilobyte = |request: &Request| (request.body.len() as u32).div_ceil(1024).max(1);
+
+    for strategy in [Strategy::FixedWindow, Strategy::LocalLockFree] {
+        let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 10, Duration::from_secs(60))
+            .with_strategy(strategy);
+
+        let upload = Request { body: vec![0; 6 * 1024] };
+        let decision = limiter.check_request("10.0.0.1", &upload, &per_kilobyte);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 4);
+
+        // A second upload does not fit and consumes nothing.
+        assert!(!limiter.check_request("10.0.0.1", &upload, &per_kilobyte).allowed);
+        assert!(limiter.allow_n("10.0.0.1", 4));
+        assert!(!limiter.allow("10.0.0.1"));
+    }
+}
+
+#[test]
+fn test_on_response_counts_only_failed_logins() {
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 3, Duration::from_secs(60));
+    let failed_logins = |status: u16| status == 401;
+
+    // Successful logins are refunded, so they never use up attempts.
+    for _ in 0..5 {
+        let decision = limiter.check("10.0.0.1");
+        assert!(decision.allowed);
+        assert!(limiter.on_response("10.0.0.1", &decision, 1, 200, &failed_logins));
+    }
+    assert_eq!(limiter.usage("10.0.0.1").count, 0);
+
+    for _ in 0..3 {
+        let decision = limiter.check("10.0.0.1");
+        assert!(decision.allowed);
+        assert!(!limiter.on_response("10.0.0.1", &decision, 1, 401, &failed_logins));
+    }
+    let blocked = limiter.check("10.0.0.1");
+    assert!(!blocked.allowed);
+    // Blocked requests consumed nothing, so there is nothing to refund.
+    assert!(!limiter.on_response("10.0.0.1", &blocked, 1, 200, &failed_logins));
+
+    assert_eq!(limiter.refund("10.0.0.1", 5), Ok(0));
+    assert_eq!(limiter.refund("10.0.0.9", 1), Ok(0));
+
+    // A response arriving after its window expired is not refunded from the next one.
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 3, Duration::from_millis(100));
+    let decision = limiter.check("10.0.0.1");
+    thread::sleep(Duration::from_millis(150));
+    assert!(limiter.allow_n("10.0.0.1", 3));
+    limiter.on_response("10.0.0.1", &decision, 1, 200, &failed_logins);
+    assert!(!limiter.allow("10.0.0.1"));
+}
+
+#[test]
+fn test_refund_returns_quota_for_each_strategy() {
+    use api_rate_limiter::limiter::Strategy;
+
+    for strategy in [Strategy::FixedWindow, Strategy::LocalLockFree] {
+        let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 5, Duration::from_secs(60))
+            .with_strategy(strategy);
+        assert!(limiter.allow_n("10.0.0.1", 5));
+        assert!(!limiter.allow("10.0.0.1"));
+
+        // A cancelled operation gives back two units.
+        assert_eq!(limiter.refund("10.0.0.1", 2), Ok(3));
+        assert!(limiter.allow_n("10.0.0.1", 2));
+        assert!(!limiter.allow("10.0.0.1"));
+
+        // Refunds never go below zero.
+        assert_eq!(limiter.refund("10.0.0.1", 100), Ok(0));
+    }
+}
+
+#[test]
+fn test_would_allow_does_not_consume() {
+    use api_rate_limiter::limiter::Strategy;
+
+    for strategy in [Strategy::FixedWindow, Strategy::LocalLockFree] {
+        let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 3, Duration::from_secs(60))
+            .with_strategy(strategy);
+        for _ in 0..10 {
+            assert!(limiter.would_allow("10.0.0.1").allowed);
+        }
+        assert!(limiter.allow_n("10.0.0.1", 2));
+
+        let peek = limiter.would_allow("10.0.0.1");
+        assert!(peek.allowed);
+        assert_eq!(peek.count, 2);
+        assert_eq!(peek.remaining, 1);
+
+        assert!(limiter.allow("10.0.0.1"));
+        let peek = limiter.would_allow("10.0.0.1");
+        assert!(!peek.allowed);
+        assert!(peek.retry_after.is_some());
+        assert_eq!(limiter.stats().checked, 2);
+    }
+}
+
+#[test]
+fn test_reservation_commit_cancel_and_drop() {
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 10, Duration::from_secs(60));
+
+    let reservation = limiter.reserve("10.0.0.1", 4).unwrap();
+    assert_eq!(reservation.decision().count, 4);
+    assert!(reservation.commit().is_ok());
+
+    assert_eq!(limiter.reserve("10.0.0.1", 4).unwrap().cancel(), Ok(4));
+    // Dropping an uncommitted reservation refunds it too.
+    drop(limiter.reserve("10.0.0.1", 6).unwrap());
+    assert_eq!(limiter.usage("10.0.0.1").count, 4);
+
+    let blocked = limiter.reserve("10.0.0.1", 7).err().unwrap();
+    assert!(!blocked.allowed);
+
+    // A commit after the timeout refunds instead.
+    let reservation = limiter
+        .reserve("10.0.0.1", 6)
+        .unwrap()
+        .with_timeout(Duration::from_millis(0));
+    assert!(reservation.is_expired());
+    assert!(reservation.commit().is_err());
+    assert_eq!(limiter.usage("10.0.0.1").count, 4);
+
+    // A forgotten reservation is cancelled by the next check after its timeout.
+    let forgotten = limiter
+        .reserve("10.0.0.1", 6)
+        .unwrap()
+        .with_timeout(Duration::from_millis(20));
+    assert!(!limiter.allow("10.0.0.1"));
+    thread::sleep(Duration::from_millis(30));
+    assert_eq!(limiter.check("10.0.0.1").count, 5);
+    // Settling it afterwards refunds nothing more.
+    assert_eq!(forgotten.cancel(), Ok(5));
+    assert_eq!(limiter.usage("10.0.0.1").count, 5);
+}
+
+#[test]
+fn test_reservation_refunds_only_its_own_window() {
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 10, Duration::from_millis(100));
+
+    let reservation = limiter.reserve("10.0.0.1", 6).unwrap();
+    thread::sleep(Duration::from_millis(150));
+    assert!(limiter.allow_n("10.0.0.1", 8));
+
+    // The reserved units left with the old window; the new one keeps its count.
+    assert_eq!(reservation.cancel(), Ok(8));
+    assert_eq!(limiter.usage("10.0.0.1").count, 8);
+}
+
+#[test]
+fn test_composite_and_route_decisions_are_reported() {
+    use std::sync::Mutex;
+    use api_rate_limiter::audit::{AuditRecord, AuditSink};
+    use api_rate_limiter::composite::KeyedPolicy;
+    use api_rate_limiter::logging::{DecisionLogger, Level};
+    use api_rate_limiter::rate::Rate;
+    use api_rate_limiter::route::RouteTable;
+    use api_rate_limiter::telemetry::DecisionEvent;
+
+    struct Collect(Mutex<Vec<u32>>);
+
+    impl AuditSink for Collect {
+        fn record(&self, record: &AuditRecord) {
+            self.0.lock().unwrap().push(record.limit);
+        }
+    }
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let audit = Arc::new(Collect(Mutex::new(Vec::new())));
+    let (seen, logged) = (Arc::clone(&events), Arc::clone(&lines));
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 5, Duration::from_secs(60))
+        .with_observer(Arc::new(move |event: &DecisionEvent| {
+            seen.lock().unwrap().push((event.key.to_string(), event.allowed, event.limit));
+        }))
+        .with_decision_logger(DecisionLogger::new(Arc::new(move |_: Level, line: &str| {
+            logged.lock().unwrap().push(line.to_string());
+        })))
+        .with_audit_sink(audit.clone());
+
+    let minute = Duration::from_secs(60);
+    let policies = [
+        KeyedPolicy::new("ip", "10.0.0.1", Rate::new(3, minute)),
+        KeyedPolicy::new("endpoint", "/search", Rate::new(1, minute)),
+    ];
+    assert_eq!(policies[0].rate(), Rate::new(3, minute));
+    assert!(limiter.check_all(&policies).allowed);
+    assert!(!limiter.check_all(&policies).allowed);
+    let routes = RouteTable::new().route("POST", "/login", "login", 2, minute);
+    for _ in 0..3 {
+        limiter.check_route(&routes, "POST", "/login", "10.0.0.2");
+    }
+
+    // Blocked composites report the dimension that blocked them.
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            ("10.0.0.1".to_string(), true, 3),
+            ("10.0.0.1".to_string(), false, 1),
+            ("10.0.0.2".to_string(), true, 2),
+            ("10.0.0.2".to_string(), true, 2),
+            ("10.0.0.2".to_string(), false, 2),
+        ]
+    );
+    assert_eq!(*audit.0.lock().unwrap(), vec![1, 2]);
+    assert_eq!(lines.lock().unwrap().len(), 2);
+    assert_eq!(limiter.stats().checked, 5);
+}
+
+#[test]
+fn test_observer_sees_every_decision() {
+    use std::sync::Mutex;
+    use api_rate_limiter::telemetry::DecisionEvent;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 2, Duration::from_secs(60))
+        .with_policy_version(3)
+        .with_observer(Arc::new(move |event: &DecisionEvent| {
+            seen.lock().unwrap().push((event.key.to_string(), event.allowed, event.cost, event.policy_version));
+        }));
+
+    assert!(limiter.allow_n("10.0.0.1", 2));
+    assert!(!limiter.allow("10.0.0.1"));
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            ("10.0.0.1".to_string(), true, 2, 3),
+            ("10.0.0.1".to_string(), false, 1, 3),
+        ]
+    );
+}
+
+#[test]
+fn test_decision_logger_throttles_repeated_blocks() {
+    use std::sync::Mutex;
+    use api_rate_limiter::logging::{DecisionLogger, Level};
+
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&lines);
+    let logger = DecisionLogger::new(Arc::new(move |level: Level, message: &str| {
+        sink.lock().unwrap().push((level, message.to_string()));
+    }))
+    .with_allowed(true)
+    .with_block_interval(Duration::from_millis(200));
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 1, Duration::from_secs(60))
+        .with_decision_logger(logger);
+
+    for _ in 0..5 {
+        limiter.allow("10.0.0.1");
+    }
+    thread::sleep(Duration::from_millis(250));
+    limiter.allow("10.0.0.1");
+
+    let lines = lines.lock().unwrap();
+    let levels: Vec<Level> = lines.iter().map(|(level, _)| *level).collect();
+    assert_eq!(levels, vec![Level::Trace, Level::Warn, Level::Warn]);
+    assert_eq!(lines[1].1, "rate limit blocked key=10.0.0.1 count=1 limit=1");
+    assert!(lines[2].1.ends_with("suppressed=3"));
+}
+
+#[test]
+fn test_decision_logger_throttles_errors_and_forgets_idle_keys() {
+    use std::sync::Mutex;
+    use api_rate_limiter::limiter::CacheBackend;
+    use api_rate_limiter::logging::{DecisionLogger, Level};
+
+    /// A backend whose every write fails, as if the server were down.
+    struct Down;
+
+    impl CacheBackend for Down {
+        fn get(&self, _key: &str) -> Option<u32> {
+            None
+        }
+
+        fn set(&self, _key: &str, _value: u32, _ttl: Duration) -> Result<(), String> {
+            Err("connection refused".to_string())
+        }
+
+        fn incr(&self, _key: &str, _amount: u32, _ttl: Duration) -> Result<u32, String> {
+            Err("connection refused".to_string())
+        }
+    }
+
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let logger = |lines: &Arc<Mutex<Vec<String>>>| {
+        let sink = Arc::clone(lines);
+        DecisionLogger::new(Arc::new(move |_: Level, message: &str| {
+            sink.lock().unwrap().push(message.to_string());
+        }))
+        .with_block_interval(Duration::from_millis(100))
+        .with_error_interval(Duration::from_millis(100))
+    };
+
+    // An outage logs one error per interval, not one per request.
+    let limiter = RateLimiter::new(Arc::new(Down), 1, Duration::from_secs(60)).with_decision_logger(logger(&lines));
+    for _ in 0..20 {
+        limiter.allow("10.0.0.1");
+    }
+    thread::sleep(Duration::from_millis(150));
+    limiter.allow("10.0.0.1");
+    {
+        let lines = lines.lock().unwrap();
+        let errors: Vec<&String> = lines.iter().filter(|line| line.contains("backend error")).collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[1].ends_with("suppressed=19"));
+    }
+
+    // Idle keys are forgotten without calling purge_idle, with a summary
+    // of the blocks suppressed for them.
+    lines.lock().unwrap().clear();
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 1, Duration::from_secs(60))
+        .with_decision_logger(logger(&lines));
+    for _ in 0..4 {
+        limiter.allow("10.0.0.1");
+    }
+    thread::sleep(Duration::from_millis(150));
+    limiter.allow("10.0.0.2");
+    limiter.allow("10.0.0.2");
+    assert_eq!(
+        *lines.lock().unwrap(),
+        [
+            "rate limit blocked key=10.0.0.1 count=1 limit=1",
+            "rate limit blocked key=10.0.0.1 suppressed=2",
+            "rate limit blocked key=10.0.0.2 count=1 limit=1",
+        ]
+    );
+}
+
+#[test]
+fn test_graphql_complexity_consumes_units() {
+    use api_rate_limiter::graphql::QueryComplexity;
+
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 50, Duration::from_secs(60));
+    let complexity = QueryComplexity::new();
+
+    // 1 + 10 * (1 + 1) = 21 units each.
+    let query = "{ posts(first: 10) { id title } }";
+    assert_eq!(limiter.check_request("10.0.0.1", query, &complexity).count, 21);
+    assert!(limiter.check_request("10.0.0.1", query, &complexity).allowed);
+    assert!(!limiter.check_request("10.0.0.1", query, &complexity).allowed);
+    assert!(limiter.check_request("10.0.0.1", "{ me { id } }", &complexity).allowed);
+}
+
+#[test]
+fn test_pacer_paces_and_stops() {
+    use std::time::Instant;
+    use api_rate_limiter::pacer::{PaceError, Pacer};
+
+    // 50 messages per second with bursts of 2: the third waits ~20ms.
+    let pacer = Pacer::per_second(50.0, 2).unwrap();
+    let start = Instant::now();
+    for _ in 0..3 {
+        pacer.pace("orders-0").unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(15));
+
+    // A stalled limit gives up after the maximum wait.
+    let pacer = Pacer::per_second(0.01, 1).unwrap().with_max_wait(Duration::from_millis(30));
+    pacer.pace("orders-1").unwrap();
+    assert_eq!(pacer.pace("orders-1"), Err(PaceError::TimedOut));
+
+    // Shutdown wakes a waiting consumer.
+    let pacer = Arc::new(Pacer::per_second(0.01, 1).unwrap());
+    pacer.pace("orders-2").unwrap();
+    let shutdown = pacer.shutdown_handle();
+    let waiter = {
+        let pacer = Arc::clone(&pacer);
+        thread::spawn(move || pacer.pace("orders-2"))
+    };
+    thread::sleep(Duration::from_millis(20));
+    shutdown.shutdown();
+    assert_eq!(waiter.join().unwrap(), Err(PaceError::Shutdown));
+
+    // Rates that cannot be paced are rejected rather than panicking.
+    for rate in [0.0, -1.0, f64::NAN, f64::MIN_POSITIVE] {
+        assert!(Pacer::per_second(rate, 1).is_err(), "{}", rate);
+    }
+}
+
+#[test]
+fn test_simulation_compares_algorithms() {
+    use api_rate_limiter::simulate::{simulate, Algorithm, SimPolicy, Trace, TraceEvent};
+
+    let minute = Duration::from_secs(60);
+    let trace = Trace::from_events(vec![
+        TraceEvent { at: Duration::from_secs(61), key: "a".to_string(), cost: 60 },
+        TraceEvent { at: Duration::from_secs(0), key: "a".to_string(), cost: 1 },
+        TraceEvent { at: Duration::from_secs(59), key: "a".to_string(), cost: 59 },
+    ]);
+    let policies = [Algorithm::FixedWindow, Algorithm::SlidingLog, Algorithm::TokenBucket]
+        .map(|algorithm| SimPolicy { algorithm, limit: 60, window: minute });
+    let reports = simulate(&trace, &policies);
+
+    // The fixed window lets 119 units through within two seconds.
+    assert_eq!((reports[0].allowed, reports[0].max_burst), (3, 119));
+    assert_eq!((reports[1].allowed, reports[1].blocked), (2, 1));
+    assert_eq!(reports[2].blocked, 1);
+    assert!(reports.iter().all(|report| report.peak_keys == 1));
+}
+
+#[test]
+fn test_trace_recording_replays_through_simulation() {
+    use std::io::Cursor;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use api_rate_limiter::audit::hash_key;
+    use api_rate_limiter::recording::{read_trace, TraceRecorder};
+    use api_rate_limiter::simulate::{simulate, Algorithm, SimPolicy, Trace};
+
+    let recorder = Arc::new(
+        TraceRecorder::new(Vec::new())
+            .with_clock(Arc::new(|| UNIX_EPOCH + Duration::from_secs(1_700_000_000))),
+    );
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 3, Duration::from_secs(60))
+        .with_observer(Arc::clone(&recorder) as _);
+    for _ in 0..5 {
+        limiter.allow("10.0.0.1");
+    }
+    limiter.allow_n("10.0.0.2", 2);
+    drop(limiter);
+
+    let bytes = Arc::try_unwrap(recorder).ok().unwrap().into_inner();
+    let records = read_trace(Cursor::new(bytes)).unwrap();
+    assert_eq!(records.len(), 6);
+    assert_eq!(records[0].timestamp, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    assert!(records[0].timestamp < SystemTime::now());
+
+    // Why was 10.0.0.1 blocked? Its own history shows it used up the limit.
+    let customer: Vec<_> = records
+        .iter()
+        .filter(|record| record.key_hash == hash_key("10.0.0.1"))
+        .cloned()
+        .collect();
+    let outcomes: Vec<bool> = customer.iter().map(|record| record.allowed).collect();
+    assert_eq!(outcomes, vec![true, true, true, false, false]);
+
+    // Replaying the trace reproduces the same decisions.
+    let policy = SimPolicy { algorithm: Algorithm::FixedWindow, limit: 3, window: Duration::from_secs(60) };
+    let report = &simulate(&Trace::from_recorded(&records), &[policy])[0];
+    assert_eq!((report.allowed, report.blocked), (4, 2));
+
+    // Recording to a file is buffered until flushed.
+    let path = std::env::temp_dir().join(format!("arl-trace-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let recorder = Arc::new(TraceRecorder::open(&path).unwrap());
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 3, Duration::from_secs(60))
+        .with_observer(Arc::clone(&recorder) as _);
+    limiter.allow("10.0.0.1");
+    limiter.allow("10.0.0.1");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+    recorder.flush().unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    assert_eq!(read_trace(std::io::BufReader::new(file)).unwrap().len(), 2);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_burst_detector_flags_spikes_over_baseline() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+    use std::time::UNIX_EPOCH;
+    use api_rate_limiter::anomaly::{AnomalyEvent, BurstDetector};
+
+    let millis = Arc::new(AtomicU64::new(0));
+    let clock = {
+        let millis = Arc::clone(&millis);
+        move || UNIX_EPOCH + Duration::from_millis(millis.load(Ordering::Relaxed))
+    };
+    let flagged = Arc::new(Mutex::new(Vec::new()));
+    let detector = {
+        let flagged = Arc::clone(&flagged);
+        Arc::new(
+            BurstDetector::new(Arc::new(move |event: &AnomalyEvent| {
+                flagged.lock().unwrap().push(event.key.to_string());
+            }))
+            .with_clock(Arc::new(clock)),
+        )
+    };
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 1_000_000, Duration::from_secs(3600))
+        .with_observer(Arc::clone(&detector) as _);
+
+    // Twenty minutes of steady traffic at 2 requests per second is not flagged.
+    for _ in 0..2400 {
+        millis.fetch_add(500, Ordering::Relaxed);
+        limiter.allow("steady");
+        limiter.allow("attacker");
+    }
+    assert!(flagged.lock().unwrap().is_empty());
+
+    // The attacker jumps to 50 requests per second and is flagged once.
+    for _ in 0..500 {
+        millis.fetch_add(20, Ordering::Relaxed);
+        limiter.allow("attacker");
+    }
+    assert_eq!(*flagged.lock().unwrap(), vec!["attacker".to_string()]);
+    assert!(detector.is_flagged("attacker"));
+    assert!(!detector.is_flagged("steady"));
+
+    // Keys idle for four baseline windows are forgotten without a manual purge.
+    assert_eq!(detector.key_count(), 2);
+    millis.fetch_add(2_400_000, Ordering::Relaxed);
+    for _ in 0..1024 {
+        millis.fetch_add(500, Ordering::Relaxed);
+        limiter.allow("newcomer");
+    }
+    assert_eq!(detector.key_count(), 1);
+    assert!(!detector.is_flagged("attacker"));
+}
+
+#[test]
+fn test_upstream_quota_caps_outbound_requests() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::UNIX_EPOCH;
+    use api_rate_limiter::hashing::KeyHasher;
+    use api_rate_limiter::upstream::UpstreamQuotaTracker;
+
+    let seconds = Arc::new(AtomicU64::new(1_700_000_000));
+    let clock = {
+        let seconds = Arc::clone(&seconds);
+        move || UNIX_EPOCH + Duration::from_secs(seconds.load(Ordering::Relaxed))
+    };
+    let outbound = RateLimiter::new(Arc::new(InMemoryCache::new()), 100, Duration::from_secs(3600))
+        .with_key_hasher(KeyHasher::new());
+    let tracker = UpstreamQuotaTracker::new(outbound).with_clock(Arc::new(clock));
+
+    // Unknown upstreams are limited only by our own policy.
+    assert!(tracker.allow("api.example.com"));
+
+    // The upstream says 2 requests are left for the next 30 seconds.
+    tracker.update("api.example.com", [("RateLimit-Remaining", "2"), ("RateLimit-Reset", "30")]);
+    assert_eq!(tracker.check("api.example.com").remaining, 1);
+    assert!(tracker.allow("api.example.com"));
+    let blocked = tracker.check("api.example.com");
+    assert!(!blocked.allowed);
+    assert_eq!(blocked.retry_after, Some(Duration::from_secs(30)));
+
+    // Once the upstream quota resets, our own policy applies again.
+    seconds.fetch_add(30, Ordering::Relaxed);
+    assert_eq!(tracker.remaining("api.example.com"), None);
+    assert!(tracker.allow("api.example.com"));
+
+    // Retry-After blocks the host until then.
+    tracker.update("api.example.com", [("Retry-After", "5")]);
+    assert!(!tracker.allow("api.example.com"));
+    assert!(tracker.allow("other.example.com"));
+}
+
+#[test]
+fn test_calendar_windows_reset_at_local_midnight() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::UNIX_EPOCH;
+    use api_rate_limiter::calendar::CalendarWindows;
+
+    // 2023-11-14 21:59:50 UTC is 23:59:50 at UTC+2.
+    let seconds = Arc::new(AtomicU64::new(1_699_999_190));
+    let clock = {
+        let seconds = Arc::clone(&seconds);
+        move || UNIX_EPOCH + Duration::from_secs(seconds.load(Ordering::Relaxed))
+    };
+    let windows = CalendarWindows::new().with_utc_offset_minutes(120).with_clock(Arc::new(clock));
+    assert_eq!(windows.current(Duration::from_secs(86_400)).1, Duration::from_secs(10));
+
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 2, Duration::from_secs(86_400))
+        .with_calendar_windows(windows);
+    assert!(limiter.allow("customer"));
+    assert!(limiter.allow("customer"));
+    let blocked = limiter.check("customer");
+    assert!(!blocked.allowed);
+    assert!(blocked.retry_after.unwrap() <= Duration::from_secs(10));
+
+    // A new calendar day starts a new window.
+    seconds.fetch_add(10, Ordering::Relaxed);
+    assert!(limiter.allow("customer"));
+    assert_eq!(limiter.usage("customer").count, 1);
+}
+
+#[test]
+fn test_monthly_quota_carries_over_unused_units() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::UNIX_EPOCH;
+    use api_rate_limiter::calendar::{CalendarWindows, Period};
+    use api_rate_limiter::quota::QuotaTracker;
+
+    // 2024-01-31 23:00 UTC.
+    let seconds = Arc::new(AtomicU64::new(1_706_742_000));
+    let clock = {
+        let seconds = Arc::clone(&seconds);
+        move || UNIX_EPOCH + Duration::from_secs(seconds.load(Ordering::Relaxed))
+    };
+    let quotas = QuotaTracker::new(Arc::new(InMemoryCache::new()), 100, Period::Month)
+        .with_carry_over_percent(50)
+        .with_calendar(CalendarWindows::new().with_clock(Arc::new(clock)));
+
+    // January: 60 of 100 used, nothing to carry from December.
+    let january = quotas.consume("customer", 60);
+    assert!(january.allowed);
+    assert_eq!((january.allowance, january.carried_over), (100, 0));
+    assert_eq!(january.resets_in, Duration::from_secs(3600));
+    assert!(!quotas.consume("customer", 41).allowed);
+    assert_eq!(quotas.usage("customer").used, 60);
+
+    // February: half of the 40 unused units carry over.
+    seconds.fetch_add(3600, Ordering::Relaxed);
+    let february = quotas.usage("customer");
+    assert_eq!((february.used, february.allowance, february.carried_over), (0, 120, 20));
+    assert!(quotas.consume("customer", 120).allowed);
+    assert!(!quotas.allow("customer"));
+
+    // Hashed subjects share nothing with their raw form.
+    let cache = Arc::new(InMemoryCache::new());
+    let hashed = QuotaTracker::new(cache.clone(), 10, Period::Day)
+        .with_key_hasher(api_rate_limiter::hashing::KeyHasher::with_secret(b"s3cret"));
+    assert!(hashed.consume("customer", 3).allowed);
+    assert_eq!(hashed.usage("customer").used, 3);
+    assert_eq!(QuotaTracker::new(cache, 10, Period::Day).usage("customer").used, 0);
+}
+
+#[test]
+fn test_limiters_from_parsed_rates() {
+    use api_rate_limiter::gcra::GcraLimiter;
+    use api_rate_limiter::plan::Plan;
+    use api_rate_limiter::quota::QuotaTracker;
+    use api_rate_limiter::rate::Rate;
+    use api_rate_limiter::route::RouteTable;
+
+    let rate: Rate = "3/5m".parse().unwrap();
+    assert_eq!(rate, Rate::new(3, Duration::from_secs(300)));
+    let limiter = RateLimiter::from_rate(Arc::new(InMemoryCache::new()), rate);
+    assert_eq!((limiter.limit, limiter.ttl), (3, Duration::from_secs(300)));
+    for _ in 0..3 {
+        assert!(limiter.allow("10.0.0.1"));
+    }
+    assert!(!limiter.allow("10.0.0.1"));
+
+    let gcra = GcraLimiter::from_rate(Arc::new(InMemoryCache::new()), Rate::per_minute(2));
+    assert!(gcra.allow("10.0.0.1"));
+    assert!(gcra.allow("10.0.0.1"));
+    assert!(!gcra.allow("10.0.0.1"));
+
+    let routes = RouteTable::new().route_rate("POST", "/login", "login", "5/m".parse().unwrap());
+    let policy = routes.find("POST", "/login").unwrap().policy;
+    assert_eq!((policy.limit, policy.window), (5, Duration::from_secs(60)));
+    assert_eq!(policy.rate(), Rate::per_minute(5));
+
+    let plan = Plan::new("team", 10)
+        .with_policy_limit("search", 4)
+        .with_policy_rate("login", "2/10s".parse().unwrap());
+    assert_eq!(plan.policy_rate("search", Duration::from_secs(60)), Some(Rate::per_minute(4)));
+    assert_eq!(plan.policy_rate("login", Duration::from_secs(60)), Some(Rate::new(2, Duration::from_secs(10))));
+    assert_eq!(plan.policy_rate("other", Duration::from_secs(60)), None);
+
+    let cache = Arc::new(InMemoryCache::new());
+    let quotas = QuotaTracker::from_rate(cache.clone(), "2/d".parse().unwrap()).unwrap();
+    assert!(quotas.consume("customer", 2).allowed);
+    assert!(!quotas.allow("customer"));
+    assert!(QuotaTracker::from_rate(cache, Rate::per_minute(2)).is_err());
+}
+
+#[test]
+fn test_in_memory_sweeper_reports_expired_keys() {
+    use std::sync::Mutex;
+    use api_rate_limiter::cache::in_memory::InMemoryCache as Cache;
+
+    let expired = Arc::new(Mutex::new(Vec::new()));
+    let handler = {
+        let expired = Arc::clone(&expired);
+        move |key: &str| expired.lock().unwrap().push(key.to_string())
+    };
+    let cache = Arc::new(Cache::new().with_expiry_handler(Arc::new(handler)));
+    let limiter = RateLimiter::new(Arc::clone(&cache), 5, Duration::from_millis(50));
+    assert!(limiter.allow("quiet"));
+    assert!(limiter.allow("busy"));
+
+    thread::sleep(Duration::from_millis(60));
+    // The busy key's window rolls over in place; only the quiet key is dropped.
+    assert!(limiter.allow("busy"));
+    assert_eq!(cache.sweep(), 1);
+    assert_eq!(*expired.lock().unwrap(), vec!["rate_limit:quiet".to_string()]);
+
+    // Once the busy key goes quiet too, the sweeper thread drops it.
+    let sweeper = Cache::spawn_sweeper(&cache, Duration::from_millis(10));
+    for _ in 0..100 {
+        if expired.lock().unwrap().len() == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(sweeper);
+    assert_eq!(expired.lock().unwrap()[1], "rate_limit:busy");
+}
+
+#[test]
+fn test_shutdown_stops_sweepers_and_flushes() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use api_rate_limiter::audit::{AuditRecord, AuditSink};
+    use api_rate_limiter::limiter::CacheBackend;
+
+    #[derive(Default)]
+    struct Flushes(AtomicU32);
+
+    impl AuditSink for Flushes {
+        fn record(&self, _record: &AuditRecord) {}
+
+        fn flush(&self) -> Result<(), String> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    let cache = Arc::new(InMemoryCache::new());
+    let audit = Arc::new(Flushes::default());
+    let limiter = RateLimiter::new(Arc::clone(&cache), 5, Duration::from_millis(10))
+        .with_audit_sink(audit.clone())
+        .with_sweeper(InMemoryCache::spawn_sweeper(&cache, Duration::from_millis(5)));
+    limiter.shutdown().unwrap();
+    assert_eq!(audit.0.load(Ordering::Relaxed), 1);
+
+    // Checks still work, but expired keys are no longer swept.
+    assert!(limiter.allow("10.0.0.1"));
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(cache.key_count(), Some(1));
+}
+
+#[test]
+fn test_soft_limit_warns_before_blocking() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use api_rate_limiter::telemetry::DecisionEvent;
+
+    let warnings = Arc::new(AtomicU32::new(0));
+    let observer = {
+        let warnings = Arc::clone(&warnings);
+        move |event: &DecisionEvent<'_>| {
+            if event.warning {
+                warnings.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    };
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 10, Duration::from_secs(60))
+        .with_soft_limit(80)
+        .with_observer(Arc::new(observer));
+
+    for _ in 0..7 {
+        let decision = limiter.check("10.0.0.1");
+        assert!(decision.allowed && !decision.warning);
+    }
+    assert!(limiter.would_allow("10.0.0.1").warning);
+    for _ in 0..3 {
+        let decision = limiter.check("10.0.0.1");
+        assert!(decision.allowed && decision.warning);
+        assert!(decision.headers().contains(&("X-RateLimit-Warning", "approaching limit".to_string())));
+    }
+    assert_eq!(warnings.load(Ordering::Relaxed), 3);
+
+    // Blocked requests carry Retry-After rather than a warning.
+    let blocked = limiter.check("10.0.0.1");
+    assert!(!blocked.allowed && !blocked.warning);
+    let headers = blocked.headers();
+    assert!(headers.contains(&("RateLimit-Remaining", "0".to_string())));
+    assert!(headers.contains(&("Retry-After", "60".to_string())));
+}
+
+#[test]
+fn test_fair_share_within_quota_groups() {
+    use api_rate_limiter::group::FairShare;
+
+    let org = |_: &str| Some("org".to_string());
+    let capped = RateLimiter::new(Arc::new(InMemoryCache::new()), 10, Duration::from_secs(60))
+        .with_group_resolver(org)
+        .with_fair_share(FairShare::MaxShare(50));
+    let noisy = (0..8).filter(|_| capped.allow("noisy")).count();
+    assert_eq!(noisy, 5);
+    assert_eq!((0..8).filter(|_| capped.allow("quiet")).count(), 5);
+
+    let even = RateLimiter::new(Arc::new(InMemoryCache::new()), 10, Duration::from_secs(60))
+        .with_group_resolver(org)
+        .with_fair_share(FairShare::Even);
+    for _ in 0..4 {
+        assert!(even.allow("noisy"));
+    }
+    // A second member halves the share: the noisy key gets one more request.
+    assert!(even.allow("quiet"));
+    assert!(even.allow("noisy"));
+    let blocked = even.check("noisy");
+    assert!(!blocked.allowed);
+    assert_eq!(blocked.count, 5);
+    assert_eq!((0..8).filter(|_| even.allow("quiet")).count(), 4);
+}
+
+#[test]
+fn test_global_limit_checked_before_key_limits() {
+    use api_rate_limiter::composite::KeyedPolicy;
+    use api_rate_limiter::limiter::Strategy;
+    use api_rate_limiter::rate::Rate;
+    use api_rate_limiter::route::RouteTable;
+
+    for strategy in [Strategy::FixedWindow, Strategy::LocalLockFree] {
+        let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 2, Duration::from_secs(60))
+            .with_strategy(strategy)
+            .with_global_limit(Rate::per_minute(5));
+        assert!(limiter.allow("a"));
+        assert!(limiter.allow("a"));
+        // Blocked by its own limit: the global unit is given back.
+        let own = limiter.check("a");
+        assert!(!own.allowed && !own.global_limited, "{:?}", strategy);
+        assert!(limiter.allow("b"));
+        assert!(limiter.allow("b"));
+        assert!(limiter.allow("c"));
+
+        let global = limiter.check("d");
+        assert!(!global.allowed && global.global_limited, "{:?}", strategy);
+        assert_eq!((global.count, global.remaining), (0, 2));
+        assert!(limiter.would_allow("d").global_limited);
+
+        limiter.refund("c", 1).unwrap();
+        assert!(limiter.allow("d"), "{:?}", strategy);
+
+        // Composite and per-route checks count against the global limit too.
+        let minute = Duration::from_secs(60);
+        let policy = [KeyedPolicy { name: "ip", key: "e", limit: 10, window: minute }];
+        let composite = limiter.check_all(&policy);
+        assert_eq!(composite.failed.as_deref(), Some("global"));
+        assert!(composite.decisions[0].global_limited);
+        let routes = RouteTable::new().route("GET", "/search", "search", 10, minute);
+        assert!(limiter.check_route(&routes, "GET", "/search", "e").global_limited);
+
+        limiter.refund("d", 1).unwrap();
+        assert!(limiter.check_all(&policy).allowed, "{:?}", strategy);
+        assert!(limiter.check("f").global_limited);
+
+        // Refunding again, or refunding the request "a" was blocked on, has
+        // nothing left to give back and keeps the global units others hold.
+        limiter.refund("d", 1).unwrap();
+        limiter.refund("f", 1).unwrap();
+        assert!(limiter.check("g").global_limited, "{:?}", strategy);
+    }
+}
+
+#[test]
+fn test_in_memory_hot_keys_keep_counting_after_promotion() {
+    use std::sync::Mutex;
+    use api_rate_limiter::limiter::CacheBackend;
+
+    let expired = Arc::new(Mutex::new(Vec::new()));
+    let handler = {
+        let expired = Arc::clone(&expired);
+        move |key: &str| expired.lock().unwrap().push(key.to_string())
+    };
+    let cache = InMemoryCache::new()
+        .with_hot_key_threshold(3)
+        .with_expiry_handler(Arc::new(handler));
+    let ttl = Duration::from_millis(100);
+    for expected in 1..=5 {
+        assert_eq!(cache.incr_within("k", 1, 5, ttl).unwrap(), (true, expected));
+    }
+    assert_eq!(cache.incr_within("k", 1, 5, ttl).unwrap(), (false, 5));
+    assert_eq!(cache.get("k"), Some(5));
+    assert_eq!(cache.decr("k", 2).unwrap(), 3);
+    assert_eq!(cache.incr("k", 1, ttl).unwrap(), 4);
+    assert!(cache.ttl("k").is_some_and(|left| left <= ttl));
+    assert_eq!(cache.key_count(), Some(1));
+
+    // A hot key's window rolls over like any other, and expires like any other.
+    thread::sleep(Duration::from_millis(120));
+    assert_eq!(cache.get("k"), None);
+    assert_eq!(*expired.lock().unwrap(), vec!["k".to_string()]);
+    assert_eq!(cache.incr_within("k", 2, 5, ttl).unwrap(), (true, 2));
+    thread::sleep(Duration::from_millis(120));
+    assert_eq!(cache.sweep(), 1);
+    assert_eq!(*expired.lock().unwrap(), vec!["k".to_string(), "k".to_string()]);
+
+    // Non-atomic updates move the key back to the locked path.
+    for _ in 0..4 {
+        cache.incr("k", 1, ttl).unwrap();
+    }
+    assert!(cache.compare_and_swap("k", Some(4), 9, ttl).unwrap());
+    assert_eq!(cache.get("k"), Some(9));
+}
+
+#[test]
+fn test_in_memory_counts_never_wrap() {
+    use api_rate_limiter::limiter::CacheBackend;
+
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), u32::MAX, Duration::from_secs(60));
+    assert!(limiter.allow_n("10.0.0.1", u32::MAX));
+    assert!(!limiter.allow_n("10.0.0.1", u32::MAX));
+
+    // The same holds for cold and for promoted hot keys.
+    let ttl = Duration::from_secs(60);
+    for threshold in [u32::MAX, 1] {
+        let cache = InMemoryCache::new().with_hot_key_threshold(threshold);
+        cache.set("k", u32::MAX - 1, ttl).unwrap();
+        cache.incr("k", 1, ttl).unwrap();
+        assert_eq!(cache.incr_within("k", 5, u32::MAX, ttl).unwrap(), (false, u32::MAX));
+        assert_eq!(cache.incr("k", 5, ttl).unwrap(), u32::MAX);
+    }
+}
+
+#[test]
+fn test_invalid_key_policies() {
+    use api_rate_limiter::composite::KeyedPolicy;
+    use api_rate_limiter::route::RouteTable;
+    use api_rate_limiter::validation::{ip_address, InvalidKeyPolicy};
+
+    let limiter = |policy: InvalidKeyPolicy| {
+        RateLimiter::new(Arc::new(InMemoryCache::new()), 2, Duration::from_secs(60))
+            .with_key_validator(ip_address)
+            .with_invalid_key_policy(policy)
+    };
+
+    let allowing = limiter(InvalidKeyPolicy::Allow);
+    for _ in 0..5 {
+        let decision = allowing.check("not-an-ip");
+        assert!(decision.allowed && decision.invalid_key);
+    }
+    let valid = allowing.check("10.0.0.1");
+    assert!(valid.allowed && !valid.invalid_key);
+    // Nothing was counted for the invalid key, so nothing is refunded.
+    let decision = allowing.check("not-an-ip");
+    assert!(!allowing.on_response("not-an-ip", &decision, 1, 500, &|status: u16| status < 500));
+    assert_eq!(allowing.usage("not-an-ip").count, 0);
+
+    let blocking = limiter(InvalidKeyPolicy::Block);
+    let decision = blocking.check("");
+    assert!(!decision.allowed && decision.invalid_key);
+    assert_eq!(blocking.stats().blocked, 1);
+    assert_eq!(blocking.usage("").remaining, 0);
+
+    // Invalid keys share the fallback bucket, apart from valid ones.
+    let fallback = limiter(InvalidKeyPolicy::UseFallbackKey("unknown".to_string()));
+    assert!(fallback.check("garbage").invalid_key);
+    assert!(fallback.allow(""));
+    assert!(!fallback.allow("junk"));
+    assert_eq!(fallback.usage("unknown").count, 2);
+    // Usage and retry times read the fallback bucket, like checks.
+    assert_eq!(fallback.usage("junk").count, 2);
+    assert!(fallback.retry_after("junk").is_some());
+    assert!(fallback.allow("10.0.0.1"));
+
+    // Without a policy, every key is accepted as before.
+    let default = RateLimiter::new(Arc::new(InMemoryCache::new()), 2, Duration::from_secs(60));
+    assert!(!default.check("").invalid_key);
+
+    // Composite and per-route checks validate the client key the same way.
+    let minute = Duration::from_secs(60);
+    let policies = |client| {
+        [
+            KeyedPolicy { name: "ip", key: client, limit: 2, window: minute },
+            KeyedPolicy { name: "endpoint", key: "/search", limit: 10, window: minute },
+        ]
+    };
+    let routes = RouteTable::new().route("GET", "/search", "search", 2, minute);
+    let composite = blocking.check_all(&policies("junk"));
+    assert_eq!(composite.failed.as_deref(), Some("ip"));
+    assert!(composite.decisions.iter().all(|decision| decision.invalid_key));
+    let route = blocking.check_route(&routes, "GET", "/search", "junk");
+    assert!(!route.allowed && route.invalid_key);
+    assert!(blocking.check_all(&policies("10.0.0.1")).allowed);
+
+    assert!(fallback.check_all(&policies("junk")).decisions[0].invalid_key);
+    assert!(fallback.check_route(&routes, "GET", "/search", "garbage").allowed);
+    assert!(fallback.check_route(&routes, "GET", "/search", "").allowed);
+    assert!(!fallback.check_route(&routes, "GET", "/search", "junk").allowed);
+}
+
+#[test]
+fn test_plan_limiter_applies_tiers() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use api_rate_limiter::plan::{Plan, PlanLimiter};
+    use api_rate_limiter::route::RouteTable;
+
+    let free = Arc::new(Plan::new("free", 2).with_policy_limit("search", 1));
+    let pro = Arc::new(Plan::new("pro", 5).with_burst(1));
+    let lookups = Arc::new(AtomicU32::new(0));
+    let resolver = {
+        let lookups = Arc::clone(&lookups);
+        move |api_key: &str| {
+            lookups.fetch_add(1, Ordering::Relaxed);
+            match api_key {
+                "key-free" => Some(Arc::clone(&free)),
+                "key-pro" => Some(Arc::clone(&pro)),
+                _ => None,
+            }
+        }
+    };
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 3, Duration::from_secs(60))
+        .with_key_hasher(api_rate_limiter::hashing::KeyHasher::new());
+    let plans = PlanLimiter::new(limiter, resolver);
+
+    assert_eq!((0..10).filter(|_| plans.allow("key-free")).count(), 2);
+    assert_eq!((0..10).filter(|_| plans.allow("key-pro")).count(), 6);
+    // Keys without a plan get the limiter's own limit.
+    assert_eq!((0..10).filter(|_| plans.allow("key-other")).count(), 3);
+    assert_eq!(plans.plan("key-pro").unwrap().name, "pro");
+    assert_eq!(lookups.load(Ordering::Relaxed), 3);
+
+    let minute = Duration::from_secs(60);
+    let routes = RouteTable::new().route("GET", "/search", "search", 10, minute);
+    let search = |key| (0..20).filter(|_| plans.check_route(&routes, "GET", "/search", key).allowed).count();
+    assert_eq!(search("key-free"), 1);
+    assert_eq!(search("key-pro"), 11);
+
+    plans.invalidate("key-free");
+    plans.plan("key-free");
+    assert_eq!(lookups.load(Ordering::Relaxed), 4);
+}
+
+#[test]
+fn test_plan_limiter_purges_expired_plans() {
+    use api_rate_limiter::plan::{Plan, PlanLimiter};
+
+    let limiter = RateLimiter::new(Arc::new(InMemoryCache::new()), 3, Duration::from_secs(60));
+    let plans = PlanLimiter::new(limiter, |_: &str| None::<Arc<Plan>>).with_cache_ttl(Duration::ZERO);
+
+    // Random keys are cached as "no plan", then purged once expired.
+    for i in 0..3000 {
+        plans.plan(&format!("random-{}", i));
+    }
+    assert!(plans.cached_plans() < 1024);
+}